@@ -0,0 +1,861 @@
+use std::thread;
+use std::io;
+use std::cmp;
+use std::io::{Read, Write};
+use std::fs::{OpenOptions, File};
+use std::error::Error;
+use std::sync::mpsc::{channel, TryRecvError, Receiver};
+use std::sync::{RwLock, Arc};
+use std::process::Command;
+use chrono::{DateTime,Local,NaiveDate,NaiveTime};
+use carboxyl::{Signal, Stream};
+use serde_json;
+
+// logging
+use std::env;
+use log::{LogRecord, LogLevelFilter};
+use env_logger::LogBuilder;
+
+use ::uom::temp::*;
+use ::uom::length::Length;
+use ::uom::area::Area;
+use ::uom::volume::Volume;
+use ::uom::flow::FlowRate;
+use ::devices::{Depth, Hardware, Relay, Input, PiGpio, TempProbe, parse_rom, PhConfig, PhCalibrationCommand, TempCalibration};
+use ::clock::Clock;
+use ::filter;
+use ::filter::FilterConfig;
+use ::controller::{AquariumController, Outputs, OutletConfig, Role, Calibration, Dose, TemperatureRange, TemperaturePoint, DepthCalibration, DepthFit, PidSettings, CoolerLimits, StageOffsets, EvaporationDay};
+use ::controller::schedule::{Schedule, ScheduleLeg};
+use ::controller::Status;
+
+use aquamon_server;
+use aquamon_server::server::Status as StatusDto;
+use aquamon_server::server::{ProbeStatus, SensorStatus, InputStatus, PhCalibrationStatus, PhCalibrationPoint, PhCalibrationAction, DepthCalibrationStatus, DepthCalibrationPoint, DepthCalibrationAction, HeaterPidStatus, TemperatureStageStatus, AtoReservoirStatus, AtoEvaporationStatus};
+use aquamon_server::server::LightingSchedule as ScheduleDto;
+use aquamon_server::server::{Settings, Commands, LightSettings, TemperatureSettings, TemperatureRangeSettings, DepthSettings, DepthSettingsMaintain, DepthSettingsDepthValues, LiveModeSettings, DoserSettings, HardwareSettings, FilterSettings, FilterStageSettings, PhSettings, UnitSettings, TemperatureUnit, HeaterPidSettings, CoolerLimitSettings, StageOffsetSettings, ReservoirSettings, default_fault_check_minutes};
+
+use ::alerting::alert;
+
+const TICK_MS: u64 = 10;
+
+/// Loads the settings saved by the web UI, or the defaults if there aren't any yet
+pub fn load_settings() -> Settings {
+    read_settings().unwrap_or(Settings {
+        temperature_settings: TemperatureSettings { 
+            heater: TemperatureRangeSettings { min: 79.5, minTime: "07:00".to_string(), max: 79.5, maxTime: "16:00".to_string(), points: vec![] },
+            cooler: TemperatureRangeSettings { min: 80.5, minTime: "07:00".to_string(), max: 80.5, maxTime: "16:00".to_string(), points: vec![] },
+            unit: None,
+            heaterPid: None,
+            coolerLimits: CoolerLimitSettings::default(),
+            stageOffsets: StageOffsetSettings::default(),
+            faultCheckMinutes: default_fault_check_minutes(),
+        },
+        depth_settings: DepthSettings { 
+            maintainRange: DepthSettingsMaintain { low: 0, high: 1},
+            depthValues: DepthSettingsDepthValues { low: 0, high: 4096, highLevel: 10.0, surfaceArea: 17*10, tankVolume: 10.0, pumpRate: 50.0, metric: false },
+            reservoir: ReservoirSettings::default(),
+            maxDailyVolume: None,
+        },
+        lighting_schedule: ScheduleDto {
+            schedule: vec![
+                LightSettings { intensity: 0, intensities: [0_u8; 6], startTime: "09:00".to_string() },
+                LightSettings { intensity: 0, intensities: [0_u8; 6], startTime: "17:00".to_string() },
+            ]
+        },
+        doser_settings: DoserSettings {
+            pumpRateMlMin: 1.1,
+            schedule: vec![],
+            doseAmountMl: 1.1,
+            doseRangeStart: 7,
+            doseRangeEnd: 18,
+        },
+        hardware: HardwareSettings::default(),
+        filters: FilterSettings::default(),
+        ph: PhSettings::default(),
+        units: UnitSettings::default(),
+    })
+}
+
+/// Opens the outlets named in the hardware settings, failing if the mapping isn't valid
+pub fn outputs_from_settings(hardware: &HardwareSettings, pi_gpio: &mut PiGpio) -> io::Result<Outputs> {
+    let mut outlets = vec![];
+    for outlet in hardware.outlets.iter() {
+        outlets.push(OutletConfig {
+            name: outlet.name.clone(),
+            gpio: outlet.gpio,
+            active_low: outlet.activeLow,
+            role: try!(outlet.role.parse::<Role>()),
+        });
+    }
+    Outputs::from_outlets(&outlets, |outlet| {
+        pi_gpio.take_pin(outlet.gpio, outlet.active_low).map(|pin| Box::new(pin) as Box<Relay>)
+    })
+}
+
+/// Opens the digital inputs in the hardware settings as (name, input, debounce ms). Call
+/// after `outputs_from_settings` with the same `pi_gpio` so lines can't be used twice.
+pub fn inputs_from_settings(hardware: &HardwareSettings, pi_gpio: &mut PiGpio) -> io::Result<Vec<(String, Box<Input>, u64)>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    let mut inputs: Vec<(String, Box<Input>, u64)> = vec![];
+    for settings in hardware.inputs.iter() {
+        if settings.name.is_empty() {
+            return Err(invalid(format!("Input on GPIO {} has no name", settings.gpio)));
+        }
+        if inputs.iter().any(|i| i.0 == settings.name) {
+            return Err(invalid(format!("Input name {} is used more than once", settings.name)));
+        }
+        let input = try!(pi_gpio.take_input(settings.gpio, settings.activeLow));
+        inputs.push((settings.name.clone(), Box::new(input), settings.debounceMs));
+    }
+    for float in [&hardware.atoHighFloat, &hardware.atoReservoirFloat].iter() {
+        if !float.is_empty() && !inputs.iter().any(|i| i.0 == **float) {
+            return Err(invalid(format!("ATO float {} isn't one of the inputs", float)));
+        }
+    }
+    Ok(inputs)
+}
+
+/// The water temperature probes named in the hardware settings
+pub fn probes_from_settings(hardware: &HardwareSettings) -> io::Result<Vec<TempProbe>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    let mut probes: Vec<TempProbe> = vec![];
+    for probe in hardware.tempProbes.iter() {
+        // names end up in recordings, which are comma separated
+        if probe.name.is_empty() || probe.name.contains(',') {
+            return Err(invalid(format!("Invalid temperature probe name: '{}'", probe.name)));
+        }
+        let rom = try!(parse_rom(&probe.rom));
+        if probes.iter().any(|p| p.name == probe.name || p.rom == rom) {
+            return Err(invalid(format!("Temperature probe {} ({}) is listed more than once", probe.name, probe.rom)));
+        }
+        probes.push(TempProbe { name: probe.name.clone(), rom: rom });
+    }
+    if !hardware.controlProbe.is_empty() && !probes.iter().any(|p| p.name == hardware.controlProbe) {
+        return Err(invalid(format!("Control probe {} isn't one of the temperature probes", hardware.controlProbe)));
+    }
+    Ok(probes)
+}
+
+/// Each temperature sensor's correction, by sensor name
+pub fn temp_calibrations_from_settings(hardware: &HardwareSettings) -> io::Result<Vec<(String, TempCalibration)>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    let mut calibrations: Vec<(String, TempCalibration)> = vec![];
+    for settings in hardware.tempCalibrations.iter() {
+        let sensor = settings.sensor.as_str();
+        let known = sensor == "temp" || sensor == "airTemp" ||
+            hardware.tempProbes.iter().any(|p| format!("probe:{}", p.name) == sensor);
+        if !known {
+            return Err(invalid(format!("Can't calibrate unknown temperature sensor {}", sensor)));
+        }
+        if calibrations.iter().any(|c| c.0 == sensor) {
+            return Err(invalid(format!("Temperature sensor {} is calibrated more than once", sensor)));
+        }
+        let calibration = match settings.points.len() {
+            0 => TempCalibration::offset(settings.offsetF),
+            1 => TempCalibration::offset(settings.points[0].actualF - settings.points[0].readF),
+            2 => try!(TempCalibration::two_point(
+                    (settings.points[0].readF, settings.points[0].actualF),
+                    (settings.points[1].readF, settings.points[1].actualF))),
+            _ => return Err(invalid(format!("Temperature sensor {} has more than two reference points", sensor))),
+        };
+        calibrations.push((sensor.to_string(), calibration));
+    }
+    Ok(calibrations)
+}
+
+pub fn ph_config_from_settings(ph: &PhSettings) -> PhConfig {
+    PhConfig { ph7_cal: ph.ph7Cal, ph10_cal: ph.ph10Cal, ph4_cal: ph.ph4Cal, gain: ph.ampGain }
+}
+
+fn ph_calibration_command(action: &PhCalibrationAction) -> io::Result<PhCalibrationCommand> {
+    match action.action.as_str() {
+        "start" => Ok(PhCalibrationCommand::Start),
+        "capture" => Ok(PhCalibrationCommand::Capture(action.buffer)),
+        "finish" => Ok(PhCalibrationCommand::Finish),
+        "cancel" => Ok(PhCalibrationCommand::Cancel),
+        other => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown pH calibration action: {}", other))),
+    }
+}
+
+/// Builds a sensor's filter from its stages in the settings
+pub fn filter_from_settings(stages: &[FilterStageSettings]) -> io::Result<FilterConfig> {
+    let mut filters = vec![];
+    for stage in stages.iter() {
+        filters.push(match stage.kind.as_str() {
+            "none" => FilterConfig::None,
+            "movingAverage" => FilterConfig::MovingAverage(stage.window),
+            "exponential" => FilterConfig::Exponential(stage.alpha),
+            "median" => FilterConfig::Median(stage.window),
+            "rejectSpikes" => FilterConfig::RejectSpikes { max_step: stage.maxStep, limit: stage.limit },
+            kind => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown filter: {}", kind))),
+        });
+    }
+    let config = FilterConfig::Chain(filters);
+    try!(config.validate());
+    Ok(config)
+}
+
+/// Runs the controller and web server against the given hardware until the web server
+/// goes away. `server_addr` is the address the web UI is served on and `speed` how many
+/// times faster than real time the loop ticks, at least 1.
+pub fn run<H: Hardware>(mut devices: H, outputs: Outputs, clock: Box<Clock>, mut settings_dto: Settings, server_addr: &str, speed: u64) {
+    assert!(speed >= 1, "speed must be at least 1");
+    let mut i:u64 = 0;
+    // the last pH calibration command that couldn't be carried out
+    let mut ph_calibration_error: Option<String> = None;
+
+    let (status_lock, rx_live, rx_commands) = start_server(server_addr, &settings_dto);
+    // each sensor is filtered once here so the controllers and the UI see the same values
+    let sensor_filter = |stages: &[FilterStageSettings]| filter_from_settings(stages)
+        .unwrap_or_else(|err| panic!("Invalid filter settings: {}", err));
+    let temp_filter = sensor_filter(&settings_dto.filters.temp);
+    let control_temp = filter::apply(&control_temp_stream(&devices, &settings_dto.hardware.controlProbe), &temp_filter);
+    let depth = filter::apply(&devices.depth_stream(), &sensor_filter(&settings_dto.filters.depth));
+
+    let temp_signal = rounded_temp(&control_temp);
+    let probe_signals: Vec<(String, Signal<f32>)> = devices.probe_streams().into_iter()
+        .map(|(name, stream)| (name, rounded_temp(&filter::apply(&stream, &temp_filter))))
+        .collect();
+    let input_signals: Vec<(String, Signal<bool>)> = devices.input_streams().into_iter()
+        .map(|(name, stream)| (name, stream.map(|level| level.unwrap_or(false)).hold(false)))
+        .collect();
+    let air_temp_signal = rounded_temp(&filter::apply(&devices.air_temp_stream(), &sensor_filter(&settings_dto.filters.airTemp)));
+    let humidity_signal = filter::apply(&devices.humidity_stream(), &sensor_filter(&settings_dto.filters.humidity))
+        .map(|humidity| (humidity * 10.0).round() / 10.0)
+        .hold(50.0);
+    let ph_signal = filter::apply(&devices.ph_stream(), &sensor_filter(&settings_dto.filters.ph))
+        .map(|ph| (ph * 100.0).round() / 100.0)
+        .hold(8.0);
+    let depth_signal = depth.hold(61 * 4);
+    let depth_reading = depth.map(Some).hold(None);
+    let mut depth_calibration: Option<DepthCalibration> = None;
+    let (heater_range, cooler_range) = map_temperature_ranges(&settings_dto.temperature_settings)
+        .unwrap_or_else(|err| panic!("Invalid temperature settings: {}", err));
+    let mut controller = AquariumController::new(map_schedule(&settings_dto.lighting_schedule), 
+                                                 heater_range,
+                                                 cooler_range,
+                                                 settings_dto.depth_settings.maintainRange.low,
+                                                 settings_dto.depth_settings.maintainRange.high,
+                                                 map_calibration(&settings_dto.depth_settings.depthValues),
+                                                 control_temp, depth,
+                                                 FlowRate::in_ml_per_min(settings_dto.doser_settings.pumpRateMlMin),
+                                                 outputs,
+                                                 clock);
+    controller.set_heater_pid(settings_dto.temperature_settings.heaterPid.as_ref().map(map_heater_pid));
+    controller.set_cooler_limits(map_cooler_limits(&settings_dto.temperature_settings.coolerLimits));
+    controller.set_stage_offsets(map_stage_offsets(&settings_dto.temperature_settings.stageOffsets));
+    controller.set_temperature_fault_check(map_fault_check_s(&settings_dto.temperature_settings));
+    let (reservoir_capacity, reservoir_alert_s) = map_reservoir(&settings_dto.depth_settings);
+    controller.set_reservoir(reservoir_capacity, reservoir_alert_s);
+    controller.set_ato_daily_cap(map_daily_cap(&settings_dto.depth_settings));
+    controller.set_ambient(air_temp_signal.clone(), humidity_signal.clone());
+    match read_evaporation() {
+        Ok(days) => controller.restore_evaporation(days),
+        Err(err) => info!("No evaporation history: {}", err),
+    }
+    // what was left in the reservoir before a restart
+    let mut saved_reservoir = read_reservoir().ok();
+    if let Some(remaining) = saved_reservoir {
+        controller.restore_reservoir(remaining);
+    }
+    controller.set_ato_floats(input_stream(&devices, &settings_dto.hardware.atoHighFloat),
+                              input_stream(&devices, &settings_dto.hardware.atoReservoirFloat));
+    loop {
+        match rx_live.try_recv() {
+            Ok(config_dto) => {
+                let lights = config_dto.lights;
+                let leg = ScheduleLeg { 
+                    intensity: lights.intensity,
+                    intensities: lights.intensities,
+                    start_time: NaiveTime::from_hms(0,0,0)
+                };
+
+                controller.live_mode(i * TICK_MS, leg);
+            }, 
+            Err(err) if err == TryRecvError::Disconnected => {
+                panic!("Web server disconnected!");
+            },
+            _ => (),
+        }
+
+        match rx_commands.try_recv() {
+            Ok(commands) => {
+                match commands.lighting_schedule {
+                    Some(schedule_dto) => {
+                        let schedule = map_schedule(&schedule_dto); 
+                        controller.schedule_updated(schedule);
+
+                        settings_dto.lighting_schedule = schedule_dto;
+                    }, 
+                    None => {}
+                }
+                match commands.temperature_settings.map(|settings| (map_temperature_ranges(&settings), settings)) {
+                    Some((Err(err), _)) => error!("Ignoring temperature settings: {}", err),
+                    Some((Ok((heater_range, cooler_range)), temperature_settings)) => {
+                        controller.set_temp_range(heater_range, cooler_range);
+                        controller.set_heater_pid(temperature_settings.heaterPid.as_ref().map(map_heater_pid));
+                        controller.set_cooler_limits(map_cooler_limits(&temperature_settings.coolerLimits));
+                        controller.set_stage_offsets(map_stage_offsets(&temperature_settings.stageOffsets));
+                        controller.set_temperature_fault_check(map_fault_check_s(&temperature_settings));
+                        settings_dto.temperature_settings = temperature_settings;
+                    },
+                    None => {}
+                }
+                match commands.depth_settings {
+                    Some(depth_settings) => {
+                        controller.set_depth_settings(depth_settings.maintainRange.low, depth_settings.maintainRange.high,
+                                                      map_calibration(&depth_settings.depthValues));
+                        let (capacity, alert_s) = map_reservoir(&depth_settings);
+                        controller.set_reservoir(capacity, alert_s);
+                        controller.set_ato_daily_cap(map_daily_cap(&depth_settings));
+                        settings_dto.depth_settings = depth_settings;
+                    },
+                    None => {}
+                }
+                if let Some(toggles) = commands.toggles {
+                    if let Err(err) = controller.enable_pump(toggles.pump, i * TICK_MS) {
+                        error!("Error enabling pump: {}", err);
+                    }
+                }
+                if let Some(viewing_mode) = commands.viewing_mode {
+                    let lights = viewing_mode.lights;
+                    let leg = ScheduleLeg { 
+                        intensity: lights.intensity,
+                        intensities: lights.intensities,
+                        start_time: NaiveTime::from_hms(0,0,0)
+                    };
+                    controller.set_viewing_mode(viewing_mode.on, i * TICK_MS, leg);
+                }
+                if let Some(doser_settings) = commands.doser_settings {
+                    let schedule: io::Result<Vec<Dose>> = doser_settings.schedule.iter().map(|leg| parse_time(&leg.startTime).map(|start_time| Dose {
+                        dose_amount: Volume::in_ml(leg.doseAmountMl),
+                        start_time: start_time,
+                    })).collect();
+                    match schedule {
+                        Ok(schedule) => {
+                            controller.set_doser_settings(FlowRate::in_ml_per_min(doser_settings.pumpRateMlMin), schedule);
+                            settings_dto.doser_settings = doser_settings;
+                        },
+                        Err(err) => error!("Ignoring doser settings: {}", err),
+                    }
+                }
+                if let Some(units) = commands.units {
+                    settings_dto.units = units;
+                }
+                if let Some(action) = commands.ph_calibration {
+                    let result = ph_calibration_command(&action).and_then(|command| devices.calibrate_ph(command));
+                    match result {
+                        Ok(Some(config)) => {
+                            info!("pH probe calibrated: {:?}, efficiency {:?}%", config, config.efficiency());
+                            if let Some(warning) = config.efficiency_warning() {
+                                warn!("{}", warning);
+                            }
+                            settings_dto.ph.enabled = true;
+                            settings_dto.ph.ph7Cal = config.ph7_cal;
+                            settings_dto.ph.ph10Cal = config.ph10_cal;
+                            settings_dto.ph.ph4Cal = config.ph4_cal;
+                            settings_dto.ph.slopeMvPerPh = Some(config.get_step());
+                            settings_dto.ph.acidSlopeMvPerPh = config.get_acid_step();
+                            ph_calibration_error = None;
+                        },
+                        Ok(None) => ph_calibration_error = None,
+                        Err(err) => {
+                            error!("pH calibration: {}", err);
+                            ph_calibration_error = Some(err.to_string());
+                        },
+                    }
+                }
+                if let Some((action, reply)) = commands.depth_calibration {
+                    let metric = settings_dto.depth_settings.depthValues.metric;
+                    let result = depth_calibration_action(&action, &mut depth_calibration, depth_reading.sample(), metric);
+                    let reply_result = match result {
+                        Ok(Some(fit)) => {
+                            info!("Depth calibrated at {:.1} steps per cm, worst point {:.1}mm off", fit.steps_per_m / 100.0, fit.max_error.mm());
+                            settings_dto.depth_settings.depthValues.low = fit.low;
+                            settings_dto.depth_settings.depthValues.high = fit.high;
+                            settings_dto.depth_settings.depthValues.highLevel = length_in_unit(fit.high_level, metric);
+                            controller.set_depth_settings(settings_dto.depth_settings.maintainRange.low,
+                                                          settings_dto.depth_settings.maintainRange.high,
+                                                          map_calibration(&settings_dto.depth_settings.depthValues));
+                            Ok(Some(settings_dto.depth_settings.depthValues))
+                        },
+                        Ok(None) => Ok(None),
+                        Err(err) => {
+                            error!("Depth calibration: {}", err);
+                            Err(err.to_string())
+                        },
+                    };
+                    if reply.send(reply_result).is_err() {
+                        error!("Depth calibration request went away before the reply");
+                    }
+                }
+                if commands.ato_refilled.is_some() {
+                    controller.reservoir_refilled();
+                }
+                if commands.ato_reset.is_some() {
+                    controller.reset_ato();
+                }
+                if commands.temperature_reset.is_some() {
+                    controller.reset_temperature_faults();
+                }
+                if commands.garage_door_opener.is_some() {
+                    open_garage_door();
+                }
+                save_settings(&settings_dto);
+            }, 
+            Err(err) if err == TryRecvError::Disconnected => {
+                panic!("Web server disconnected!");
+            },
+            _ => (),
+        }
+
+        i += 1;
+        devices.tick(i * TICK_MS as u64);
+        // the status lock is only held while it's filled in
+        {
+            let mut status = status_lock.write().unwrap();
+            let controller_status = controller.status();
+            let unit = settings_dto.units.temperatureUnit;
+            status.temperatureUnit = unit;
+            status.currentTempF = temp_signal.sample();
+            status.currentTemp = unit.from_f(status.currentTempF);
+            status.probes = probe_signals.iter()
+                .map(|&(ref name, ref signal)| ProbeStatus { name: name.clone(), tempF: signal.sample(), temp: unit.from_f(signal.sample()) })
+                .collect();
+            status.sensors = devices.sensor_health().into_iter().map(|health| SensorStatus {
+                name: health.sensor.clone(),
+                healthy: health.is_healthy(),
+                secondsSinceGood: health.last_good_ticks.map(|t| (i * TICK_MS).saturating_sub(t) / 1000),
+                consecutiveFailures: health.consecutive_failures,
+                totalFailures: health.total_failures,
+                outOfRange: health.out_of_range,
+                lastError: health.last_error,
+                warning: health.warning,
+            }).collect();
+            status.inputs = input_signals.iter()
+                .map(|&(ref name, ref signal)| InputStatus { name: name.clone(), active: signal.sample() })
+                .collect();
+            status.depth = depth_signal.sample();
+            status.depthCalibration = depth_calibration_status(&depth_calibration, settings_dto.depth_settings.depthValues.metric);
+            status.airTempF = air_temp_signal.sample();
+            status.airTemp = unit.from_f(status.airTempF);
+            status.humidity = humidity_signal.sample();
+            status.pH = ph_signal.sample();
+            status.phCalibration = ph_calibration_status(&devices, &ph_config_from_settings(&settings_dto.ph), &ph_calibration_error);
+            status.heater_on = controller_status.heater_on;
+            status.heaterPid = controller_status.heater_pid.map(|pid| HeaterPidStatus {
+                setpointF: pid.setpoint,
+                errorF: pid.error,
+                p: pid.p,
+                i: pid.i,
+                d: pid.d,
+                output: pid.output,
+            });
+            status.heaterRateFPerHour = controller_status.heater_rate;
+            status.coolerRateFPerHour = controller_status.cooler_rate;
+            status.stages = controller_status.temp_stages.iter()
+                .map(|s| TemperatureStageStatus { name: s.name.to_string(), on: s.on, offsetF: s.offset })
+                .collect();
+            status.ato_pump_on = controller_status.ato_pump_on;
+            status.atoState = controller_status.ato_state.name().to_string();
+            let reservoir = controller_status.ato_reservoir;
+            let metric = settings_dto.depth_settings.depthValues.metric;
+            status.atoReservoir = AtoReservoirStatus {
+                lastRun: reservoir.last_run.map(|v| volume_in_unit(v, metric)),
+                lastDay: volume_in_unit(reservoir.last_day, metric),
+                capacity: reservoir.capacity.map(|v| volume_in_unit(v, metric)),
+                remaining: reservoir.remaining.map(|v| volume_in_unit(v, metric)),
+                hoursLeft: reservoir.hours_left,
+            };
+            status.atoEvaporation = controller_status.ato_evaporation.map(|e| AtoEvaporationStatus {
+                today: volume_in_unit(e.today, metric),
+                averagePerDay: e.average_per_day.map(|v| volume_in_unit(v, metric)),
+                days: e.days,
+                airTempCorrelation: e.air_temp_correlation.map(|c| (c * 100.0).round() / 100.0),
+                humidityCorrelation: e.humidity_correlation.map(|c| (c * 100.0).round() / 100.0),
+            });
+            status.cooler_on = controller_status.cooler_on;
+            status.pump_on = controller_status.pump_on;
+        }
+        match controller.tick(&mut devices, i * TICK_MS as u64) {
+            Err(err) => error!("Failed to tick controller. Need to add retry logic. {:?}", err),
+            Ok(_) => {}
+        }
+
+        if i % (30000 / TICK_MS as u64) == 0 {
+            let mut status = controller.status();
+            let now = controller.now(i * TICK_MS);
+            if let Err(result) = write_csv(now, &temp_signal, &depth_signal, &air_temp_signal, &humidity_signal, &ph_signal, &probe_signals, &status) {
+                error!("Could not write csv data: {}", result);
+            }
+            status.alerts.extend(controller.take_new_alerts());
+            if let Err(result) = alert(&status.alerts) {
+                error!("Error sending alerts: {:?}", result);
+            }
+            if status.ato_reservoir.remaining.is_some() && status.ato_reservoir.remaining != saved_reservoir {
+                saved_reservoir = status.ato_reservoir.remaining;
+                if let Err(result) = save_reservoir(status.ato_reservoir.remaining.unwrap()) {
+                    error!("Could not save the reservoir level: {}", result);
+                }
+            }
+            for day in controller.take_evaporation_days() {
+                if let Err(result) = append_evaporation(&day) {
+                    error!("Could not save evaporation for {}: {}", day.date, result);
+                }
+            }
+        }
+
+        thread::sleep(std::time::Duration::from_millis(TICK_MS as u64 / speed));
+    }
+}
+
+fn ph_calibration_status<H: Hardware>(devices: &H, config: &PhConfig, command_error: &Option<String>) -> PhCalibrationStatus {
+    let mut status = PhCalibrationStatus {
+        error: command_error.clone(),
+        slopeMvPerPh: config.get_step(),
+        efficiencyPercent: config.efficiency(),
+        acidSlopeMvPerPh: config.get_acid_step(),
+        acidEfficiencyPercent: config.acid_efficiency(),
+        ..Default::default()
+    };
+    if let Some(calibration) = devices.ph_calibration() {
+        status.calibrating = true;
+        status.sampling = calibration.sampling();
+        status.lastRaw = calibration.last_raw();
+        status.points = calibration.points().iter().map(|p| PhCalibrationPoint { buffer: p.0, raw: p.1 }).collect();
+        if let Some(error) = calibration.error() {
+            status.error = Some(error.clone());
+        }
+    }
+    status
+}
+
+// The fit once the calibration is finished
+// marks are in inches, or cm when metric
+fn depth_calibration_action(action: &DepthCalibrationAction, calibration: &mut Option<DepthCalibration>, depth: Option<Depth>, metric: bool) -> io::Result<Option<DepthFit>> {
+    let not_started = || io::Error::new(io::ErrorKind::InvalidInput, "No depth calibration in progress");
+    match action.action.as_str() {
+        "start" => *calibration = Some(DepthCalibration::new()),
+        "cancel" => *calibration = None,
+        "capture" => {
+            let depth = try!(depth.ok_or(io::Error::new(io::ErrorKind::NotFound, "No depth reading yet")));
+            try!(try!(calibration.as_mut().ok_or_else(not_started)).capture(length_from_unit(action.level, metric), depth));
+        },
+        "finish" => {
+            let fit = try!(try!(calibration.as_ref().ok_or_else(not_started)).finish());
+            *calibration = None;
+            return Ok(Some(fit));
+        },
+        other => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown depth calibration action: {}", other))),
+    }
+    Ok(None)
+}
+
+fn depth_calibration_status(calibration: &Option<DepthCalibration>, metric: bool) -> DepthCalibrationStatus {
+    match *calibration {
+        Some(ref calibration) => {
+            let fit = calibration.fit().ok();
+            let unit = length_from_unit(1.0, metric);
+            DepthCalibrationStatus {
+                calibrating: true,
+                points: calibration.points().iter().map(|p| DepthCalibrationPoint { level: length_in_unit(p.0, metric), raw: p.1 }).collect(),
+                stepsPerUnit: fit.as_ref().map(|f| f.steps_per_m * unit.m()),
+                maxError: fit.as_ref().map(|f| length_in_unit(f.max_error, metric)),
+            }
+        },
+        None => DepthCalibrationStatus::default(),
+    }
+}
+
+// The configured control probe, or the first probe on the bus if there isn't one
+fn control_temp_stream<H: Hardware>(devices: &H, name: &str) -> Stream<Temperature<F>> {
+    if name.is_empty() {
+        return devices.temp_stream();
+    }
+    match devices.probe_streams().into_iter().find(|p| p.0 == name) {
+        Some((_, stream)) => stream,
+        None => {
+            warn!("Control probe {} not found, using the first probe", name);
+            devices.temp_stream()
+        }
+    }
+}
+
+// (capacity, alert seconds), no capacity when it's 0
+fn map_reservoir(settings: &DepthSettings) -> (Option<Volume>, u64) {
+    let reservoir = settings.reservoir;
+    let capacity = if reservoir.capacity <= 0.0 {
+        None
+    } else {
+        Some(volume_from_unit(reservoir.capacity, settings.depthValues.metric))
+    };
+    (capacity, (reservoir.alertHours.max(0.0) * 60.0 * 60.0) as u64)
+}
+
+// a cap of 0 or less would lock the ATO out for good, so it's the default instead
+fn map_daily_cap(settings: &DepthSettings) -> Option<Volume> {
+    match settings.maxDailyVolume {
+        Some(cap) if cap > 0.0 => Some(volume_from_unit(cap, settings.depthValues.metric)),
+        Some(cap) => { warn!("Ignoring maxDailyVolume of {}", cap); None },
+        None => None,
+    }
+}
+
+fn length_from_unit(value: f32, metric: bool) -> Length {
+    if metric { Length::in_cm(value) } else { Length::in_inches(value) }
+}
+
+fn length_in_unit(length: Length, metric: bool) -> f32 {
+    if metric { length.cm() } else { length.inches() }
+}
+
+fn volume_from_unit(value: f32, metric: bool) -> Volume {
+    if metric { Volume::in_litres(value) } else { Volume::in_gallons(value) }
+}
+
+fn volume_in_unit(volume: Volume, metric: bool) -> f32 {
+    let value = if metric { volume.litres() } else { volume.gallons() };
+    (value * 100.0).round() / 100.0
+}
+
+fn input_stream<H: Hardware>(devices: &H, name: &str) -> Option<Stream<Option<bool>>> {
+    if name.is_empty() {
+        return None;
+    }
+    let stream = devices.input_streams().into_iter().find(|i| i.0 == name).map(|i| i.1);
+    if stream.is_none() {
+        warn!("Input {} not found", name);
+    }
+    stream
+}
+
+// to a tenth of a degree for display
+fn rounded_temp(stream: &Stream<Temperature<F>>) -> Signal<f32> {
+    stream.map(|temp| (temp.value() * 10.0).round() / 10.0).hold(80.0)
+}
+
+fn parse_time(time: &str) -> io::Result<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M")
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid time: {:?}", time)))
+}
+
+fn map_schedule(schedule_dto: &ScheduleDto) -> Schedule {
+    Schedule::new(schedule_dto.schedule.iter().map(|l| ScheduleLeg {
+        intensity: l.intensity,
+        intensities: l.intensities,
+        start_time: NaiveTime::parse_from_str(&l.startTime, "%H:%M").unwrap()
+    }).collect())
+}
+
+fn map_calibration(values: &DepthSettingsDepthValues) -> Calibration {
+    let area = values.surfaceArea as f32;
+    if values.metric {
+        Calibration {
+            low: values.low,
+            high: values.high,
+            high_level: Length::in_cm(values.highLevel),
+            tank_surface_area: Area::in_sq_cm(area),
+            tank_volume: Volume::in_litres(values.tankVolume),
+            pump_rate: FlowRate::in_lph(values.pumpRate),
+        }
+    } else {
+        Calibration {
+            low: values.low,
+            high: values.high,
+            high_level: Length::in_inches(values.highLevel),
+            tank_surface_area: Area::in_sq_inches(area),
+            tank_volume: Volume::in_gallons(values.tankVolume),
+            pump_rate: FlowRate::in_gph(values.pumpRate),
+        }
+    }
+}
+
+// settings without a unit are in F
+fn map_temperature_range(settings: &TemperatureRangeSettings, unit: Option<TemperatureUnit>) -> io::Result<TemperatureRange> { 
+    let temp = |value: f32| Temperature::in_f(unit.unwrap_or(TemperatureUnit::F).to_f(value));
+    if settings.points.is_empty() {
+        return Ok(TemperatureRange::min_max(temp(settings.min), try!(parse_time(&settings.minTime)),
+                                            temp(settings.max), try!(parse_time(&settings.maxTime))));
+    }
+    let points: io::Result<Vec<TemperaturePoint>> = settings.points.iter()
+        .map(|p| parse_time(&p.time).map(|time| TemperaturePoint { time: time, temp: temp(p.temp) }))
+        .collect();
+    Ok(TemperatureRange::new(try!(points)))
+}
+
+// (heater, cooler)
+fn map_temperature_ranges(settings: &TemperatureSettings) -> io::Result<(TemperatureRange, TemperatureRange)> {
+    Ok((try!(map_temperature_range(&settings.heater, settings.unit)),
+        try!(map_temperature_range(&settings.cooler, settings.unit))))
+}
+
+fn map_heater_pid(settings: &HeaterPidSettings) -> PidSettings {
+    PidSettings {
+        kp: settings.kp,
+        ki: settings.ki,
+        kd: settings.kd,
+        window_s: settings.windowSeconds,
+    }
+}
+
+fn map_cooler_limits(settings: &CoolerLimitSettings) -> CoolerLimits {
+    CoolerLimits {
+        min_on_s: settings.minRunSeconds,
+        min_off_s: settings.minRestSeconds,
+        max_on_s: settings.maxRunSeconds,
+    }
+}
+
+// a check of 0 minutes would cut the outlets as soon as they came on
+fn map_fault_check_s(settings: &TemperatureSettings) -> u64 {
+    cmp::max(settings.faultCheckMinutes, 1) * 60
+}
+
+fn map_stage_offsets(settings: &StageOffsetSettings) -> StageOffsets {
+    StageOffsets {
+        heater: settings.heaterF,
+        backup_heater: settings.backupHeaterF,
+        fan: settings.fanF,
+        cooler: settings.coolerF,
+    }
+}
+
+fn start_server(server_addr: &str, settings: &Settings) -> (Arc<RwLock<StatusDto>>, Receiver<LiveModeSettings>, Receiver<Commands>) {
+    let (tx, rx) = channel();
+    let status = StatusDto { currentTempF: 0.0, temperatureUnit: settings.units.temperatureUnit, currentTemp: 0.0, airTemp: 0.0, depth: 0, airTempF: 0.0, humidity: 0.0, pH: 0.0, phCalibration: PhCalibrationStatus::default(), depthCalibration: DepthCalibrationStatus::default(), probes: vec![], sensors: vec![], inputs: vec![], heater_on: false, heaterPid: None, heaterRateFPerHour: None, coolerRateFPerHour: None, stages: vec![], cooler_on: false, ato_pump_on: false, atoState: "idle".to_string(), atoReservoir: AtoReservoirStatus::default(), atoEvaporation: None, pump_on: false };
+    let status_lock = Arc::new(RwLock::new(status));
+    let status_return = status_lock.clone();
+    let (tx_c, rx_c) = channel();
+    
+    let settings_for_server = settings.clone();
+    let addr = server_addr.to_string();
+
+    thread::spawn(move || { aquamon_server::server::start_on(&addr, tx, status_lock, tx_c, settings_for_server); });
+    trace!("started!");
+
+    (status_return, rx, rx_c)
+}
+
+// probe temperatures go at the end of the line in the order they're configured, then
+// the extra stages
+fn write_csv(now: DateTime<Local>, temp: &Signal<f32>, depth: &Signal<Depth>, air_temp: &Signal<f32>, humidity: &Signal<f32>, ph_signal: &Signal<f32>, probes: &[(String, Signal<f32>)], status: &Status) -> io::Result<()> {
+    let file_opened = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .append(true)
+        .open("history.csv");
+
+    let probe_temps: String = probes.iter().map(|p| format!(",{}", p.1.sample())).collect();
+    // heater and cooler already have their own columns
+    let stages: String = status.temp_stages.iter()
+        .filter(|s| s.name != "heater" && s.name != "cooler")
+        .map(|s| format!(",{}={}", s.name, s.on))
+        .collect();
+    file_opened.and_then(|mut file| {
+        file.write_all(format!("{},{},{},{},{},{},{},{},{}{}{}\n", 
+                           now.format("%Y-%m-%dT%H:%M:%S%z"), 
+                           temp.sample(),
+                           depth.sample(),
+                           status.heater_on,
+                           status.ato_pump_on,
+                           status.cooler_on,
+                           air_temp.sample(),
+                           humidity.sample(), 
+                           ph_signal.sample(),
+                           probe_temps,
+                           stages).as_bytes())
+            .and_then(|_| file.sync_data())
+    })
+}
+
+// reservoir.txt holds the litres left in the ATO reservoir
+fn read_reservoir() -> io::Result<Volume> {
+    let mut s = String::new();
+    try!(File::open("reservoir.txt").and_then(|mut file| file.read_to_string(&mut s)));
+    s.trim().parse::<f32>()
+        .map(Volume::in_litres)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+}
+
+fn save_reservoir(remaining: Volume) -> io::Result<()> {
+    File::create("reservoir.txt").and_then(|mut file| file.write_all(format!("{}\n", remaining.litres()).as_bytes()))
+}
+
+// evaporation.csv has a line per day: date, litres, air temp in F, humidity
+fn read_evaporation() -> io::Result<Vec<EvaporationDay>> {
+    let mut s = String::new();
+    try!(File::open("evaporation.csv").and_then(|mut file| file.read_to_string(&mut s)));
+    Ok(s.lines().filter_map(|line| {
+        let fields: Vec<&str> = line.split(',').collect();
+        if fields.len() < 4 {
+            return None;
+        }
+        match (NaiveDate::parse_from_str(fields[0], "%Y-%m-%d"), fields[1].parse(), fields[2].parse(), fields[3].parse()) {
+            (Ok(date), Ok(litres), Ok(air_temp), Ok(humidity)) =>
+                Some(EvaporationDay { date: date, volume: Volume::in_litres(litres), air_temp: air_temp, humidity: humidity }),
+            _ => {
+                warn!("Skipping evaporation line {}", line);
+                None
+            },
+        }
+    }).collect())
+}
+
+fn append_evaporation(day: &EvaporationDay) -> io::Result<()> {
+    OpenOptions::new().create(true).append(true).open("evaporation.csv").and_then(|mut file| {
+        file.write_all(format!("{},{},{},{}\n", day.date.format("%Y-%m-%d"), day.volume.litres(), day.air_temp, day.humidity).as_bytes())
+    })
+}
+
+fn read_settings() -> io::Result<Settings> {
+    let mut file = try!(File::open("settings.json"));
+    let mut s = String::new();
+    try!(file.read_to_string(&mut s));
+    Ok(serde_json::from_str(&s).unwrap())
+}
+
+fn save_settings(config: &Settings) {
+    let file_opened = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .append(false)
+        .open("settings.json");
+
+    let result = file_opened.and_then(|mut file| {
+        let json_string = serde_json::to_string(config).unwrap();
+        // can safely ignore as it only fails if not opened for writing
+        file.set_len(0).unwrap();
+        file.write_all(json_string.as_bytes())
+    });
+    
+    if let Err(e) = result {
+        error!("Could not write to settings file {}", e.description());
+    } else {
+        info!("settings written");
+    }
+}
+
+pub fn init_logging() {
+    let format = |record: &LogRecord| {
+        format!("{} - {}", record.level(), record.args())
+    };
+    let mut builder = LogBuilder::new();
+    builder.format(format).filter(None, LogLevelFilter::Error);
+
+    if env::var("RUST_LOG").is_ok() {
+        builder.parse(&env::var("RUST_LOG").unwrap());
+    }
+    builder.init().unwrap();
+}
+
+fn open_garage_door() {
+    info!("Opening garage door");
+    match Command::new("sh")
+        .arg("-c")
+        .arg("ssh -i /home/pi/.ssh/id_rsa_pi1 pi@pi1 './open_garage_door.sh'")
+        .output() {
+            Err(e) => error!("Could not open garage door: {:?}", e),
+            Ok(output) => info!("Opening garage door: {:?}", output)
+    }
+}
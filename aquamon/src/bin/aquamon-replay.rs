@@ -0,0 +1,44 @@
+extern crate aquamon;
+extern crate chrono;
+
+use std::env;
+
+use chrono::Local;
+
+use aquamon::app;
+use aquamon::clock::TickClock;
+use aquamon::controller::Outputs;
+use aquamon::devices::{MemoryRelay, Replay};
+
+// Feeds a recording made with `aquamon --record` back through the controllers, with the
+// outputs switching in memory only. Run it from `aquamon_server` like `aquamon-sim`.
+// Usage: aquamon-replay <recording> [address] [speed]
+fn main() {
+    app::init_logging();
+    let path = env::args().nth(1).expect("Usage: aquamon-replay <recording> [address] [speed]");
+    let addr = env::args().nth(2).unwrap_or("127.0.0.1:8080".to_string());
+    let speed = env::args().nth(3).map(|s| match s.parse::<u64>() {
+        Ok(speed) if speed >= 1 => speed,
+        _ => panic!("Usage: aquamon-replay <recording> [address] [speed], speed must be a whole number of at least 1"),
+    }).unwrap_or(1);
+    let settings = app::load_settings();
+    let mut replay = Replay::open(&path).unwrap_or_else(|err| panic!("Could not read {}: {}", path, err));
+    let calibrations = app::temp_calibrations_from_settings(&settings.hardware)
+        .unwrap_or_else(|err| panic!("Invalid hardware settings: {}", err));
+    for (sensor, calibration) in calibrations {
+        replay.calibrate(&sensor, calibration);
+    }
+    let outputs = Outputs {
+        heater: Box::new(MemoryRelay::new()),
+        ato: Box::new(MemoryRelay::new()),
+        cooler: Box::new(MemoryRelay::new()),
+        return_pump: Box::new(MemoryRelay::new()),
+        fuge_light: Box::new(MemoryRelay::new()),
+        doser: Box::new(MemoryRelay::new()),
+        backup_heater: None,
+        fan: None,
+    };
+    // schedules see the time of day the recording was made
+    let clock = TickClock::new(replay.start().unwrap_or(Local::now()));
+    app::run(replay, outputs, Box::new(clock), settings, &addr, speed);
+}
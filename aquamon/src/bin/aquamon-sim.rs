@@ -0,0 +1,27 @@
+extern crate aquamon;
+extern crate chrono;
+
+use std::env;
+
+use chrono::Local;
+
+use aquamon::app;
+use aquamon::clock::TickClock;
+use aquamon::devices::{Simulator, SimulatorConfig};
+
+// Runs the controller and web UI against a simulated tank. Run it from a directory
+// containing the web UI's `static/` folder, e.g. `aquamon_server`.
+// Usage: aquamon-sim [address] [speed]
+fn main() {
+    app::init_logging();
+    let addr = env::args().nth(1).unwrap_or("127.0.0.1:8080".to_string());
+    let speed = env::args().nth(2).map(|s| match s.parse::<u64>() {
+        Ok(speed) if speed >= 1 => speed,
+        _ => panic!("Usage: aquamon-sim [address] [speed], speed must be a whole number of at least 1"),
+    }).unwrap_or(1);
+    let simulator = Simulator::new(SimulatorConfig::default());
+    let outputs = simulator.outputs();
+    // simulated time starts now and runs `speed` times faster than the wall clock
+    let clock = TickClock::new(Local::now());
+    app::run(simulator, outputs, Box::new(clock), app::load_settings(), &addr, speed);
+}
@@ -0,0 +1,48 @@
+use chrono::prelude::*;
+use chrono::Duration;
+
+/// Source of local wall-clock time for the schedules. `ticks` is the millisecond counter
+/// the main loop hands to every controller, so clocks that don't follow the system time
+/// can be derived from it.
+pub trait Clock {
+    fn now(&self, ticks: u64) -> DateTime<Local>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self, _ticks: u64) -> DateTime<Local> {
+        UTC::now().with_timezone(&Local)
+    }
+}
+
+/// A clock that only moves with the tick counter, starting at `start` on tick 0. Lets
+/// tests and the simulator run through a day of schedules as fast as they can tick.
+pub struct TickClock {
+    start: DateTime<Local>,
+}
+
+impl TickClock {
+    pub fn new(start: DateTime<Local>) -> TickClock {
+        TickClock { start: start }
+    }
+}
+
+impl Clock for TickClock {
+    fn now(&self, ticks: u64) -> DateTime<Local> {
+        self.start + Duration::milliseconds(ticks as i64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tick_clock_follows_ticks() {
+        let clock = TickClock::new(Local.ymd(2018, 3, 1).and_hms(23, 0, 0));
+
+        assert_eq!(clock.now(0).time(), NaiveTime::from_hms(23, 0, 0));
+        assert_eq!(clock.now(90 * 60 * 1000).time(), NaiveTime::from_hms(0, 30, 0));
+    }
+}
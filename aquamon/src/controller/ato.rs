@@ -1,59 +1,129 @@
 use std::io as io;
-use ::devices::GpioPin; 
+use std::cmp;
+use ::devices::Relay;
 use ::devices::Depth;
+use ::uom::length::Length;
+use ::uom::area::Area;
+use ::uom::volume::Volume;
+use ::uom::flow::FlowRate;
+use super::freshness::Freshness;
+use super::reservoir::{Reservoir, ReservoirStatus};
 use carboxyl::{Signal,Stream};
 
+/// What the ATO is doing, or why it isn't
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AtoState {
+    Idle,
+    Filling,
+    /// Ran longer than the calibration says a fill takes, rests for `TIMEOUT_PERIOD`
+    TimedOut,
+    /// Hit the daily cap or dropped too fast between fills, stays off until reset
+    FailedSafe,
+    /// Waiting to see if a sudden drop in depth comes back
+    QuickDrop,
+    /// Too far below the low point to fill automatically
+    TooLow,
+    NoDepth,
+    HighFloat,
+    ReservoirEmpty,
+}
+
+impl AtoState {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            AtoState::Idle => "idle",
+            AtoState::Filling => "filling",
+            AtoState::TimedOut => "timedOut",
+            AtoState::FailedSafe => "failedSafe",
+            AtoState::QuickDrop => "quickDrop",
+            AtoState::TooLow => "tooLow",
+            AtoState::NoDepth => "noDepth",
+            AtoState::HighFloat => "highFloat",
+            AtoState::ReservoirEmpty => "reservoirEmpty",
+        }
+    }
+
+    // raised once on the way in. NoDepth, ReservoirEmpty and FailedSafe have alerts for as
+    // long as they last instead
+    fn alert(&self) -> Option<&'static str> {
+        match *self {
+            AtoState::TimedOut => Some("ATO ran longer than a fill should take and is resting for 2 hours"),
+            AtoState::QuickDrop => Some("Depth dropped suddenly, ATO is waiting for it to come back"),
+            AtoState::TooLow => Some("Water level is too far below the low point for the ATO, fill the tank by hand"),
+            AtoState::HighFloat => Some("High float switch is active, ATO is off"),
+            _ => None,
+        }
+    }
+}
+
 pub struct AtoController {
     low_point: Depth,
     high_point: Depth,
-    pin: GpioPin,
+    pin: Box<Relay>,
     on_tick_s: u64,
     timed_out_tick_s: u64,
     calibration: Calibration,
     depth_signal: Signal<Depth>,
-    prev_high: (u64, Depth),
     recent_high: Signal<Depth>,
-    failed_safe: bool,
+    prev_high: (u64, Depth),
+    daily_cap: Volume,
+    // why it's locked out, until it's reset
+    locked_out: Option<String>,
+    // runs before a reset don't count against the cap
+    cap_reset_tick_s: u64,
     pump_off: u64,
+    freshness: Freshness,
+    stale: bool,
+    // None until a float is read, or when it can't be
+    high_float: Option<Signal<Option<bool>>>,
+    reservoir_empty: Option<Signal<Option<bool>>>,
+    reservoir: Reservoir,
+    last_tick_s: u64,
+    state: AtoState,
+    // alerts for states entered since they were last taken
+    new_alerts: Vec<String>,
 }
 
 pub struct Calibration {
     pub low: Depth,
     pub high: Depth,
-    pub high_inches: f32,
-    pub tank_surface_area: u16,
-    pub pump_gph: f32,
-    pub tank_volume: f32,
+    /// How far the water is above the `low` reading at the `high` reading
+    pub high_level: Length,
+    pub tank_surface_area: Area,
+    pub pump_rate: FlowRate,
+    pub tank_volume: Volume,
 }
 
-const CU_IN_PER_GALLON: f32 = 231.0;
 const TIMEOUT_PERIOD: u64 = 2 * 60 * 60;
+// faster than this between fills is a leak or a siphon. The default daily cap is a day of it.
 const MAX_EVAP_GPH: f32 = 1.0 / 8.0;
+const DAY_S: f32 = 24.0 * 60.0 * 60.0;
+// readings come every second
+const STALE_AFTER_S: u64 = 30;
  
 impl Calibration {
-    fn run_time_s(&self, low_point: Depth, high_point: Depth) -> u64 {
-        let steps_per_in: f32 = (self.high as f32 - self.low as f32) / self.high_inches; 
-
-        let pump_gps = self.pump_gph / 60.0 / 60.0;
-        let gallons_per_in = (self.tank_surface_area as f32) / CU_IN_PER_GALLON;
+    fn steps_per_length(&self) -> f32 {
+        (self.high as f32 - self.low as f32) / self.high_level.m()
+    }
 
-        let in_to_rise = (high_point as f32 - low_point as f32) / steps_per_in;
-        let gallons_to_pump = in_to_rise * gallons_per_in;
+    fn run_time_s(&self, low_point: Depth, high_point: Depth) -> u64 {
+        let rise = Length::in_m((high_point as f32 - low_point as f32) / self.steps_per_length());
+        let to_pump = self.tank_surface_area * rise;
 
-        (gallons_to_pump / pump_gps) as u64
+        self.pump_rate.seconds_for(to_pump) as u64
     }
 
-    fn max_evap_per_hour(&self) -> Depth {
-        let steps_per_in: f32 = (self.high as f32 - self.low as f32) / self.high_inches; 
+    // in depth steps
+    fn max_evap_per_hour(&self) -> f32 {
+        let max_evap = FlowRate::in_gph(MAX_EVAP_GPH);
+        let drop = max_evap.volume_in(60.0 * 60.0) / self.tank_surface_area;
 
-        let gal_per_in = self.tank_surface_area as f32 / CU_IN_PER_GALLON;
-
-        (steps_per_in / gal_per_in * MAX_EVAP_GPH) as Depth
+        drop.m() * self.steps_per_length()
     }
 }
 
 impl AtoController {
-    pub fn new(low_point: Depth, high_point: Depth, calibration: Calibration, pin: GpioPin, depth_stream: Stream<Depth>) -> AtoController {
+    pub fn new(low_point: Depth, high_point: Depth, calibration: Calibration, pin: Box<Relay>, depth_stream: Stream<Depth>) -> AtoController {
         let i = 400;
         let recent_high = depth_stream.fold(([0_u16; 32], 0), |(mut hist, index), next| {
             hist[index/6] = next;
@@ -68,28 +138,168 @@ impl AtoController {
             timed_out_tick_s: 0,
             calibration: calibration,
             depth_signal: depth_stream.hold(i),
+            freshness: Freshness::new(&depth_stream, STALE_AFTER_S),
+            stale: false,
             recent_high: recent_high,
-            failed_safe: false,
             prev_high: (0, 0),
+            daily_cap: FlowRate::in_gph(MAX_EVAP_GPH).volume_in(DAY_S),
+            locked_out: None,
+            cap_reset_tick_s: 0,
             pump_off: 0,
+            high_float: None,
+            reservoir_empty: None,
+            reservoir: Reservoir::new(),
+            last_tick_s: 0,
+            state: AtoState::Idle,
+            new_alerts: vec![],
+        }
+    }
+
+    pub fn reservoir(&mut self) -> &mut Reservoir { &mut self.reservoir }
+
+    pub fn state(&self) -> AtoState { self.state }
+
+    fn enter(&mut self, state: AtoState) {
+        if state != self.state {
+            info!("ATO is now {}", state.name());
+            if let Some(alert) = state.alert() {
+                self.new_alerts.push(alert.to_string());
+            }
+            self.state = state;
+        }
+    }
+
+    /// Alerts for the states entered since the last call
+    pub fn take_new_alerts(&mut self) -> Vec<String> {
+        self.new_alerts.drain(..).collect()
+    }
+
+    /// Clears the timeout and the evaporation rate or daily cap lockout
+    pub fn reset(&mut self) {
+        info!("Resetting the ATO from {}", self.state.name());
+        self.locked_out = None;
+        self.prev_high = (0, 0);
+        self.cap_reset_tick_s = self.last_tick_s;
+        self.timed_out_tick_s = 0;
+        if self.on_tick_s == 0 {
+            self.state = AtoState::Idle;
+        }
+    }
+
+    /// The most the ATO may pump in 24 hours before it's locked out, None for the default
+    pub fn set_daily_cap(&mut self, cap: Option<Volume>) {
+        self.daily_cap = cap.unwrap_or(FlowRate::in_gph(MAX_EVAP_GPH).volume_in(DAY_S));
+    }
+
+    pub fn total_pumped(&self) -> Volume { self.reservoir.total() }
+
+    pub fn reservoir_status(&self) -> ReservoirStatus { self.reservoir.status(self.last_tick_s) }
+
+    // counts what a run pumped against the reservoir
+    fn stop_pump(&mut self, tick_s: u64) -> io::Result<()> {
+        if self.on_tick_s > 0 {
+            let pumped = self.calibration.pump_rate.volume_in(tick_s.saturating_sub(self.on_tick_s) as f32);
+            self.reservoir.pumped(pumped, tick_s);
         }
+        self.on_tick_s = 0;
+        self.pin.turn_off()
+    }
+
+    /// Float switches that stop the pump whatever the depth reading says: one that's active
+    /// when the water reaches it, and one that's active when the reservoir is empty. One that
+    /// hasn't been read, or can't be, counts as active.
+    pub fn set_float_switches(&mut self, high_float: Option<Stream<Option<bool>>>, reservoir_empty: Option<Stream<Option<bool>>>) {
+        self.high_float = high_float.map(|s| s.hold(None));
+        self.reservoir_empty = reservoir_empty.map(|s| s.hold(None));
+    }
+
+    fn float_active(float: &Option<Signal<Option<bool>>>) -> bool {
+        float.as_ref().map_or(false, |f| f.sample().unwrap_or(true))
+    }
+
+    fn float_unknown(float: &Option<Signal<Option<bool>>>) -> bool {
+        float.as_ref().map_or(false, |f| f.sample().is_none())
+    }
+
+    /// Turns the pump off if either float switch is active, returning whether one is. Cheap
+    /// enough to call on every tick.
+    pub fn check_floats(&mut self, tick_s: u64) -> io::Result<bool> {
+        let high = AtoController::float_active(&self.high_float);
+        let empty = AtoController::float_active(&self.reservoir_empty);
+        if !high && !empty {
+            return Ok(false);
+        }
+        if self.on_tick_s > 0 {
+            if high {
+                warn!("High float switch is active, turning ATO off");
+            } else {
+                error!("Reservoir is empty, turning ATO off");
+            }
+            try!(self.stop_pump(tick_s));
+        }
+        self.enter(if empty { AtoState::ReservoirEmpty } else { AtoState::HighFloat });
+        Ok(true)
     }
 
     pub fn set_settings(&mut self, low: Depth, high: Depth, calibration: Calibration) {
         self.low_point = low;
         self.high_point = high;
         self.calibration = calibration;
-        // reset failsafes in a round about way
-        self.failed_safe = false;
-        self.timed_out_tick_s = 0;
     }
 
     pub fn status(&mut self) -> bool { self.on_tick_s > 0 }
 
-    pub fn tick(&mut self,  tick_s: u64, pump_pin: &mut GpioPin) -> io::Result<()> {
-        if self.failed_safe { return Ok(()); }
+    pub fn alerts(&mut self) -> Vec<String> {
+        let mut alerts = vec![];
+        if self.stale {
+            alerts.push(format!("No depth reading for over {} seconds, ATO is off", self.freshness.limit_s()));
+        }
+        if AtoController::float_unknown(&self.high_float) {
+            alerts.push("ATO high float switch can't be read, ATO is off".to_string());
+        }
+        if AtoController::float_unknown(&self.reservoir_empty) {
+            alerts.push("ATO reservoir float switch can't be read, ATO is off".to_string());
+        } else if AtoController::float_active(&self.reservoir_empty) {
+            alerts.push("ATO reservoir is empty, ATO is off until it's refilled".to_string());
+        }
+        alerts.extend(self.locked_out.clone());
+        alerts.extend(self.reservoir.alert(self.last_tick_s));
+        alerts
+    }
+
+    pub fn tick(&mut self,  tick_s: u64, pump_pin: &mut Relay) -> io::Result<()> {
+        self.last_tick_s = tick_s;
+        if try!(self.check_floats(tick_s)) {
+            return Ok(());
+        }
+        // never fill blind
+        self.stale = self.freshness.is_stale(tick_s);
+        if self.stale {
+            if self.on_tick_s > 0 {
+                warn!("Depth reading is stale, turning ATO off");
+                try!(self.stop_pump(tick_s));
+            }
+            self.enter(AtoState::NoDepth);
+            return Ok(());
+        }
+
+        if self.locked_out.is_some() {
+            self.enter(AtoState::FailedSafe);
+            return Ok(());
+        }
+        // a leak or a siphon looks like a lot of evaporation
+        let running = self.calibration.pump_rate.volume_in(if self.on_tick_s > 0 { tick_s - self.on_tick_s } else { 0 } as f32);
+        let since = cmp::max(self.cap_reset_tick_s, tick_s.saturating_sub(DAY_S as u64));
+        if self.reservoir.pumped_since(since) + running >= self.daily_cap {
+            error!("ATO pumped its daily cap of {:.1}L, locking it out", self.daily_cap.litres());
+            self.locked_out = Some(format!("ATO pumped its cap of {:.1}L ({:.2} gallons) in 24 hours and is locked out until it's reset",
+                                           self.daily_cap.litres(), self.daily_cap.gallons()));
+            self.enter(AtoState::FailedSafe);
+            return self.stop_pump(tick_s);
+        }
 
         if self.timed_out_tick_s > 0 && self.timed_out_tick_s + TIMEOUT_PERIOD > tick_s {
+            self.enter(AtoState::TimedOut);
             return Ok(());
         }
         let depth = self.depth_signal.sample();
@@ -107,39 +317,44 @@ impl AtoController {
                 error!("Water level too far below bottom point, please manually fill the tank. This is to avoid overflows when the sensor isn't attached properly, during water changes, etc.");
                 self.pump_off = tick_s;
                 // try!(pump_pin.turn_off());
+                self.enter(AtoState::TooLow);
                 return Ok(());
             } 
-            if self.prev_high.0 > 0 {
-                let evap_per_hour = ((self.prev_high.1 - depth) as f32 / (tick_s - self.prev_high.0) as f32 / 60.0 / 60.0) as Depth;
+            if self.prev_high.0 > 0 && tick_s > self.prev_high.0 {
+                let evap_per_hour = (self.prev_high.1 as f32 - depth as f32) / ((tick_s - self.prev_high.0) as f32 / 60.0 / 60.0);
                 if evap_per_hour > self.calibration.max_evap_per_hour() {
-                    warn!("Exceeded max_evap_per_hour, stopping. Actual steps: {}. Max: {}\n", evap_per_hour, self.calibration.max_evap_per_hour());
-                    self.failed_safe = true;
+                    error!("Exceeded max_evap_per_hour, locking the ATO out. Actual steps: {:.1}. Max: {:.1}", evap_per_hour, self.calibration.max_evap_per_hour());
+                    self.locked_out = Some(format!("Water level dropped {:.1} steps an hour since the last fill, faster than {} gallons an hour of evaporation. ATO is locked out until it's reset",
+                                                   evap_per_hour, MAX_EVAP_GPH));
+                    self.enter(AtoState::FailedSafe);
                     return Ok(());
                 }
             }
             if self.recent_high.sample() - depth > 20 {
                 warn!("Quick drop in depth, waiting...");
+                self.enter(AtoState::QuickDrop);
                 return Ok(());
             } 
 
             info!("Water level too low, toggling on: {:?}", depth);
             self.on_tick_s = tick_s;
+            self.enter(AtoState::Filling);
             self.pin.turn_on() 
         // TODO: make configureable based on GPH of pump and estimated high - low
         } else if depth >= self.high_point { 
             info!("Water level too high, toggling off: {:?}", depth);
-            self.on_tick_s = 0;
             self.prev_high = (tick_s, depth);
-            self.pin.turn_off()
+            self.enter(AtoState::Idle);
+            self.stop_pump(tick_s)
         } else if self.on_tick_s > 0 && tick_s - self.on_tick_s > self.calibration.run_time_s(self.low_point, self.high_point)  { 
-            // TODO: alert should happen if the pump times out
             info!("Timed out depth after calculated seconds ({:?}), toggling off: {:?}", 
                      self.calibration.run_time_s(self.low_point, self.high_point), depth);
-            self.on_tick_s = 0;
             self.timed_out_tick_s = tick_s;
             self.prev_high = (tick_s, depth);
-            self.pin.turn_off()
+            self.enter(AtoState::TimedOut);
+            self.stop_pump(tick_s)
         } else {
+            self.enter(if self.on_tick_s > 0 { AtoState::Filling } else { AtoState::Idle });
             Ok(())
         }
     }
@@ -148,6 +363,7 @@ impl AtoController {
 #[cfg(test)]
 mod test {
     use super::*;
+    use ::devices::{DepthSource, Hardware, MemoryRelay, Replay};
     use carboxyl::Sink;
 
     #[test]
@@ -155,13 +371,269 @@ mod test {
         let calibration = Calibration {
             low: 60, 
             high: 120,
-            high_inches: 6.0, // 10 steps per inch
-            tank_surface_area: 100,
-            pump_gph: 5.0, // .2 gpm 
-            tank_volume: 10.0,
+            high_level: Length::in_inches(6.0), // 10 steps per inch
+            tank_surface_area: Area::in_sq_inches(100.0),
+            pump_rate: FlowRate::in_gph(5.0), // .2 gpm 
+            tank_volume: Volume::in_gallons(10.0),
         };
 
         // raise 3 inches (30 steps / 10 steps per inch)
         assert_eq!(calibration.run_time_s(70, 100), 935);
     }
+
+    #[test]
+    pub fn run_time_in_metric() {
+        let calibration = Calibration {
+            low: 60,
+            high: 120,
+            high_level: Length::in_cm(15.0), // 4 steps per cm
+            tank_surface_area: Area::in_sq_cm(600.0),
+            pump_rate: FlowRate::in_lph(18.0), // 5 mL a second
+            tank_volume: Volume::in_litres(40.0),
+        };
+
+        // raise 5cm, 3 litres
+        assert_eq!(calibration.run_time_s(80, 100), 600);
+    }
+
+    fn calibration() -> Calibration {
+        Calibration {
+            low: 60, 
+            high: 120,
+            high_level: Length::in_inches(6.0),
+            tank_surface_area: Area::in_sq_inches(100.0),
+            pump_rate: FlowRate::in_gph(5.0),
+            tank_volume: Volume::in_gallons(10.0),
+        }
+    }
+
+    #[test]
+    pub fn pump_runs_until_high_point() {
+        let (relay, sink) = (MemoryRelay::new(), Sink::new());
+        let mut pump = MemoryRelay::new();
+        let mut controller = AtoController::new(400, 410, calibration(), Box::new(relay.clone()), sink.stream());
+
+        sink.send(395);
+        controller.tick(5, &mut pump).unwrap();
+        assert!(relay.is_on());
+        assert!(controller.status());
+
+        sink.send(410);
+        controller.tick(10, &mut pump).unwrap();
+        assert!(!relay.is_on());
+        assert!(!controller.status());
+    }
+
+    #[test]
+    pub fn pump_turns_off_when_depth_goes_stale() {
+        let (relay, sink) = (MemoryRelay::new(), Sink::new());
+        let mut pump = MemoryRelay::new();
+        let mut controller = AtoController::new(400, 410, calibration(), Box::new(relay.clone()), sink.stream());
+
+        sink.send(395);
+        controller.tick(5, &mut pump).unwrap();
+        assert!(relay.is_on());
+
+        controller.tick(5 + STALE_AFTER_S + 5, &mut pump).unwrap();
+        assert!(!relay.is_on());
+        assert!(!controller.status());
+        assert_eq!(controller.alerts().len(), 1);
+    }
+
+    #[test]
+    pub fn pump_stays_off_when_far_below_low_point() {
+        let (relay, sink) = (MemoryRelay::new(), Sink::new());
+        let mut pump = MemoryRelay::new();
+        let mut controller = AtoController::new(400, 410, calibration(), Box::new(relay.clone()), sink.stream());
+
+        sink.send(350);
+        controller.tick(5, &mut pump).unwrap();
+        assert!(!relay.is_on());
+    }
+
+    #[test]
+    pub fn runs_are_counted_against_the_reservoir() {
+        let (relay, sink) = (MemoryRelay::new(), Sink::new());
+        let mut pump = MemoryRelay::new();
+        let mut controller = AtoController::new(400, 410, calibration(), Box::new(relay.clone()), sink.stream());
+        controller.reservoir().set_capacity(Some(Volume::in_gallons(5.0)), 0);
+
+        sink.send(395);
+        controller.tick(5, &mut pump).unwrap();
+        sink.send(410);
+        // 5 gph for 12 minutes
+        controller.tick(5 + 12 * 60, &mut pump).unwrap();
+
+        let status = controller.reservoir_status();
+        assert!((status.last_run.unwrap().gallons() - 1.0).abs() < 0.001);
+        assert!((status.remaining.unwrap().gallons() - 4.0).abs() < 0.001);
+    }
+
+    #[test]
+    pub fn daily_cap_locks_the_pump_out() {
+        let (relay, sink) = (MemoryRelay::new(), Sink::new());
+        let mut pump = MemoryRelay::new();
+        let mut controller = AtoController::new(400, 410, calibration(), Box::new(relay.clone()), sink.stream());
+        controller.set_daily_cap(Some(Volume::in_gallons(0.25)));
+
+        sink.send(395);
+        controller.tick(5, &mut pump).unwrap();
+        assert!(relay.is_on());
+        // 5 gph pumps a quarter gallon in 3 minutes
+        sink.send(398);
+        controller.tick(5 + 200, &mut pump).unwrap();
+        assert!(!relay.is_on());
+        assert_eq!(controller.alerts().len(), 1);
+
+        sink.send(395);
+        controller.tick(10 + 200, &mut pump).unwrap();
+        assert!(!relay.is_on());
+        assert_eq!(controller.state(), AtoState::FailedSafe);
+
+        controller.reset();
+        controller.tick(15 + 200, &mut pump).unwrap();
+        assert!(relay.is_on());
+    }
+
+    #[test]
+    pub fn fast_drop_between_fills_locks_the_pump_out() {
+        let (relay, sink) = (MemoryRelay::new(), Sink::new());
+        let mut pump = MemoryRelay::new();
+        let mut controller = AtoController::new(400, 410, calibration(), Box::new(relay.clone()), sink.stream());
+
+        sink.send(395);
+        controller.tick(5, &mut pump).unwrap();
+        sink.send(410);
+        controller.tick(10, &mut pump).unwrap();
+        assert!(!relay.is_on());
+
+        // 15 steps, 1.5 inches, in an hour is far more than evaporation
+        sink.send(395);
+        controller.tick(10 + 60 * 60, &mut pump).unwrap();
+        assert!(!relay.is_on());
+        assert_eq!(controller.state(), AtoState::FailedSafe);
+        assert_eq!(controller.alerts().len(), 1);
+
+        controller.reset();
+        controller.tick(15 + 60 * 60, &mut pump).unwrap();
+        assert!(relay.is_on());
+        assert!(controller.alerts().is_empty());
+    }
+
+    #[test]
+    pub fn timing_out_alerts_once_until_reset() {
+        let (relay, sink) = (MemoryRelay::new(), Sink::new());
+        let mut pump = MemoryRelay::new();
+        let mut controller = AtoController::new(400, 410, calibration(), Box::new(relay.clone()), sink.stream());
+
+        sink.send(395);
+        controller.tick(5, &mut pump).unwrap();
+        assert_eq!(controller.state(), AtoState::Filling);
+        assert!(controller.take_new_alerts().is_empty());
+
+        // a fill from 400 to 410 should take about 5 minutes
+        sink.send(405);
+        controller.tick(5 + 10 * 60, &mut pump).unwrap();
+        assert!(!relay.is_on());
+        assert_eq!(controller.state(), AtoState::TimedOut);
+        assert_eq!(controller.take_new_alerts().len(), 1);
+
+        sink.send(395);
+        controller.tick(10 + 10 * 60, &mut pump).unwrap();
+        assert_eq!(controller.state(), AtoState::TimedOut);
+        assert!(controller.take_new_alerts().is_empty());
+
+        controller.reset();
+        controller.tick(15 + 10 * 60, &mut pump).unwrap();
+        assert!(relay.is_on());
+        assert_eq!(controller.state(), AtoState::Filling);
+    }
+
+    #[test]
+    pub fn high_float_stops_the_pump() {
+        let (relay, sink, float) = (MemoryRelay::new(), Sink::new(), Sink::new());
+        let mut pump = MemoryRelay::new();
+        let mut controller = AtoController::new(400, 410, calibration(), Box::new(relay.clone()), sink.stream());
+        controller.set_float_switches(Some(float.stream()), None);
+
+        sink.send(395);
+        float.send(Some(false));
+        controller.tick(5, &mut pump).unwrap();
+        assert!(relay.is_on());
+
+        float.send(Some(true));
+        assert!(controller.check_floats(7).unwrap());
+        assert!(!relay.is_on());
+        controller.tick(10, &mut pump).unwrap();
+        assert!(!relay.is_on());
+        assert!(controller.alerts().is_empty());
+    }
+
+    #[test]
+    pub fn empty_reservoir_stops_the_pump_and_alerts() {
+        let (relay, sink, float) = (MemoryRelay::new(), Sink::new(), Sink::new());
+        let mut pump = MemoryRelay::new();
+        let mut controller = AtoController::new(400, 410, calibration(), Box::new(relay.clone()), sink.stream());
+        controller.set_float_switches(None, Some(float.stream()));
+
+        sink.send(395);
+        float.send(Some(true));
+        controller.tick(5, &mut pump).unwrap();
+        assert!(!relay.is_on());
+        assert_eq!(controller.alerts().len(), 1);
+
+        float.send(Some(false));
+        controller.tick(10, &mut pump).unwrap();
+        assert!(relay.is_on());
+        assert!(controller.alerts().is_empty());
+    }
+
+    #[test]
+    pub fn unread_float_keeps_the_pump_off() {
+        let (relay, sink, float) = (MemoryRelay::new(), Sink::new(), Sink::new());
+        let mut pump = MemoryRelay::new();
+        let mut controller = AtoController::new(400, 410, calibration(), Box::new(relay.clone()), sink.stream());
+        controller.set_float_switches(Some(float.stream()), None);
+
+        sink.send(395);
+        controller.tick(5, &mut pump).unwrap();
+        assert!(!relay.is_on());
+        assert_eq!(controller.state(), AtoState::HighFloat);
+        assert_eq!(controller.alerts().len(), 1);
+
+        float.send(Some(false));
+        controller.tick(10, &mut pump).unwrap();
+        assert!(relay.is_on());
+        assert!(controller.alerts().is_empty());
+
+        // the input stopped reading
+        float.send(None);
+        assert!(controller.check_floats(12).unwrap());
+        assert!(!relay.is_on());
+        assert_eq!(controller.alerts().len(), 1);
+    }
+
+    #[test]
+    pub fn replayed_quick_drop_holds_the_pump_off() {
+        // the sensor reads 400 for 10 seconds then suddenly drops, e.g. a hand in the sump
+        let mut recording = String::new();
+        for i in 0..20 {
+            let depth = if i < 10 { 400 } else { 376 };
+            recording.push_str(&format!("{},2018-03-01T07:00:{:02}-05:00,depth,{}\n", i * 1000, i, depth));
+        }
+        let mut replay = Replay::from_reader(recording.as_bytes()).unwrap();
+        let relay = MemoryRelay::new();
+        let mut pump = MemoryRelay::new();
+        let mut controller = AtoController::new(390, 410, calibration(), Box::new(relay.clone()), replay.depth_stream());
+
+        for ticks in (0..20000).filter(|t| t % 10 == 0) {
+            replay.tick(ticks);
+            if ticks % 5000 == 0 {
+                controller.tick(ticks / 1000, &mut pump).unwrap();
+            }
+        }
+
+        assert!(replay.finished());
+        assert!(!relay.is_on());
+    }
 }
@@ -0,0 +1,144 @@
+use ::devices::Depth;
+use ::uom::length::Length;
+
+use std::io as io;
+use std::io::ErrorKind;
+
+// the worst a point can be off the fitted line before the sensor is considered non-linear
+fn max_error() -> Length { Length::in_inches(0.25) }
+
+/// The line through the captured points, in the terms of `Calibration`
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepthFit {
+    /// Reading at the reference mark
+    pub low: Depth,
+    /// Reading `high_level` above the reference mark
+    pub high: Depth,
+    pub high_level: Length,
+    pub steps_per_m: f32,
+    /// How far the worst point is off the line
+    pub max_error: Length,
+}
+
+/// Captures depth readings with the water at known marks. The reference mark is 0 and
+/// every other point is measured up from it.
+pub struct DepthCalibration {
+    points: Vec<(Length, Depth)>,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, message)
+}
+
+impl DepthCalibration {
+    pub fn new() -> DepthCalibration {
+        DepthCalibration { points: vec![] }
+    }
+
+    /// Records `depth` as the reading `mark` above the reference, replacing any earlier
+    /// point at the same mark
+    pub fn capture(&mut self, mark: Length, depth: Depth) -> io::Result<()> {
+        if !(mark.m() >= 0.0) {
+            return Err(invalid(format!("Calibration marks are measured up from the reference, not {:.1}cm", mark.cm())));
+        }
+        self.points.retain(|p| p.0 != mark);
+        self.points.push((mark, depth));
+        Ok(())
+    }
+
+    /// Captured (mark, reading) points
+    pub fn points(&self) -> &[(Length, Depth)] { &self.points }
+
+    /// Least squares line through the points so far
+    pub fn fit(&self) -> io::Result<DepthFit> {
+        if !self.points.iter().any(|p| p.0.m() == 0.0) {
+            return Err(invalid("Calibration needs a reading at the reference mark".to_string()));
+        }
+        if self.points.len() < 2 {
+            return Err(invalid("Calibration needs a reading above the reference mark".to_string()));
+        }
+
+        let n = self.points.len() as f32;
+        let mean_m = self.points.iter().fold(0.0, |a, p| a + p.0.m()) / n;
+        let mean_depth = self.points.iter().fold(0.0, |a, p| a + p.1 as f32) / n;
+        let covariance = self.points.iter().fold(0.0, |a, p| a + (p.0.m() - mean_m) * (p.1 as f32 - mean_depth));
+        let variance = self.points.iter().fold(0.0, |a, p| a + (p.0.m() - mean_m) * (p.0.m() - mean_m));
+        let steps_per_m = covariance / variance;
+        if !(steps_per_m > 0.0) {
+            return Err(invalid("Depth readings didn't rise with the water".to_string()));
+        }
+        let intercept = mean_depth - steps_per_m * mean_m;
+
+        let high_m = self.points.iter().fold(0.0, |a: f32, p| a.max(p.0.m()));
+        let max_error_m = self.points.iter()
+            .map(|p| (p.1 as f32 - (intercept + steps_per_m * p.0.m())).abs() / steps_per_m)
+            .fold(0.0, |a: f32, e| a.max(e));
+        Ok(DepthFit {
+            low: intercept.round().max(0.0) as Depth,
+            high: (intercept + steps_per_m * high_m).round() as Depth,
+            high_level: Length::in_m(high_m),
+            steps_per_m: steps_per_m,
+            max_error: Length::in_m(max_error_m),
+        })
+    }
+
+    /// The fit, if the sensor is linear enough to use it
+    pub fn finish(&self) -> io::Result<DepthFit> {
+        let fit = try!(self.fit());
+        if fit.max_error > max_error() {
+            return Err(invalid(format!("Depth sensor isn't linear, a point is {:.1}mm ({:.2} inches) off",
+                                       fit.max_error.mm(), fit.max_error.inches())));
+        }
+        Ok(fit)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fits_a_line_through_the_marks() {
+        let mut calibration = DepthCalibration::new();
+        assert!(calibration.finish().is_err());
+
+        calibration.capture(Length::in_inches(0.0), 400).unwrap();
+        assert!(calibration.finish().is_err());
+        calibration.capture(Length::in_inches(2.0), 480).unwrap();
+        calibration.capture(Length::in_inches(4.0), 561).unwrap();
+
+        let fit = calibration.finish().unwrap();
+        assert_eq!((fit.low, fit.high), (400, 561));
+        assert!((fit.high_level.inches() - 4.0).abs() < 0.001);
+        assert!((fit.steps_per_m * Length::in_inches(1.0).m() - 40.25).abs() < 0.01);
+        assert!(fit.max_error.inches() < 0.02);
+    }
+
+    #[test]
+    fn takes_metric_marks() {
+        let mut calibration = DepthCalibration::new();
+        calibration.capture(Length::in_cm(0.0), 400).unwrap();
+        calibration.capture(Length::in_cm(5.0), 480).unwrap();
+        calibration.capture(Length::in_cm(10.0), 561).unwrap();
+
+        let fit = calibration.finish().unwrap();
+        assert!((fit.high_level.cm() - 10.0).abs() < 0.001);
+        assert!((fit.steps_per_m / 100.0 - 16.1).abs() < 0.01);
+        assert!(calibration.capture(Length::in_cm(-1.0), 390).is_err());
+    }
+
+    #[test]
+    fn rejects_a_sensor_that_isnt_linear() {
+        let mut calibration = DepthCalibration::new();
+        calibration.capture(Length::in_inches(0.0), 400).unwrap();
+        calibration.capture(Length::in_inches(2.0), 480).unwrap();
+        calibration.capture(Length::in_inches(4.0), 500).unwrap();
+
+        assert!(calibration.fit().is_ok());
+        assert!(calibration.finish().is_err());
+
+        // recapturing a mark replaces it
+        calibration.capture(Length::in_inches(4.0), 560).unwrap();
+        assert!(calibration.finish().is_ok());
+    }
+}
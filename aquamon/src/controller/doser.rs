@@ -1,49 +1,49 @@
 use std::io as io;
-use ::devices::GpioPin;
+use ::devices::Relay;
+use ::uom::volume::Volume;
+use ::uom::flow::FlowRate;
 use chrono::prelude::*;
 use chrono::Duration;
 
 pub struct DoserController {
-    pin: GpioPin,
+    pin: Box<Relay>,
     stream: DoserStream,
 }
 
 struct DoserStream {
-    pump_rate_ml_min: f32,
+    pump_rate: FlowRate,
     schedule: Vec<Dose>,
 }
 
 #[derive(Debug)]
 pub struct Dose {
-    pub dose_amount_ml: f32,
+    pub dose_amount: Volume,
     pub start_time: NaiveTime,
 }
 
 impl DoserController {
-    pub fn new(pin: GpioPin, pump_rate_ml_min: f32) -> DoserController {
+    pub fn new(pin: Box<Relay>, pump_rate: FlowRate) -> DoserController {
         DoserController {
             pin: pin,
             stream: DoserStream {
-                pump_rate_ml_min: pump_rate_ml_min,
+                pump_rate: pump_rate,
                 schedule: vec![],
             }
         }
     }
 
-    pub fn set_settings(&mut self, pump_rate_ml_min: f32, schedule: Vec<Dose>) {
-        info!("Doser settings updated. Pump rate: {:?}, schedule: {:?}", pump_rate_ml_min, schedule);
+    pub fn set_settings(&mut self, pump_rate: FlowRate, schedule: Vec<Dose>) {
+        info!("Doser settings updated. Pump rate: {:?}mL/min, schedule: {:?}", pump_rate.ml_per_min(), schedule);
         self.stream.schedule = schedule;
-        self.stream.pump_rate_ml_min = pump_rate_ml_min;
+        self.stream.pump_rate = pump_rate;
     }
 
-    pub fn tick(&mut self, tick_ms: u64) -> io::Result<()> {
+    pub fn tick(&mut self, tick_ms: u64, time: NaiveTime) -> io::Result<()> {
         // only check every second
         if tick_ms % 1000 != 0 { return Ok(()); }
 
-        let time = UTC::now().with_timezone(&Local).time();
-
         if let Some(dose) = self.stream.tick(time) {
-            info!("Doser: dosing {:?}mL", dose.dose_amount_ml); 
+            info!("Doser: dosing {:?}mL", dose.dose_amount.ml()); 
             self.pin.turn_on()
         } else {
             self.pin.turn_off()
@@ -55,14 +55,13 @@ impl DoserStream {
     pub fn tick<'a>(&'a self, time: NaiveTime) -> Option<&'a Dose> {
         self.schedule
             .iter()
-            .find(|dose| dose.start_time <= time && dose.get_end_time(self.pump_rate_ml_min) >= time)
+            .find(|dose| dose.start_time <= time && dose.get_end_time(self.pump_rate) >= time)
     }
 }
 
 impl Dose {
-    fn get_end_time(&self, pump_rate_ml_min: f32) -> NaiveTime {
-        let ml_s = pump_rate_ml_min / 60.0;
-        let seconds = self.dose_amount_ml / ml_s;
+    fn get_end_time(&self, pump_rate: FlowRate) -> NaiveTime {
+        let seconds = pump_rate.seconds_for(self.dose_amount);
         self.start_time + Duration::seconds(seconds.round() as i64)
     }
 }
@@ -74,22 +73,22 @@ mod test {
     #[test]
     fn doser_start_end_time() {
         let dose = Dose {
-            dose_amount_ml: 0.4,
+            dose_amount: Volume::in_ml(0.4),
             start_time: NaiveTime::from_hms(7, 0, 0),
         };
 
-        assert_eq!(dose.get_end_time(1.1), NaiveTime::from_hms(7, 0, 22));
+        assert_eq!(dose.get_end_time(FlowRate::in_ml_per_min(1.1)), NaiveTime::from_hms(7, 0, 22));
     }
 
     #[test]
     fn doser_on() {
         let dose = Dose {
-            dose_amount_ml: 0.4,
+            dose_amount: Volume::in_ml(0.4),
             start_time: NaiveTime::from_hms(7, 0, 0),
         };
 
         let stream = DoserStream {
-            pump_rate_ml_min: 1.1,
+            pump_rate: FlowRate::in_ml_per_min(1.1),
             schedule: vec![dose]
         };
 
@@ -101,12 +100,12 @@ mod test {
     #[test]
     fn doser_off() {
         let dose = Dose {
-            dose_amount_ml: 0.4,
+            dose_amount: Volume::in_ml(0.4),
             start_time: NaiveTime::from_hms(7, 0, 0),
         };
 
         let stream = DoserStream {
-            pump_rate_ml_min: 1.1,
+            pump_rate: FlowRate::in_ml_per_min(1.1),
             schedule: vec![dose]
         };
 
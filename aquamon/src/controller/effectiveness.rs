@@ -0,0 +1,201 @@
+use ::uom::temp::*;
+
+/// Which way an output moves the temperature
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Heating,
+    Cooling,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    /// On for the whole check period without moving the temperature
+    Ineffective { minutes: u64 },
+    /// Off while the temperature moved the way the output moves it, about as fast as it does
+    StuckOn { change: f32, minutes: u64 },
+}
+
+// the least an output should move the temperature in a check period
+const MIN_CHANGE_F: f32 = 0.1;
+// off periods moving at this much of the learned rate look like the output is still on
+const STUCK_FRACTION: f32 = 0.75;
+// and have to move at least this far, so noise on a slow learned rate isn't a fault
+const STUCK_MIN_F: f32 = 0.5;
+// shorter runs are mostly the lag before the water responds
+const LEARN_MIN_S: u64 = 10 * 60;
+const LEARN_WEIGHT: f32 = 0.25;
+
+/// Learns how fast an output moves the temperature, and notices when it stops doing so
+/// while on or seems to keep doing so while off.
+pub struct Effectiveness {
+    direction: Direction,
+    check_s: u64,
+    on: bool,
+    window_start: Option<(u64, Temperature<F>)>,
+    // degrees F per hour in the output's direction
+    learned_rate: Option<f32>,
+    fault: Option<Fault>,
+}
+
+impl Effectiveness {
+    pub fn new(direction: Direction, check_s: u64) -> Effectiveness {
+        Effectiveness {
+            direction: direction,
+            check_s: check_s,
+            on: false,
+            window_start: None,
+            learned_rate: None,
+            fault: None,
+        }
+    }
+
+    pub fn fault(&self) -> Option<&Fault> { self.fault.as_ref() }
+
+    pub fn learned_rate(&self) -> Option<f32> { self.learned_rate }
+
+    pub fn set_check_s(&mut self, check_s: u64) {
+        self.check_s = check_s;
+    }
+
+    /// Starts watching over, e.g. after a gap in readings
+    pub fn restart(&mut self) {
+        self.window_start = None;
+    }
+
+    /// Clears a fault once the outlet has been looked at. What was learned is kept.
+    pub fn clear(&mut self) {
+        self.fault = None;
+        self.restart();
+    }
+
+    /// Returns a fault the first time it's seen. Faults stay until cleared.
+    pub fn update(&mut self, on: bool, temp: Temperature<F>, tick_s: u64) -> Option<Fault> {
+        if self.fault.is_some() {
+            return None;
+        }
+        let (start_s, start_temp) = match self.window_start {
+            Some(start) if on == self.on => start,
+            _ => {
+                // the tail of a run is still worth learning from if it was long enough
+                if let (Some((start_s, start_temp)), true, false) = (self.window_start, self.on, on) {
+                    self.learn(start_s, start_temp, tick_s, temp);
+                }
+                self.on = on;
+                self.window_start = Some((tick_s, temp));
+                return None;
+            },
+        };
+        if tick_s < start_s + self.check_s {
+            return None;
+        }
+        self.window_start = Some((tick_s, temp));
+
+        let minutes = (tick_s - start_s) / 60;
+        let change = self.towards(temp - start_temp);
+        let fault = if on {
+            if change < MIN_CHANGE_F {
+                Some(Fault::Ineffective { minutes: minutes })
+            } else {
+                self.learn(start_s, start_temp, tick_s, temp);
+                None
+            }
+        } else {
+            match self.learned_rate {
+                Some(rate) if change >= STUCK_MIN_F && change >= rate * STUCK_FRACTION * (tick_s - start_s) as f32 / 3600.0 =>
+                    Some(Fault::StuckOn { change: change, minutes: minutes }),
+                _ => None,
+            }
+        };
+        self.fault = fault.clone();
+        fault
+    }
+
+    /// Describes the fault for an alert
+    pub fn alert(&self, output: &str) -> Option<String> {
+        let (verb, moved) = match self.direction {
+            Direction::Heating => ("warming", "rose"),
+            Direction::Cooling => ("cooling", "fell"),
+        };
+        self.fault.as_ref().map(|fault| match *fault {
+            Fault::Ineffective { minutes } =>
+                format!("The {} was on for {} minutes without {} the tank and has been turned off", output, minutes, verb),
+            Fault::StuckOn { change, minutes } =>
+                format!("Temperature {} {:.1}F in {} minutes with the {} off. Its relay may be stuck, so it has been turned off", moved, change, minutes, output),
+        })
+    }
+
+    fn towards(&self, change: Temperature<F>) -> f32 {
+        match self.direction {
+            Direction::Heating => change.value(),
+            Direction::Cooling => -change.value(),
+        }
+    }
+
+    fn learn(&mut self, start_s: u64, start_temp: Temperature<F>, tick_s: u64, temp: Temperature<F>) {
+        if tick_s < start_s + LEARN_MIN_S {
+            return;
+        }
+        let rate = self.towards(temp - start_temp) * 3600.0 / (tick_s - start_s) as f32;
+        if rate <= 0.0 {
+            return;
+        }
+        self.learned_rate = Some(match self.learned_rate {
+            Some(learned) => learned + (rate - learned) * LEARN_WEIGHT,
+            None => rate,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp(f: f32) -> Temperature<F> {
+        Temperature::in_f(f)
+    }
+
+    #[test]
+    fn heater_that_doesnt_warm_is_a_fault() {
+        let mut heater = Effectiveness::new(Direction::Heating, 30 * 60);
+
+        heater.update(true, temp(77.0), 0);
+        assert_eq!(heater.update(true, temp(77.5), 30 * 60), None);
+        assert_eq!(heater.learned_rate(), Some(1.0));
+
+        assert_eq!(heater.update(true, temp(77.5), 60 * 60), Some(Fault::Ineffective { minutes: 30 }));
+        // reported once, but kept
+        assert_eq!(heater.update(true, temp(77.5), 90 * 60), None);
+        assert!(heater.alert("heater").is_some());
+
+        heater.clear();
+        assert!(heater.fault().is_none());
+    }
+
+    #[test]
+    fn warming_like_the_heater_with_it_off_is_a_stuck_relay() {
+        let mut heater = Effectiveness::new(Direction::Heating, 30 * 60);
+
+        // nothing to compare against until a run has been seen
+        heater.update(false, temp(77.0), 0);
+        assert_eq!(heater.update(false, temp(78.0), 30 * 60), None);
+
+        // a 20 minute run at 1.5F an hour
+        heater.update(true, temp(77.0), 30 * 60);
+        heater.update(false, temp(77.5), 50 * 60);
+        assert_eq!(heater.learned_rate(), Some(1.5));
+
+        // drifting up slowly is fine
+        assert_eq!(heater.update(false, temp(77.75), 80 * 60), None);
+        assert_eq!(heater.update(false, temp(78.75), 110 * 60), Some(Fault::StuckOn { change: 1.0, minutes: 30 }));
+    }
+
+    #[test]
+    fn chiller_learns_falling_temperatures() {
+        let mut chiller = Effectiveness::new(Direction::Cooling, 30 * 60);
+
+        chiller.update(true, temp(81.0), 0);
+        assert_eq!(chiller.update(true, temp(80.0), 30 * 60), None);
+        assert_eq!(chiller.learned_rate(), Some(2.0));
+        assert!(chiller.update(true, temp(80.05), 60 * 60).is_some());
+    }
+}
@@ -0,0 +1,200 @@
+use ::uom::volume::Volume;
+
+use carboxyl::Signal;
+use chrono::NaiveDate;
+
+// enough to see the seasons change
+const HISTORY_DAYS: usize = 90;
+// fewer days than this don't say anything about the weather
+const MIN_CORRELATION_DAYS: usize = 5;
+
+/// What the ATO topped off over a day, and the room it evaporated into
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EvaporationDay {
+    pub date: NaiveDate,
+    pub volume: Volume,
+    /// Means over the day, F and %
+    pub air_temp: f32,
+    pub humidity: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EvaporationStatus {
+    pub today: Volume,
+    pub average_per_day: Option<Volume>,
+    pub days: usize,
+    /// Pearson correlation of the daily volume with air temperature and humidity
+    pub air_temp_correlation: Option<f32>,
+    pub humidity_correlation: Option<f32>,
+}
+
+struct Today {
+    date: NaiveDate,
+    pumped_at_start: Volume,
+    air_temp_total: f32,
+    humidity_total: f32,
+    samples: u32,
+    // the day the controller started on is only partly seen, even after a short restart, as
+    // what was pumped before it isn't saved
+    partial: bool,
+}
+
+/// Daily ATO volumes, which is to say evaporation, alongside the air temperature and humidity
+pub struct Evaporation {
+    air_temp: Signal<f32>,
+    humidity: Signal<f32>,
+    days: Vec<EvaporationDay>,
+    finished: Vec<EvaporationDay>,
+    today: Option<Today>,
+    pumped: Volume,
+}
+
+impl Evaporation {
+    pub fn new(air_temp: Signal<f32>, humidity: Signal<f32>) -> Evaporation {
+        Evaporation { air_temp: air_temp, humidity: humidity, days: vec![], finished: vec![], today: None, pumped: Volume::in_litres(0.0) }
+    }
+
+    /// Picks up days saved before a restart
+    pub fn restore(&mut self, mut days: Vec<EvaporationDay>) {
+        days.sort_by_key(|d| d.date);
+        let skip = days.len().saturating_sub(HISTORY_DAYS);
+        self.days = days.into_iter().skip(skip).collect();
+    }
+
+    /// `pumped` is everything the ATO has pumped so far
+    pub fn tick(&mut self, date: NaiveDate, pumped: Volume) {
+        self.pumped = pumped;
+        let starting = self.today.is_none();
+        if self.today.as_ref().map_or(false, |t| t.date != date) {
+            let today = self.today.take().unwrap();
+            if today.partial || today.samples == 0 {
+                info!("Not counting evaporation for {}, only part of it was seen", today.date);
+            } else {
+                let day = EvaporationDay {
+                    date: today.date,
+                    volume: pumped - today.pumped_at_start,
+                    air_temp: today.air_temp_total / today.samples as f32,
+                    humidity: today.humidity_total / today.samples as f32,
+                };
+                info!("Evaporated {:.2}L on {}", day.volume.litres(), day.date);
+                self.days.push(day);
+                if self.days.len() > HISTORY_DAYS {
+                    self.days.remove(0);
+                }
+                self.finished.push(day);
+            }
+        }
+        let today = self.today.get_or_insert(Today {
+            date: date,
+            pumped_at_start: pumped,
+            air_temp_total: 0.0,
+            humidity_total: 0.0,
+            samples: 0,
+            partial: starting,
+        });
+        today.air_temp_total += self.air_temp.sample();
+        today.humidity_total += self.humidity.sample();
+        today.samples += 1;
+    }
+
+    /// Days finished since the last call, to be saved
+    pub fn take_finished(&mut self) -> Vec<EvaporationDay> {
+        self.finished.drain(..).collect()
+    }
+
+    pub fn status(&self) -> EvaporationStatus {
+        let volumes: Vec<f32> = self.days.iter().map(|d| d.volume.litres()).collect();
+        let average = if volumes.is_empty() {
+            None
+        } else {
+            Some(Volume::in_litres(volumes.iter().sum::<f32>() / volumes.len() as f32))
+        };
+        let air_temps: Vec<f32> = self.days.iter().map(|d| d.air_temp).collect();
+        let humidities: Vec<f32> = self.days.iter().map(|d| d.humidity).collect();
+        EvaporationStatus {
+            today: self.today.as_ref().map_or(Volume::in_litres(0.0), |t| self.pumped - t.pumped_at_start),
+            average_per_day: average,
+            days: self.days.len(),
+            air_temp_correlation: correlation(&volumes, &air_temps),
+            humidity_correlation: correlation(&volumes, &humidities),
+        }
+    }
+}
+
+// None without enough days or when either side never changes
+fn correlation(xs: &[f32], ys: &[f32]) -> Option<f32> {
+    if xs.len() < MIN_CORRELATION_DAYS {
+        return None;
+    }
+    let n = xs.len() as f32;
+    let (mean_x, mean_y) = (xs.iter().sum::<f32>() / n, ys.iter().sum::<f32>() / n);
+    let (mut cov, mut var_x, mut var_y) = (0.0, 0.0, 0.0);
+    for (x, y) in xs.iter().zip(ys.iter()) {
+        cov += (x - mean_x) * (y - mean_y);
+        var_x += (x - mean_x) * (x - mean_x);
+        var_y += (y - mean_y) * (y - mean_y);
+    }
+    if var_x <= 0.0 || var_y <= 0.0 {
+        None
+    } else {
+        Some(cov / (var_x * var_y).sqrt())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use carboxyl::Sink;
+
+    #[test]
+    fn records_whole_days() {
+        let (air_temp, humidity) = (Sink::new(), Sink::new());
+        let mut evaporation = Evaporation::new(air_temp.stream().hold(70.0), humidity.stream().hold(50.0));
+        let day = |d| NaiveDate::from_ymd(2018, 3, d);
+
+        evaporation.tick(day(1), Volume::in_litres(0.0));
+        evaporation.tick(day(2), Volume::in_litres(1.0));
+        assert!(evaporation.take_finished().is_empty());
+
+        air_temp.send(74.0);
+        evaporation.tick(day(2), Volume::in_litres(1.5));
+        evaporation.tick(day(3), Volume::in_litres(3.0));
+
+        let finished = evaporation.take_finished();
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].date, day(2));
+        assert!((finished[0].volume.litres() - 2.0).abs() < 0.001);
+        assert_eq!(finished[0].air_temp, 72.0);
+        assert!(evaporation.take_finished().is_empty());
+    }
+
+    #[test]
+    fn skips_the_day_it_restarted_on() {
+        let day = |d| NaiveDate::from_ymd(2018, 3, d);
+        let mut evaporation = Evaporation::new(Signal::new(70.0), Signal::new(50.0));
+        evaporation.restore(vec![EvaporationDay { date: day(1), volume: Volume::in_litres(2.0), air_temp: 70.0, humidity: 50.0 }]);
+
+        // restarted in the afternoon of the 2nd, with the morning's pumping lost
+        evaporation.tick(day(2), Volume::in_litres(0.0));
+        evaporation.tick(day(2), Volume::in_litres(0.5));
+        evaporation.tick(day(3), Volume::in_litres(0.7));
+        assert!(evaporation.take_finished().is_empty());
+        assert_eq!(evaporation.status().days, 1);
+    }
+
+    #[test]
+    fn correlates_with_the_weather() {
+        let mut evaporation = Evaporation::new(Signal::new(70.0), Signal::new(50.0));
+        evaporation.restore((1..8).map(|d| EvaporationDay {
+            date: NaiveDate::from_ymd(2018, 3, d),
+            volume: Volume::in_litres(d as f32),
+            air_temp: 70.0 + d as f32,
+            humidity: 60.0 - d as f32 * 2.0,
+        }).collect());
+
+        let status = evaporation.status();
+        assert_eq!(status.days, 7);
+        assert!(status.air_temp_correlation.unwrap() > 0.99);
+        assert!(status.humidity_correlation.unwrap() < -0.99);
+    }
+}
@@ -0,0 +1,53 @@
+use carboxyl::{Signal, Stream};
+
+/// Notices when a sensor stream stops producing readings, so a controller isn't left
+/// acting on the last value it held forever.
+pub struct Freshness {
+    count: Signal<u64>,
+    last_count: u64,
+    last_reading_s: u64,
+    limit_s: u64,
+}
+
+impl Freshness {
+    /// Input is stale after `limit_s` seconds without a reading, including at startup
+    pub fn new<A: Clone + Send + Sync + 'static>(stream: &Stream<A>, limit_s: u64) -> Freshness {
+        Freshness {
+            count: stream.fold(0, |count, _| count + 1),
+            last_count: 0,
+            last_reading_s: 0,
+            limit_s: limit_s,
+        }
+    }
+
+    pub fn is_stale(&mut self, tick_s: u64) -> bool {
+        let count = self.count.sample();
+        if count != self.last_count {
+            self.last_count = count;
+            self.last_reading_s = tick_s;
+        }
+        tick_s > self.last_reading_s + self.limit_s
+    }
+
+    pub fn limit_s(&self) -> u64 { self.limit_s }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use carboxyl::Sink;
+
+    #[test]
+    fn stale_after_limit_without_readings() {
+        let sink = Sink::new();
+        let mut freshness = Freshness::new(&sink.stream(), 30);
+
+        assert!(!freshness.is_stale(30));
+        assert!(freshness.is_stale(35));
+
+        sink.send(400);
+        assert!(!freshness.is_stale(40));
+        assert!(!freshness.is_stale(70));
+        assert!(freshness.is_stale(75));
+    }
+}
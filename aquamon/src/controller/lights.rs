@@ -3,11 +3,9 @@ use super::schedule::{Schedule, ScheduleLeg, Intensities, interpolated_intensity
 use std::io as io;
 use std::cmp;
 
-use ::devices::GpioPin;
+use ::devices::{Relay, LightChannels};
 
 use chrono::prelude::*;
- 
-use ::devices::Devices;
 
 struct LiveMode {
     pub end_tick: u64,
@@ -25,7 +23,7 @@ impl LiveMode {
 pub struct LightController {
     schedule: Schedule,
     live_mode: LiveMode,
-    fuge_light_pin: GpioPin,
+    fuge_light_pin: Box<Relay>,
 }
 
 pub enum FadeSpeed {
@@ -36,7 +34,7 @@ pub enum FadeSpeed {
 }
 
 impl LightController {
-    pub fn new(schedule: Schedule, fuge_light_pin: GpioPin) -> LightController {
+    pub fn new(schedule: Schedule, fuge_light_pin: Box<Relay>) -> LightController {
         LightController {
             schedule: schedule,
             live_mode: LiveMode::new(0, ([0_u8; 6], [0_u8; 6]), 0, 0),
@@ -49,14 +47,10 @@ impl LightController {
         self.schedule = schedule;
     }
 
-    pub fn tick(&mut self, devices: &mut Devices, tick: u64) -> io::Result<()> {
+    pub fn tick(&mut self, devices: &mut LightChannels, tick: u64, time: NaiveTime) -> io::Result<()> {
         if self.live_mode.end_tick < tick && tick % 30 != 0 {
             return Ok(());
         }
-        let local_time = UTC::now().with_timezone(&Local);
-        // shift the current time because the scheule legs use a NaiveTime
-        let time = local_time.time();
-         
 
         let intensities = if self.live_mode.end_tick < tick {
             self.schedule.get_intensities(time)
@@ -77,10 +71,7 @@ impl LightController {
             .map_err(From::from)
     }
 
-    pub fn live_mode(&mut self, leg: ScheduleLeg, fade_speed: FadeSpeed, duration_ticks: u64, tick: u64){
-        let local_time = UTC::now().with_timezone(&Local);
-        // shift the current time because the scheule legs use a NaiveTime
-        let time = local_time.time();
+    pub fn live_mode(&mut self, leg: ScheduleLeg, fade_speed: FadeSpeed, duration_ticks: u64, tick: u64, time: NaiveTime) {
         let current_intensities = self.schedule.get_intensities(time);
 
         let fade_duration_ticks = match fade_speed {
@@ -92,13 +83,38 @@ impl LightController {
         self.live_mode = LiveMode::new(live_mode_end_tick, (current_intensities, leg.weighted_intensities()), fade_duration_ticks, tick);
     }
 
-    pub fn disable_live_mode(&mut self, tick: u64) {
-        let local_time = UTC::now().with_timezone(&Local);
-        // shift the current time because the scheule legs use a NaiveTime
-        let time = local_time.time();
-         
+    pub fn disable_live_mode(&mut self, tick: u64, time: NaiveTime) {
         let current_intensities = self.schedule.get_intensities(time);
 
         self.live_mode = LiveMode::new(tick + 20000, (self.live_mode.intensities.1, current_intensities), 20000, tick);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::devices::MemoryRelay;
+
+    struct RecordingChannels {
+        intensities: Vec<Intensities>,
+    }
+
+    impl LightChannels for RecordingChannels {
+        fn set_intensities(&mut self, values: &[u8; 6]) -> io::Result<()> {
+            self.intensities.push(*values);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn live_mode_sends_intensities_to_channels() {
+        let mut channels = RecordingChannels { intensities: vec![] };
+        let mut controller = LightController::new(Schedule::default(), Box::new(MemoryRelay::new()));
+        let leg = ScheduleLeg { intensity: 255, intensities: [10, 20, 30, 40, 50, 60], start_time: NaiveTime::from_hms(0, 0, 0) };
+
+        controller.live_mode(leg, FadeSpeed::DurationMS(0), 1000, 30, NaiveTime::from_hms(12, 0, 0));
+        controller.tick(&mut channels, 60, NaiveTime::from_hms(12, 0, 0)).unwrap();
+
+        assert_eq!(channels.intensities, vec![[10, 20, 30, 40, 50, 60]]);
+    }
+}
@@ -3,12 +3,21 @@ mod lights;
 mod temperature;
 mod ato;
 pub mod doser;
+mod outputs;
+mod freshness;
+mod pid;
+mod effectiveness;
+mod reservoir;
+mod evaporation;
+mod stages;
+mod depth_calibration;
 
 use ::uom::temp::*;
-use ::devices::Devices;
-use ::devices::PiGpio;
-use ::devices::GpioPin;
+use ::uom::flow::FlowRate;
+use ::uom::volume::Volume;
+use ::devices::{LightChannels, Relay};
 use ::devices::Depth;
+use ::clock::Clock;
 use std::io as io;
 
 use self::temperature::TemperatureController;
@@ -16,20 +25,31 @@ use self::lights::{LightController, FadeSpeed};
 use self::ato::AtoController;
 use self::schedule::{Schedule, ScheduleLeg};
 use self::doser::DoserController;
+use self::evaporation::Evaporation;
 
-use carboxyl::Stream;
+use carboxyl::{Signal, Stream};
+use chrono::{DateTime, Local, NaiveDate, NaiveTime};
 
-pub use self::ato::Calibration;
+pub use self::ato::{Calibration, AtoState};
+pub use self::depth_calibration::{DepthCalibration, DepthFit};
 pub use self::doser::Dose;
-pub use self::temperature::TemperatureRange;
+pub use self::temperature::{TemperatureRange, TemperaturePoint, CoolerLimits, StageStatus};
+pub use self::pid::{PidSettings, PidStatus};
+pub use self::stages::StageOffsets;
+pub use self::reservoir::ReservoirStatus;
+pub use self::evaporation::{EvaporationDay, EvaporationStatus};
+pub use self::freshness::Freshness;
+pub use self::outputs::{Outputs, OutletConfig, Role};
 
 pub struct AquariumController {
     light_controller: LightController,
     temp_controller: TemperatureController,
     ato_controller: AtoController,
     doser_controller: DoserController,
-    pump_pin: GpioPin,
+    evaporation: Option<Evaporation>,
+    pump_pin: Box<Relay>,
     pump_off_timeout_s: u64,
+    clock: Box<Clock>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -47,7 +67,16 @@ pub struct Alert {
 
 pub struct Status {
     pub heater_on: bool,
+    pub heater_pid: Option<PidStatus>,
+    // how fast each output is learned to move the temperature, F per hour
+    pub heater_rate: Option<f32>,
+    pub cooler_rate: Option<f32>,
+    pub temp_stages: Vec<StageStatus>,
     pub ato_pump_on: bool,
+    pub ato_state: AtoState,
+    pub ato_reservoir: ReservoirStatus,
+    // None until there's air temperature and humidity to go with it
+    pub ato_evaporation: Option<EvaporationStatus>,
     pub cooler_on: bool,
     pub pump_on: bool,
     pub alerts: Vec<Alert>,
@@ -57,23 +86,21 @@ const TICK_RESOLUTION_MS: u64 = 5000;
 const PUMP_TIMEOUT_S: u64 = 20 * 60;
 
 impl AquariumController {
-    pub fn new(schedule: Schedule, heater_range: TemperatureRange, cooler_range: TemperatureRange, depth_low: Depth, depth_high: Depth, depth_calibration: Calibration, temp_stream: Stream<Temperature<F>>, depth_stream: Stream<Depth>, pump_rate_ml_min: f32) -> AquariumController {
-        let mut pi_gpio = PiGpio::new();
-        let pin0 =  pi_gpio.take_pin(0, true).unwrap();
-        let pin1 = pi_gpio.take_pin(1, true).unwrap();
-        let pin2 =  pi_gpio.take_pin(2, true).unwrap();
-        let mut pin3 = pi_gpio.take_pin(3, true).unwrap();
-        let pin4 = pi_gpio.take_pin(4, false).unwrap();
-        let pin5 = pi_gpio.take_pin(5, false).unwrap();
-        // pin3.turn_on().unwrap();
-        pin3.turn_off().unwrap();
+    pub fn new(schedule: Schedule, heater_range: TemperatureRange, cooler_range: TemperatureRange, depth_low: Depth, depth_high: Depth, depth_calibration: Calibration, temp_stream: Stream<Temperature<F>>, depth_stream: Stream<Depth>, doser_pump_rate: FlowRate, outputs: Outputs, clock: Box<Clock>) -> AquariumController {
+        let mut pump_pin = outputs.return_pump;
+        // pump_pin.turn_on().unwrap();
+        pump_pin.turn_off().unwrap();
+        let mut temp_controller = TemperatureController::new(heater_range, cooler_range, outputs.heater, outputs.cooler, temp_stream);
+        temp_controller.set_stage_outlets(outputs.backup_heater, outputs.fan);
         AquariumController {
-            light_controller: LightController::new(schedule, pin4),
-            temp_controller: TemperatureController::new(heater_range, cooler_range, pin0, pin2, temp_stream),
-            ato_controller: AtoController::new(depth_low, depth_high, depth_calibration, pin1, depth_stream),
-            doser_controller: DoserController::new(pin5, pump_rate_ml_min),
-            pump_pin: pin3,
+            light_controller: LightController::new(schedule, outputs.fuge_light),
+            temp_controller: temp_controller,
+            ato_controller: AtoController::new(depth_low, depth_high, depth_calibration, outputs.ato, depth_stream),
+            doser_controller: DoserController::new(outputs.doser, doser_pump_rate),
+            evaporation: None,
+            pump_pin: pump_pin,
             pump_off_timeout_s: u64::max_value(),
+            clock: clock,
         }
     }
 
@@ -85,19 +112,95 @@ impl AquariumController {
         self.temp_controller.set_range(heater_range, cooler_range);
     }
 
+    pub fn set_cooler_limits(&mut self, limits: CoolerLimits) {
+        self.temp_controller.set_cooler_limits(limits);
+    }
+
+    pub fn set_temperature_fault_check(&mut self, check_s: u64) {
+        self.temp_controller.set_effectiveness_check_s(check_s);
+    }
+
+    /// Clears heater and cooler faults
+    pub fn reset_temperature_faults(&mut self) {
+        self.temp_controller.reset_faults();
+    }
+
+    pub fn set_stage_offsets(&mut self, offsets: StageOffsets) {
+        self.temp_controller.set_stage_offsets(offsets);
+    }
+
+    /// None for on/off heater control
+    pub fn set_heater_pid(&mut self, settings: Option<PidSettings>) {
+        self.temp_controller.set_pid(settings);
+    }
+
+    /// Float switches backing up the depth reading, see `AtoController::set_float_switches`
+    pub fn set_ato_floats(&mut self, high_float: Option<Stream<Option<bool>>>, reservoir_empty: Option<Stream<Option<bool>>>) {
+        self.ato_controller.set_float_switches(high_float, reservoir_empty);
+    }
+
+    /// None when there's no reservoir capacity to track against
+    pub fn set_reservoir(&mut self, capacity: Option<Volume>, alert_s: u64) {
+        self.ato_controller.reservoir().set_capacity(capacity, alert_s);
+    }
+
+    pub fn restore_reservoir(&mut self, remaining: Volume) {
+        self.ato_controller.reservoir().set_remaining(remaining);
+    }
+
+    pub fn reservoir_refilled(&mut self) {
+        self.ato_controller.reservoir().refilled();
+    }
+
+    /// Clears the ATO's timeout and failsafe lockouts
+    pub fn reset_ato(&mut self) {
+        self.ato_controller.reset();
+    }
+
+    /// Alerts raised once, as they happen, since the last call. `status` has the ones that
+    /// last.
+    pub fn take_new_alerts(&mut self) -> Vec<Alert> {
+        self.ato_controller.take_new_alerts().into_iter().map(|a| Alert { component: Component::Ato, message: a }).collect()
+    }
+
+    pub fn set_ato_daily_cap(&mut self, cap: Option<Volume>) {
+        self.ato_controller.set_daily_cap(cap);
+    }
+
+    /// Starts keeping daily evaporation against the air temperature (F) and humidity
+    pub fn set_ambient(&mut self, air_temp: Signal<f32>, humidity: Signal<f32>) {
+        self.evaporation = Some(Evaporation::new(air_temp, humidity));
+    }
+
+    pub fn restore_evaporation(&mut self, days: Vec<EvaporationDay>) {
+        if let Some(ref mut evaporation) = self.evaporation {
+            evaporation.restore(days);
+        }
+    }
+
+    /// Evaporation days finished since the last call
+    pub fn take_evaporation_days(&mut self) -> Vec<EvaporationDay> {
+        self.evaporation.as_mut().map_or(vec![], |e| e.take_finished())
+    }
+
     pub fn set_depth_settings(&mut self, low: Depth, high: Depth, calibration: Calibration) {
         self.ato_controller.set_settings(low, high, calibration)
     }
      
-    pub fn set_doser_settings(&mut self, pump_rate_ml_min: f32, schedule: Vec<Dose>) {
-        self.doser_controller.set_settings(pump_rate_ml_min, schedule);
+    pub fn set_doser_settings(&mut self, pump_rate: FlowRate, schedule: Vec<Dose>) {
+        self.doser_controller.set_settings(pump_rate, schedule);
     }
 
-    pub fn tick(&mut self, devices: &mut Devices, ticks: u64) -> Result<(), io::Error> {
-        try!(self.light_controller.tick(devices, ticks));
-        try!(self.doser_controller.tick(ticks));
+    pub fn tick(&mut self, devices: &mut LightChannels, ticks: u64) -> Result<(), io::Error> {
+        // sample the clock once so every controller sees the same time
+        let now = self.clock.now(ticks);
+        let time = now.time();
+        try!(self.light_controller.tick(devices, ticks, time));
+        try!(self.doser_controller.tick(ticks, time));
+        // floats are checked between controller runs so they stop the pump straight away
+        try!(self.ato_controller.check_floats(ticks / 1000));
         self.next_tick(ticks)
-            .map_or(Ok(()), |tick| self.run(tick))
+            .map_or(Ok(()), |tick| self.run(tick, time, now.naive_local().date()))
     }
 
     pub fn enable_pump(&mut self, enabled: bool, tick_ms: u64) -> io::Result<()> {
@@ -112,32 +215,50 @@ impl AquariumController {
     }
 
     pub fn set_viewing_mode(&mut self, enabled: bool, tick: u64, leg: ScheduleLeg) { 
+        let time = self.clock.now(tick).time();
         if enabled {
-            self.light_controller.live_mode(leg, FadeSpeed::Slow, 60 * 10 * 1000, tick)
+            self.light_controller.live_mode(leg, FadeSpeed::Slow, 60 * 10 * 1000, tick, time)
         } else { 
-            self.light_controller.disable_live_mode(tick)
+            self.light_controller.disable_live_mode(tick, time)
         }
     }
 
     pub fn live_mode(&mut self, tick: u64, leg: ScheduleLeg) {
-        self.light_controller.live_mode(leg, FadeSpeed::DurationMS(0), 30 * 1000, tick)
+        let time = self.clock.now(tick).time();
+        self.light_controller.live_mode(leg, FadeSpeed::DurationMS(0), 30 * 1000, tick, time)
+    }
+
+    pub fn now(&self, ticks: u64) -> DateTime<Local> {
+        self.clock.now(ticks)
     }
 
     pub fn status(&mut self) -> Status {
         let temp_status = self.temp_controller.status();
+        let mut alerts: Vec<Alert> = temp_status.alerts.into_iter().map(|a| Alert { component: Component::Temperature, message: a }).collect();
+        alerts.extend(self.ato_controller.alerts().into_iter().map(|a| Alert { component: Component::Ato, message: a }));
         Status {
             heater_on: temp_status.heater,
+            heater_pid: temp_status.pid,
+            heater_rate: temp_status.heater_rate,
+            cooler_rate: temp_status.cooler_rate,
+            temp_stages: temp_status.stages,
             cooler_on: temp_status.cooler,
             ato_pump_on: self.ato_controller.status(),
+            ato_state: self.ato_controller.state(),
+            ato_reservoir: self.ato_controller.reservoir_status(),
+            ato_evaporation: self.evaporation.as_ref().map(|e| e.status()),
             pump_on: self.pump_pin.status().unwrap_or(false),
-            alerts: temp_status.alerts.into_iter().map(|a| Alert { component: Component::Temperature, message: a }).collect(),
+            alerts: alerts,
         }
     }
 
     // Run loop for when a tick overflows
-    fn run(&mut self, tick_s: u64) -> io::Result<()> {
-        try!(self.temp_controller.tick(tick_s));
-        try!(self.ato_controller.tick(tick_s, &mut self.pump_pin));
+    fn run(&mut self, tick_s: u64, time: NaiveTime, date: NaiveDate) -> io::Result<()> {
+        try!(self.temp_controller.tick(tick_s, time));
+        try!(self.ato_controller.tick(tick_s, &mut *self.pump_pin));
+        if let Some(ref mut evaporation) = self.evaporation {
+            evaporation.tick(date, self.ato_controller.total_pumped());
+        }
         try!(self.check_pump(tick_s));
         Ok(())
     }
@@ -161,3 +282,57 @@ impl AquariumController {
 
 }
 
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::clock::TickClock;
+    use ::devices::MemoryRelay;
+    use ::uom::length::Length;
+    use ::uom::area::Area;
+    use ::uom::volume::Volume;
+    use carboxyl::Sink;
+    use chrono::prelude::*;
+
+    struct NoLights;
+
+    impl LightChannels for NoLights {
+        fn set_intensities(&mut self, _values: &[u8; 6]) -> io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn doser_runs_once_over_a_simulated_day() {
+        let doser = MemoryRelay::new();
+        let outputs = Outputs {
+            heater: Box::new(MemoryRelay::new()),
+            ato: Box::new(MemoryRelay::new()),
+            cooler: Box::new(MemoryRelay::new()),
+            return_pump: Box::new(MemoryRelay::new()),
+            fuge_light: Box::new(MemoryRelay::new()),
+            doser: Box::new(doser.clone()),
+            backup_heater: None,
+            fan: None,
+        };
+        let clock = TickClock::new(Local.ymd(2018, 3, 1).and_hms(0, 0, 0));
+        let (temp_sink, depth_sink) = (Sink::new(), Sink::new());
+        let calibration = Calibration {
+            low: 0, high: 4096, high_level: Length::in_inches(10.0), tank_surface_area: Area::in_sq_inches(170.0),
+            pump_rate: FlowRate::in_gph(50.0), tank_volume: Volume::in_gallons(10.0),
+        };
+        let mut controller = AquariumController::new(Schedule::default(),
+                                                     TemperatureRange::discrete(Temperature::in_f(78.0)),
+                                                     TemperatureRange::discrete(Temperature::in_f(80.0)),
+                                                     0, 1, calibration, temp_sink.stream(), depth_sink.stream(), FlowRate::in_ml_per_min(1.1),
+                                                     outputs, Box::new(clock));
+        controller.set_doser_settings(FlowRate::in_ml_per_min(1.1), vec![Dose { dose_amount: Volume::in_ml(1.1), start_time: NaiveTime::from_hms(7, 0, 0) }]);
+
+        let mut on_seconds = vec![];
+        for second in 0..24 * 60 * 60 {
+            controller.tick(&mut NoLights, second * 1000).unwrap();
+            if doser.is_on() { on_seconds.push(second); }
+        }
+
+        assert_eq!(on_seconds.len(), 61);
+        assert_eq!(on_seconds[0], 7 * 60 * 60);
+    }
+}
@@ -0,0 +1,222 @@
+use std::io as io;
+use std::io::ErrorKind;
+use std::str::FromStr;
+
+use ::devices::{Relay, RelayGroup};
+
+/// What a controller uses an outlet for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Heater,
+    Ato,
+    Cooler,
+    ReturnPump,
+    FugeLight,
+    Doser,
+    BackupHeater,
+    Fan,
+}
+
+const ROLES: [Role; 8] = [Role::Heater, Role::Ato, Role::Cooler, Role::ReturnPump, Role::FugeLight, Role::Doser, Role::BackupHeater, Role::Fan];
+
+impl Role {
+    /// Only heaters can be spread over several outlets, the rest need exactly one
+    fn allows_multiple(&self) -> bool {
+        *self == Role::Heater
+    }
+
+    /// Extra temperature stages are only used if an outlet is assigned to them
+    fn is_optional(&self) -> bool {
+        *self == Role::BackupHeater || *self == Role::Fan
+    }
+}
+
+impl FromStr for Role {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Role> {
+        match s {
+            "heater" => Ok(Role::Heater),
+            "ato" => Ok(Role::Ato),
+            "cooler" => Ok(Role::Cooler),
+            "returnPump" => Ok(Role::ReturnPump),
+            "fugeLight" => Ok(Role::FugeLight),
+            "doser" => Ok(Role::Doser),
+            "backupHeater" => Ok(Role::BackupHeater),
+            "fan" => Ok(Role::Fan),
+            _ => Err(invalid(format!("Unknown outlet role: {}", s))),
+        }
+    }
+}
+
+/// A named outlet on the relay board
+#[derive(Debug, Clone)]
+pub struct OutletConfig {
+    pub name: String,
+    pub gpio: u64,
+    pub active_low: bool,
+    pub role: Role,
+}
+
+/// The switched outputs each controller drives
+pub struct Outputs {
+    pub heater: Box<Relay>,
+    pub ato: Box<Relay>,
+    pub cooler: Box<Relay>,
+    pub return_pump: Box<Relay>,
+    pub fuge_light: Box<Relay>,
+    pub doser: Box<Relay>,
+    pub backup_heater: Option<Box<Relay>>,
+    pub fan: Option<Box<Relay>>,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, message)
+}
+
+/// Checks that names and GPIO lines are unique and every role has the outlets it needs
+pub fn validate(outlets: &[OutletConfig]) -> io::Result<()> {
+    for (i, outlet) in outlets.iter().enumerate() {
+        if outlet.name.is_empty() {
+            return Err(invalid(format!("Outlet on GPIO {} has no name", outlet.gpio)));
+        }
+        for other in &outlets[i + 1..] {
+            if other.name == outlet.name {
+                return Err(invalid(format!("Outlet name {} is used more than once", outlet.name)));
+            }
+            if other.gpio == outlet.gpio {
+                return Err(invalid(format!("Outlets {} and {} are both on GPIO {}", outlet.name, other.name, outlet.gpio)));
+            }
+        }
+    }
+
+    for role in ROLES.iter() {
+        let count = outlets.iter().filter(|o| o.role == *role).count();
+        if count == 0 && !role.is_optional() {
+            return Err(invalid(format!("No outlet is assigned to {:?}", role)));
+        }
+        if count > 1 && !role.allows_multiple() {
+            return Err(invalid(format!("{} outlets are assigned to {:?}, only one is allowed", count, role)));
+        }
+    }
+
+    Ok(())
+}
+
+impl Outputs {
+    /// Validates the outlets and opens each one with `open`
+    pub fn from_outlets<O>(outlets: &[OutletConfig], mut open: O) -> io::Result<Outputs>
+        where O: FnMut(&OutletConfig) -> io::Result<Box<Relay>> {
+        try!(validate(outlets));
+
+        let mut take = |role: Role| -> io::Result<Vec<Box<Relay>>> {
+            let mut relays = vec![];
+            for outlet in outlets.iter().filter(|o| o.role == role) {
+                info!("Outlet {} on GPIO {} is the {:?}", outlet.name, outlet.gpio, role);
+                relays.push(try!(open(outlet)));
+            }
+            Ok(relays)
+        };
+
+        Ok(Outputs {
+            heater: Box::new(RelayGroup::new(try!(take(Role::Heater)))),
+            ato: try!(take(Role::Ato)).remove(0),
+            cooler: try!(take(Role::Cooler)).remove(0),
+            return_pump: try!(take(Role::ReturnPump)).remove(0),
+            fuge_light: try!(take(Role::FugeLight)).remove(0),
+            doser: try!(take(Role::Doser)).remove(0),
+            backup_heater: try!(take(Role::BackupHeater)).pop(),
+            fan: try!(take(Role::Fan)).pop(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::devices::MemoryRelay;
+
+    fn outlet(name: &str, gpio: u64, role: Role) -> OutletConfig {
+        OutletConfig { name: name.to_string(), gpio: gpio, active_low: true, role: role }
+    }
+
+    fn outlets() -> Vec<OutletConfig> {
+        vec![
+            outlet("heater", 17, Role::Heater),
+            outlet("ato", 27, Role::Ato),
+            outlet("chiller", 22, Role::Cooler),
+            outlet("return", 5, Role::ReturnPump),
+            outlet("fuge", 6, Role::FugeLight),
+            outlet("doser", 13, Role::Doser),
+        ]
+    }
+
+    #[test]
+    fn parses_roles() {
+        assert_eq!("returnPump".parse::<Role>().unwrap(), Role::ReturnPump);
+        assert!("toaster".parse::<Role>().is_err());
+    }
+
+    #[test]
+    fn valid_outlets() {
+        assert!(validate(&outlets()).is_ok());
+    }
+
+    #[test]
+    fn duplicate_gpio_is_invalid() {
+        let mut outlets = outlets();
+        outlets[1].gpio = 17;
+        assert_eq!(validate(&outlets).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn duplicate_name_is_invalid() {
+        let mut outlets = outlets();
+        outlets[1].name = "heater".to_string();
+        assert!(validate(&outlets).is_err());
+    }
+
+    #[test]
+    fn missing_role_is_invalid() {
+        let mut outlets = outlets();
+        outlets.pop();
+        assert!(validate(&outlets).is_err());
+    }
+
+    #[test]
+    fn only_heaters_can_repeat() {
+        let mut outlets = outlets();
+        outlets.push(outlet("heater 2", 26, Role::Heater));
+        assert!(validate(&outlets).is_ok());
+
+        outlets.push(outlet("ato 2", 19, Role::Ato));
+        assert!(validate(&outlets).is_err());
+    }
+
+    #[test]
+    fn stage_outlets_are_optional() {
+        let mut outlets = outlets();
+        outlets.push(outlet("fan", 26, Role::Fan));
+        let outputs = Outputs::from_outlets(&outlets, |_| Ok(Box::new(MemoryRelay::new()) as Box<Relay>)).unwrap();
+
+        assert!(outputs.fan.is_some());
+        assert!(outputs.backup_heater.is_none());
+    }
+
+    #[test]
+    fn second_heater_switches_with_the_first() {
+        let mut outlets = outlets();
+        outlets.push(outlet("heater 2", 26, Role::Heater));
+        let relays: Vec<MemoryRelay> = outlets.iter().map(|_| MemoryRelay::new()).collect();
+
+        let mut outputs = Outputs::from_outlets(&outlets, |o| {
+            let index = outlets.iter().position(|x| x.gpio == o.gpio).unwrap();
+            Ok(Box::new(relays[index].clone()) as Box<Relay>)
+        }).unwrap();
+        outputs.heater.turn_on().unwrap();
+
+        assert!(relays[0].is_on());
+        assert!(relays[6].is_on());
+        assert!(!relays[1].is_on());
+    }
+}
@@ -0,0 +1,148 @@
+use ::uom::temp::*;
+
+/// Gains are per degree F, `ki` per degree F second and `kd` per degree F per second.
+/// The output is the fraction of each `window_s` the heater is on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PidSettings {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+    pub window_s: u64,
+}
+
+/// The terms from the last update, for tuning
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PidStatus {
+    pub setpoint: f32,
+    pub error: f32,
+    pub p: f32,
+    pub i: f32,
+    pub d: f32,
+    pub output: f32,
+}
+
+/// A PID loop driving a relay by time proportioning: within each window the relay is on
+/// for the first `output * window_s` seconds.
+pub struct Pid {
+    settings: PidSettings,
+    // kept already multiplied by ki so changing gains doesn't bump the output
+    integral: f32,
+    last: Option<(u64, Temperature<F>)>,
+    window_start_s: Option<u64>,
+    status: PidStatus,
+}
+
+fn clamp(value: f32) -> f32 {
+    value.max(0.0).min(1.0)
+}
+
+impl Pid {
+    pub fn new(settings: PidSettings) -> Pid {
+        Pid {
+            settings: settings,
+            integral: 0.0,
+            last: None,
+            window_start_s: None,
+            status: PidStatus::default(),
+        }
+    }
+
+    pub fn set_settings(&mut self, settings: PidSettings) {
+        self.settings = settings;
+    }
+
+    /// Forgets the history, for when the output has been forced off
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.last = None;
+        self.window_start_s = None;
+        self.status = PidStatus::default();
+    }
+
+    pub fn status(&self) -> PidStatus { self.status }
+
+    /// Updates the output and returns whether the relay should be on
+    pub fn tick(&mut self, setpoint: Temperature<F>, temp: Temperature<F>, tick_s: u64) -> bool {
+        let output = self.update(setpoint, temp, tick_s);
+
+        let window_s = self.settings.window_s.max(1);
+        let start = match self.window_start_s {
+            Some(start) if tick_s < start + window_s => start,
+            _ => tick_s,
+        };
+        self.window_start_s = Some(start);
+        ((tick_s - start) as f32) < output * window_s as f32
+    }
+
+    fn update(&mut self, setpoint: Temperature<F>, temp: Temperature<F>, tick_s: u64) -> f32 {
+        let error = (setpoint - temp).value();
+        let (dt, d) = match self.last {
+            // on the measurement rather than the error, so setpoint changes don't kick
+            Some((last_s, last_temp)) if tick_s > last_s => {
+                let dt = (tick_s - last_s) as f32;
+                (dt, -self.settings.kd * (temp - last_temp).value() / dt)
+            },
+            _ => (0.0, 0.0),
+        };
+        self.last = Some((tick_s, temp));
+
+        let p = self.settings.kp * error;
+        // anti-windup: stop integrating once the output is saturated in the same direction
+        let integral = self.integral + self.settings.ki * error * dt;
+        let unclamped = p + integral + d;
+        if (unclamped <= 1.0 || error < 0.0) && (unclamped >= 0.0 || error > 0.0) {
+            self.integral = integral;
+        }
+        self.integral = clamp(self.integral);
+
+        let output = clamp(p + self.integral + d);
+        self.status = PidStatus {
+            setpoint: setpoint.value(),
+            error: error,
+            p: p,
+            i: self.integral,
+            d: d,
+            output: output,
+        };
+        output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn settings() -> PidSettings {
+        PidSettings { kp: 0.5, ki: 0.001, kd: 0.0, window_s: 100 }
+    }
+
+    #[test]
+    fn time_proportions_the_output() {
+        let mut pid = Pid::new(PidSettings { ki: 0.0, ..settings() });
+
+        // 0.5F below is 25% of the window
+        let on: Vec<bool> = (0..20).map(|t| pid.tick(Temperature::in_f(78.0), Temperature::in_f(77.5), t * 5)).collect();
+        assert_eq!(on.iter().filter(|&&on| on).count(), 5);
+        assert!(on[0] && on[4] && !on[5]);
+        assert_eq!(pid.status().output, 0.25);
+
+        // and a new window starts
+        assert!(pid.tick(Temperature::in_f(78.0), Temperature::in_f(77.5), 100));
+    }
+
+    #[test]
+    fn integral_doesnt_wind_up_while_saturated() {
+        let mut pid = Pid::new(settings());
+
+        // far below the setpoint for hours, the output is pinned at 1
+        for t in 0..2000 {
+            pid.tick(Temperature::in_f(78.0), Temperature::in_f(74.0), t * 5);
+        }
+        assert_eq!(pid.status().output, 1.0);
+        assert_eq!(pid.status().i, 0.0);
+
+        // so it backs off as soon as the setpoint is reached
+        pid.tick(Temperature::in_f(78.0), Temperature::in_f(78.1), 10000);
+        assert!(pid.status().output < 0.01);
+    }
+}
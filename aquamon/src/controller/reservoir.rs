@@ -0,0 +1,144 @@
+use ::uom::volume::Volume;
+
+const DAY_S: u64 = 24 * 60 * 60;
+// too little history to project from before this
+const MIN_PROJECTION_S: u64 = 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReservoirStatus {
+    pub last_run: Option<Volume>,
+    pub last_day: Volume,
+    /// None when no capacity is configured
+    pub capacity: Option<Volume>,
+    pub remaining: Option<Volume>,
+    /// At the last day's usage, None until there's been some
+    pub hours_left: Option<f32>,
+}
+
+/// Estimates what the ATO has pumped, and so what's left in the reservoir, from how long the
+/// pump has run
+pub struct Reservoir {
+    capacity: Option<Volume>,
+    remaining: Volume,
+    alert_s: u64,
+    // (tick_s the run ended, volume) for the last day
+    runs: Vec<(u64, Volume)>,
+    last_run: Option<Volume>,
+    // everything pumped since starting
+    total: Volume,
+}
+
+impl Reservoir {
+    pub fn new() -> Reservoir {
+        Reservoir { capacity: None, remaining: Volume::in_litres(0.0), alert_s: 0, runs: vec![], last_run: None, total: Volume::in_litres(0.0) }
+    }
+
+    /// Alerts when it's projected to run dry within `alert_s`. A newly configured reservoir
+    /// is assumed to be full.
+    pub fn set_capacity(&mut self, capacity: Option<Volume>, alert_s: u64) {
+        if let Some(capacity) = capacity {
+            if self.capacity.is_none() || self.remaining > capacity {
+                self.remaining = capacity;
+            }
+        }
+        self.capacity = capacity;
+        self.alert_s = alert_s;
+    }
+
+    /// Picks up an estimate saved before a restart
+    pub fn set_remaining(&mut self, remaining: Volume) {
+        self.remaining = match self.capacity {
+            Some(capacity) if remaining > capacity => capacity,
+            _ => remaining,
+        };
+    }
+
+    pub fn refilled(&mut self) {
+        if let Some(capacity) = self.capacity {
+            info!("ATO reservoir refilled, {:.1}L", capacity.litres());
+            self.remaining = capacity;
+        }
+    }
+
+    pub fn pumped(&mut self, volume: Volume, tick_s: u64) {
+        info!("ATO pumped {:.2}L", volume.litres());
+        self.last_run = Some(volume);
+        self.runs.retain(|&(end, _)| end + DAY_S > tick_s);
+        self.runs.push((tick_s, volume));
+        self.total = self.total + volume;
+        self.remaining = if volume > self.remaining { Volume::in_litres(0.0) } else { self.remaining - volume };
+    }
+
+    pub fn total(&self) -> Volume { self.total }
+
+    /// Pumped over the 24 hours up to `tick_s`
+    pub fn last_day(&self, tick_s: u64) -> Volume {
+        self.pumped_since(tick_s.saturating_sub(DAY_S))
+    }
+
+    /// Pumped by runs that ended after `since_s`, as far back as a day
+    pub fn pumped_since(&self, since_s: u64) -> Volume {
+        self.runs.iter()
+            .filter(|&&(end, _)| end > since_s)
+            .fold(Volume::in_litres(0.0), |total, &(_, volume)| total + volume)
+    }
+
+    pub fn status(&self, tick_s: u64) -> ReservoirStatus {
+        let last_day = self.last_day(tick_s);
+        // ticks start with the process, so early on there's less than a day to go on
+        let window_s = if tick_s < DAY_S { tick_s } else { DAY_S };
+        let remaining = self.capacity.map(|_| self.remaining);
+        let hours_left = match remaining {
+            Some(remaining) if window_s >= MIN_PROJECTION_S && last_day.litres() > 0.0 =>
+                Some(remaining.litres() / last_day.litres() * window_s as f32 / 3600.0),
+            _ => None,
+        };
+        ReservoirStatus {
+            last_run: self.last_run,
+            last_day: last_day,
+            capacity: self.capacity,
+            remaining: remaining,
+            hours_left: hours_left,
+        }
+    }
+
+    pub fn alert(&self, tick_s: u64) -> Option<String> {
+        let status = self.status(tick_s);
+        match (status.remaining, status.hours_left) {
+            (Some(remaining), _) if remaining.litres() <= 0.0 =>
+                Some("ATO reservoir should be empty by now, refill it and mark it refilled".to_string()),
+            (_, Some(hours)) if hours * 3600.0 < self.alert_s as f32 =>
+                Some(format!("ATO reservoir is projected to run dry in about {:.0} hours", hours)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn projects_when_it_runs_dry() {
+        let mut reservoir = Reservoir::new();
+        reservoir.set_capacity(Some(Volume::in_litres(10.0)), 24 * 60 * 60);
+
+        // 1L every 6 hours for a day
+        for run in 1..5 {
+            reservoir.pumped(Volume::in_litres(1.0), run * 6 * 60 * 60);
+        }
+        let status = reservoir.status(DAY_S);
+        assert!((status.last_day.litres() - 4.0).abs() < 0.001);
+        assert!((status.remaining.unwrap().litres() - 6.0).abs() < 0.001);
+        assert!((status.hours_left.unwrap() - 36.0).abs() < 0.1);
+        assert!(reservoir.alert(DAY_S).is_none());
+
+        reservoir.pumped(Volume::in_litres(2.0), DAY_S + 60);
+        assert!((reservoir.status(DAY_S + 60).hours_left.unwrap() - 16.0).abs() < 0.1);
+        assert!(reservoir.alert(DAY_S + 60).is_some());
+
+        reservoir.refilled();
+        assert_eq!(reservoir.status(DAY_S + 60).remaining, Some(Volume::in_litres(10.0)));
+        assert!(reservoir.alert(DAY_S + 60).is_none());
+    }
+}
@@ -0,0 +1,81 @@
+use ::uom::temp::*;
+use ::devices::Relay;
+use super::effectiveness::Direction;
+
+use std::io as io;
+
+/// Where each stage switches, in F from the heater setpoint for heating stages and from the
+/// cooler setpoint for cooling stages
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StageOffsets {
+    pub heater: f32,
+    pub backup_heater: f32,
+    pub fan: f32,
+    pub cooler: f32,
+}
+
+impl Default for StageOffsets {
+    fn default() -> StageOffsets {
+        StageOffsets { heater: 0.0, backup_heater: -0.5, fan: -0.5, cooler: 0.0 }
+    }
+}
+
+/// An on/off output beyond the main heater and cooler, switched around its own threshold
+pub struct Stage {
+    name: &'static str,
+    direction: Direction,
+    relay: Box<Relay>,
+    on: bool,
+}
+
+impl Stage {
+    pub fn new(name: &'static str, direction: Direction, relay: Box<Relay>) -> Stage {
+        Stage { name: name, direction: direction, relay: relay, on: false }
+    }
+
+    pub fn name(&self) -> &'static str { self.name }
+
+    pub fn is_on(&self) -> bool { self.on }
+
+    /// Stays off while not `allowed`
+    pub fn tick(&mut self, temp: Temperature<F>, threshold: Temperature<F>, hysteresis: Temperature<F>, allowed: bool) -> io::Result<()> {
+        let wanted = match (self.direction, self.on) {
+            (Direction::Heating, true) => temp < threshold + hysteresis,
+            (Direction::Heating, false) => temp < threshold - hysteresis,
+            (Direction::Cooling, true) => temp > threshold - hysteresis,
+            (Direction::Cooling, false) => temp > threshold + hysteresis,
+        };
+        self.set(wanted && allowed)
+    }
+
+    pub fn set(&mut self, on: bool) -> io::Result<()> {
+        if on == self.on {
+            return Ok(());
+        }
+        info!("Turning {} {}", self.name, if on { "on" } else { "off" });
+        self.on = on;
+        if on { self.relay.turn_on() } else { self.relay.turn_off() }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::devices::MemoryRelay;
+
+    #[test]
+    fn switches_around_its_threshold() {
+        let relay = MemoryRelay::new();
+        let mut fan = Stage::new("fan", Direction::Cooling, Box::new(relay.clone()));
+        let (threshold, hysteresis) = (Temperature::in_f(79.5), Temperature::in_f(0.2));
+
+        fan.tick(Temperature::in_f(79.6), threshold, hysteresis, true).unwrap();
+        assert!(!relay.is_on());
+        fan.tick(Temperature::in_f(79.8), threshold, hysteresis, true).unwrap();
+        assert!(relay.is_on());
+        fan.tick(Temperature::in_f(79.4), threshold, hysteresis, true).unwrap();
+        assert!(relay.is_on());
+        fan.tick(Temperature::in_f(79.8), threshold, hysteresis, false).unwrap();
+        assert!(!relay.is_on() && !fan.is_on());
+    }
+}
@@ -1,6 +1,10 @@
 use ::uom::temp::*;
 use std::io as io;
-use ::devices::GpioPin; 
+use ::devices::Relay;
+use super::freshness::Freshness;
+use super::pid::{Pid, PidSettings, PidStatus};
+use super::effectiveness::{Effectiveness, Direction};
+use super::stages::{Stage, StageOffsets};
 use carboxyl::{Stream, Signal};
 use chrono::prelude::*;
 
@@ -8,19 +12,39 @@ pub struct TemperatureController {
     cooler_range: TemperatureRange,
     heater_range: TemperatureRange,
     hysteresis: Temperature<F>,
-    pin: GpioPin,
-    cool_pin: GpioPin,
+    pin: Box<Relay>,
+    cool_pin: Box<Relay>,
     on_tick_s: u64,
     cool_on_tick_s: u64,
+    cool_off_tick_s: Option<u64>,
+    cooler_limits: CoolerLimits,
+    // set when the cooler hits its max runtime, until it next reaches temperature
+    cooler_overran: bool,
     timed_out_tick_s: u64,
     temp_sink: Signal<Temperature<F>>,
+    freshness: Freshness,
+    stale: bool,
+    // heater runs off the PID loop when set, cooler is always on/off
+    pid: Option<Pid>,
+    heater_check: Effectiveness,
+    cooler_check: Effectiveness,
+    offsets: StageOffsets,
+    backup_heater: Option<Stage>,
+    fan: Option<Stage>,
 }
 
+/// A setpoint at a time of day
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TemperaturePoint {
+    pub time: NaiveTime,
+    pub temp: Temperature<F>,
+}
+
+/// Setpoints through the day. The temperature is interpolated between points, and from
+/// the last point of the day round to the first.
+#[derive(Debug, Clone)]
 pub struct TemperatureRange {
-    pub min: Temperature<F>,
-    pub min_time: NaiveTime,
-    pub max: Temperature<F>,
-    pub max_time: NaiveTime,
+    points: Vec<TemperaturePoint>,
 }
 
 const SECONDS_IN_DAY: u32 = 60 * 60 * 24;
@@ -34,50 +58,95 @@ fn sub_seconds_absolute(first: u32, second: u32) -> u32 {
 }
 
 impl TemperatureRange {
+    /// Needs at least one point. Points with the same time keep the last one given.
+    pub fn new(points: Vec<TemperaturePoint>) -> TemperatureRange {
+        assert!(!points.is_empty(), "A temperature range needs at least one point");
+        let mut sorted: Vec<TemperaturePoint> = vec![];
+        for point in points.into_iter() {
+            sorted.retain(|p| p.time != point.time);
+            sorted.push(point);
+        }
+        sorted.sort_by_key(|p| p.time);
+        TemperatureRange { points: sorted }
+    }
+
     pub fn discrete(temp: Temperature<F>) -> TemperatureRange {
-        TemperatureRange {
-            min: temp,
-            max: temp,
-            min_time: NaiveTime::from_hms(0,0,0),
-            max_time: NaiveTime::from_hms(0,0,0),
+        TemperatureRange::new(vec![TemperaturePoint { time: NaiveTime::from_hms(0,0,0), temp: temp }])
+    }
+
+    /// Rising from min at min_time to max at max_time and back down overnight
+    pub fn min_max(min: Temperature<F>, min_time: NaiveTime, max: Temperature<F>, max_time: NaiveTime) -> TemperatureRange {
+        if min_time == max_time {
+            return TemperatureRange::discrete(min);
         }
+        TemperatureRange::new(vec![
+            TemperaturePoint { time: min_time, temp: min },
+            TemperaturePoint { time: max_time, temp: max },
+        ])
     }
 
+    pub fn points(&self) -> &[TemperaturePoint] { &self.points }
+
     fn temperature_for_time(&self, time: NaiveTime) -> Temperature<F> {
-        let min_s = self.min_time.num_seconds_from_midnight();
-        let max_s = self.max_time.num_seconds_from_midnight();
         let now_s = time.num_seconds_from_midnight();
+        let next_pos = self.points.iter().position(|p| p.time > time).unwrap_or(0);
+        let prev_pos = if next_pos == 0 { self.points.len() - 1 } else { next_pos - 1 };
+        let (prev, next) = (&self.points[prev_pos], &self.points[next_pos]);
 
-        if max_s < min_s || max_s - min_s == 0 {
-            return self.min;
+        let interval_s = sub_seconds_absolute(next.time.num_seconds_from_midnight(), prev.time.num_seconds_from_midnight());
+        if interval_s == 0 {
+            return prev.temp;
         }
+        let percent_elapsed = sub_seconds_absolute(now_s, prev.time.num_seconds_from_midnight()) as f32 / interval_s as f32;
+        let interpolated = percent_elapsed * (next.temp - prev.temp).value();
+        Temperature::in_f(prev.temp.value() + (interpolated * 10.0).round() / 10.0)
+    }
+}
 
-        let diff_temp = self.max - self.min;
+/// Protects a chiller's compressor from short cycling and running flat out forever
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CoolerLimits {
+    pub min_on_s: u64,
+    pub min_off_s: u64,
+    pub max_on_s: u64,
+}
 
-        if now_s < min_s || now_s > max_s {
-            let percent_elapsed = sub_seconds_absolute(now_s, max_s) as f32 / sub_seconds_absolute(min_s, max_s) as f32;
-            let interpolated = percent_elapsed * diff_temp.value();
-            Temperature::in_f(self.max.value() - (interpolated * 10.0).round() / 10.0)
-        } else {
-            let percent_elapsed = (now_s- min_s) as f32 / (max_s - min_s) as f32;
-            let interpolated = percent_elapsed * diff_temp.value();
-            Temperature::in_f(self.min.value() + (interpolated * 10.0).round() / 10.0)
-        }
+impl Default for CoolerLimits {
+    fn default() -> CoolerLimits {
+        CoolerLimits { min_on_s: 3 * 60, min_off_s: 5 * 60, max_on_s: 4 * 60 * 60 }
     }
 }
 
+/// Whether a heating or cooling stage is on
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageStatus {
+    pub name: &'static str,
+    pub on: bool,
+    pub offset: f32,
+}
+
 pub struct Status {
     pub heater: bool, 
     pub cooler: bool,
+    pub pid: Option<PidStatus>,
+    // learned F per hour, None until a long enough run has been seen
+    pub heater_rate: Option<f32>,
+    pub cooler_rate: Option<f32>,
+    // every stage in order, heater first and cooler last
+    pub stages: Vec<StageStatus>,
     pub alerts: Vec<String>,
 }
 
 const HEATER_MAX_CYCLE_TIME: u64 = 24 * 60 * 60; // 12 hours * 60 minutes * 60 seconds
 const TIMEOUT_PERIOD: u64 = 10 * 60;
+// readings come every 6.5 seconds
+const STALE_AFTER_S: u64 = 2 * 60;
+// how long an output gets to move the temperature before it's considered faulty, unless
+// it's set
+const EFFECTIVENESS_CHECK_S: u64 = 30 * 60;
 
 impl TemperatureController {
-    pub fn new(heater_range: TemperatureRange, cooler_range: TemperatureRange, pin: GpioPin, cool_pin: GpioPin, temp_stream: Stream<Temperature<F>>) -> TemperatureController {
-        let temp = Temperature::in_f(80.0);
+    pub fn new(heater_range: TemperatureRange, cooler_range: TemperatureRange, pin: Box<Relay>, cool_pin: Box<Relay>, temp_stream: Stream<Temperature<F>>) -> TemperatureController {
         TemperatureController {
             heater_range: heater_range,
             cooler_range: cooler_range,
@@ -86,49 +155,129 @@ impl TemperatureController {
             cool_pin: cool_pin,
             on_tick_s: 0,
             cool_on_tick_s: 0,
+            cool_off_tick_s: None,
+            cooler_limits: CoolerLimits::default(),
+            cooler_overran: false,
             timed_out_tick_s: 0,
-            temp_sink: temp_stream.fold((temp, temp, temp, temp), |(b, c, d, _), a| (a, b, c, d))
-                                  .map(|(a,b,c,d)| Temperature::in_f((a + b + c + d).value() / 4.0))
+            // the stream is already filtered
+            temp_sink: temp_stream.hold(Temperature::in_f(80.0)),
+            freshness: Freshness::new(&temp_stream, STALE_AFTER_S),
+            stale: false,
+            pid: None,
+            heater_check: Effectiveness::new(Direction::Heating, EFFECTIVENESS_CHECK_S),
+            cooler_check: Effectiveness::new(Direction::Cooling, EFFECTIVENESS_CHECK_S),
+            offsets: StageOffsets::default(),
+            backup_heater: None,
+            fan: None,
         }
     }
 
+    /// A backup heater comes on below its offset if the heater can't keep up, a fan above
+    /// its offset before the cooler
+    pub fn set_stage_outlets(&mut self, backup_heater: Option<Box<Relay>>, fan: Option<Box<Relay>>) {
+        self.backup_heater = backup_heater.map(|relay| Stage::new("backupHeater", Direction::Heating, relay));
+        self.fan = fan.map(|relay| Stage::new("fan", Direction::Cooling, relay));
+    }
+
+    pub fn set_stage_offsets(&mut self, offsets: StageOffsets) {
+        self.offsets = offsets;
+    }
+
+    /// Switches the heater between PID and on/off control
+    pub fn set_pid(&mut self, settings: Option<PidSettings>) {
+        self.pid = match (settings, self.pid.take()) {
+            (Some(settings), Some(mut pid)) => {
+                pid.set_settings(settings);
+                Some(pid)
+            },
+            (settings, _) => settings.map(Pid::new),
+        };
+    }
+
+    pub fn set_cooler_limits(&mut self, limits: CoolerLimits) {
+        self.cooler_limits = limits;
+    }
+
+    /// How long the heater or cooler gets to move the temperature before it's a fault
+    pub fn set_effectiveness_check_s(&mut self, check_s: u64) {
+        self.heater_check.set_check_s(check_s);
+        self.cooler_check.set_check_s(check_s);
+    }
+
     pub fn set_range(&mut self, heater_range: TemperatureRange, cooler_range: TemperatureRange) {
         self.heater_range = heater_range;
         self.cooler_range = cooler_range;
     }
 
+    /// Clears heater and cooler faults once the outlets have been looked at
+    pub fn reset_faults(&mut self) {
+        info!("Clearing heater and cooler faults");
+        self.heater_check.clear();
+        self.cooler_check.clear();
+    }
+
     pub fn status(&mut self) -> Status { 
         let temp = self.temp_sink.sample();
+        let mut alerts = vec![];
+        if self.stale {
+            alerts.push(format!("No temperature reading for over {} seconds, heater and cooler are off", self.freshness.limit_s()));
+        } else if temp >= Temperature::in_f(84.0) {
+            alerts.push(format!("Temperature too high: {}", temp));
+        }
+        if self.cooler_overran {
+            alerts.push(format!("Cooler ran for over {} minutes without reaching temperature, resting it", self.cooler_limits.max_on_s / 60));
+        }
+        alerts.extend(self.heater_check.alert("heater"));
+        alerts.extend(self.cooler_check.alert("cooler"));
         Status {
             heater: self.on_tick_s > 0, 
             cooler: self.cool_on_tick_s > 0,
-            alerts: if temp >= Temperature::in_f(84.0) { vec![format!("Temperature too high: {}", temp)] } else { vec![] }
+            pid: self.pid.as_ref().map(|pid| pid.status()),
+            heater_rate: self.heater_check.learned_rate(),
+            cooler_rate: self.cooler_check.learned_rate(),
+            stages: self.stage_status(),
+            alerts: alerts,
         }
     }
 
-    pub fn tick(&mut self, tick_s: u64) -> io::Result<()> {
-        if self.timed_out_tick_s > 0 && self.timed_out_tick_s + TIMEOUT_PERIOD > tick_s {
-            return Ok(());
+    pub fn tick(&mut self, tick_s: u64, local_time: NaiveTime) -> io::Result<()> {
+        self.stale = self.freshness.is_stale(tick_s);
+        if self.stale {
+            return self.fail_safe(tick_s);
         }
         let temp = self.temp_sink.sample();
-        let local_time = UTC::now().with_timezone(&Local).time();
+        try!(self.check_effectiveness(tick_s, temp));
         let min = self.get_min(local_time);
         let max = self.get_max(local_time);
         info!("Calculated min: {:?} max: {:?}", min, max);
-         
+        if self.timed_out_tick_s == 0 || self.timed_out_tick_s + TIMEOUT_PERIOD <= tick_s {
+            let (heater_at, cooler_at) = (min + Temperature::in_f(self.offsets.heater), max + Temperature::in_f(self.offsets.cooler));
+            if self.pid.is_some() {
+                try!(self.tick_pid(tick_s, temp, heater_at, cooler_at));
+            } else {
+                try!(self.tick_on_off(tick_s, temp, heater_at, cooler_at));
+            }
+        }
+        self.tick_stages(temp, min, max)
+    }
+
+    fn tick_on_off(&mut self, tick_s: u64, temp: Temperature<F>, min: Temperature<F>, max: Temperature<F>) -> io::Result<()> {
         if temp > min + self.hysteresis && self.on_tick_s != 0 {
             info!("Toggling temp off: {:?}", temp.value());
             self.on_tick_s = 0;
             self.pin.turn_off()
-        } else if temp > max + self.hysteresis && self.on_tick_s == 0 && self.cool_on_tick_s == 0 {
+        } else if temp > max + self.hysteresis && self.on_tick_s == 0 && self.cool_on_tick_s == 0 && self.cooler_may_start(tick_s) {
             info!("Toggling cooling on: {:?}", temp.value());
-            self.cool_on_tick_s = tick_s;
-            self.cool_pin.turn_on()
-        } else if temp < max - self.hysteresis && self.cool_on_tick_s != 0 {
+            self.start_cooler(tick_s)
+        } else if temp < max - self.hysteresis && self.cool_on_tick_s != 0 && self.cooler_may_stop(tick_s) {
             info!("Toggling cooling off: {:?}", temp.value());
-            self.cool_on_tick_s = 0;
-            self.cool_pin.turn_off()
-        } else if temp < min - self.hysteresis && self.on_tick_s == 0 {
+            self.cooler_overran = false;
+            self.stop_cooler(tick_s)
+        } else if self.cooler_has_overrun(tick_s) {
+            warn!("Cooler ran for over {:?} seconds, resting it", self.cooler_limits.max_on_s);
+            self.cooler_overran = true;
+            self.stop_cooler(tick_s)
+        } else if temp < min - self.hysteresis && self.on_tick_s == 0 && self.cool_on_tick_s == 0 && self.heater_check.fault().is_none() {
             info!("Toggling temp on: {:?}", temp.value());
             self.on_tick_s = tick_s;
             self.pin.turn_on()
@@ -142,6 +291,127 @@ impl TemperatureController {
         }
     }
 
+    // holds the heater on min, cooling above max the same as on/off mode
+    fn tick_pid(&mut self, tick_s: u64, temp: Temperature<F>, min: Temperature<F>, max: Temperature<F>) -> io::Result<()> {
+        let heat = self.pid.as_mut().map_or(false, |pid| pid.tick(min, temp, tick_s))
+            && self.cool_on_tick_s == 0 && self.heater_check.fault().is_none();
+        if self.on_tick_s > 0 && tick_s - self.on_tick_s > HEATER_MAX_CYCLE_TIME {
+            info!("Heater timed out after {:?} minutes", HEATER_MAX_CYCLE_TIME);
+            self.on_tick_s = 0;
+            self.timed_out_tick_s = tick_s;
+            self.pid.as_mut().map(|pid| pid.reset());
+            return self.pin.turn_off();
+        }
+        if heat && self.on_tick_s == 0 {
+            info!("PID turning heater on: {:?}", temp.value());
+            self.on_tick_s = tick_s;
+            try!(self.pin.turn_on());
+        } else if !heat && self.on_tick_s != 0 {
+            info!("PID turning heater off: {:?}", temp.value());
+            self.on_tick_s = 0;
+            try!(self.pin.turn_off());
+        }
+
+        if temp > max + self.hysteresis && self.on_tick_s == 0 && self.cool_on_tick_s == 0 && self.cooler_may_start(tick_s) {
+            info!("Toggling cooling on: {:?}", temp.value());
+            self.start_cooler(tick_s)
+        } else if temp < max - self.hysteresis && self.cool_on_tick_s != 0 && self.cooler_may_stop(tick_s) {
+            info!("Toggling cooling off: {:?}", temp.value());
+            self.cooler_overran = false;
+            self.stop_cooler(tick_s)
+        } else if self.cooler_has_overrun(tick_s) {
+            warn!("Cooler ran for over {:?} seconds, resting it", self.cooler_limits.max_on_s);
+            self.cooler_overran = true;
+            self.stop_cooler(tick_s)
+        } else {
+            Ok(())
+        }
+    }
+
+    // the backup heater only helps a heater that's on, or has been cut for not warming
+    fn tick_stages(&mut self, temp: Temperature<F>, min: Temperature<F>, max: Temperature<F>) -> io::Result<()> {
+        let heater_struggling = self.on_tick_s != 0 || self.heater_check.fault().is_some();
+        let (offsets, hysteresis) = (self.offsets, self.hysteresis);
+        if let Some(ref mut backup_heater) = self.backup_heater {
+            try!(backup_heater.tick(temp, min + Temperature::in_f(offsets.backup_heater), hysteresis, heater_struggling));
+        }
+        if let Some(ref mut fan) = self.fan {
+            try!(fan.tick(temp, max + Temperature::in_f(offsets.fan), hysteresis, true));
+        }
+        Ok(())
+    }
+
+    fn stage_status(&self) -> Vec<StageStatus> {
+        let mut stages = vec![StageStatus { name: "heater", on: self.on_tick_s > 0, offset: self.offsets.heater }];
+        stages.extend(self.backup_heater.iter().map(|s| StageStatus { name: s.name(), on: s.is_on(), offset: self.offsets.backup_heater }));
+        stages.extend(self.fan.iter().map(|s| StageStatus { name: s.name(), on: s.is_on(), offset: self.offsets.fan }));
+        stages.push(StageStatus { name: "cooler", on: self.cool_on_tick_s > 0, offset: self.offsets.cooler });
+        stages
+    }
+
+    fn cooler_may_start(&self, tick_s: u64) -> bool {
+        self.cooler_check.fault().is_none() &&
+            self.cool_off_tick_s.map_or(true, |off_s| tick_s >= off_s + self.cooler_limits.min_off_s)
+    }
+
+    fn cooler_may_stop(&self, tick_s: u64) -> bool {
+        tick_s >= self.cool_on_tick_s + self.cooler_limits.min_on_s
+    }
+
+    fn cooler_has_overrun(&self, tick_s: u64) -> bool {
+        self.cool_on_tick_s != 0 && tick_s - self.cool_on_tick_s > self.cooler_limits.max_on_s
+    }
+
+    fn start_cooler(&mut self, tick_s: u64) -> io::Result<()> {
+        self.cool_on_tick_s = tick_s;
+        self.cool_pin.turn_on()
+    }
+
+    // the rest period starts whatever the reason, safety cut offs don't wait for the minimum run
+    fn stop_cooler(&mut self, tick_s: u64) -> io::Result<()> {
+        self.cool_on_tick_s = 0;
+        self.cool_off_tick_s = Some(tick_s);
+        self.cool_pin.turn_off()
+    }
+
+    // cuts an outlet that isn't doing what it's told
+    fn check_effectiveness(&mut self, tick_s: u64, temp: Temperature<F>) -> io::Result<()> {
+        if self.heater_check.update(self.on_tick_s != 0, temp, tick_s).is_some() {
+            warn!("Heater isn't working, turning it off: {:?}", self.heater_check.fault());
+            self.on_tick_s = 0;
+            self.pid.as_mut().map(|pid| pid.reset());
+            try!(self.pin.turn_off());
+        }
+        if self.cooler_check.update(self.cool_on_tick_s != 0, temp, tick_s).is_some() {
+            warn!("Cooler isn't working, turning it off: {:?}", self.cooler_check.fault());
+            try!(self.stop_cooler(tick_s));
+        }
+        Ok(())
+    }
+
+    // without a temperature neither heating nor cooling is safe
+    fn fail_safe(&mut self, tick_s: u64) -> io::Result<()> {
+        self.pid.as_mut().map(|pid| pid.reset());
+        self.heater_check.restart();
+        self.cooler_check.restart();
+        if let Some(ref mut backup_heater) = self.backup_heater {
+            try!(backup_heater.set(false));
+        }
+        if let Some(ref mut fan) = self.fan {
+            try!(fan.set(false));
+        }
+        if self.on_tick_s != 0 {
+            warn!("Temperature reading is stale, turning heater off");
+            self.on_tick_s = 0;
+            try!(self.pin.turn_off());
+        }
+        if self.cool_on_tick_s != 0 {
+            warn!("Temperature reading is stale, turning cooler off");
+            try!(self.stop_cooler(tick_s));
+        }
+        Ok(())
+    }
+
     fn get_min(&self, now: NaiveTime) -> Temperature<F> {
         self.heater_range.temperature_for_time(now)
     }
@@ -154,15 +424,172 @@ impl TemperatureController {
 #[cfg(test)]
 mod test {
     use super::*;
+    use ::devices::MemoryRelay;
+    use carboxyl::Sink;
+
+    fn controller(heater: &MemoryRelay, cooler: &MemoryRelay, sink: &Sink<Temperature<F>>) -> TemperatureController {
+        TemperatureController::new(TemperatureRange::discrete(Temperature::in_f(78.0)),
+                                   TemperatureRange::discrete(Temperature::in_f(80.0)),
+                                   Box::new(heater.clone()), Box::new(cooler.clone()), sink.stream())
+    }
+
+    fn send_temp(sink: &Sink<Temperature<F>>, temp: f32) {
+        sink.send(Temperature::in_f(temp));
+    }
+
+    #[test]
+    fn heater_turns_on_below_min_and_off_above() {
+        let (heater, cooler, sink) = (MemoryRelay::new(), MemoryRelay::new(), Sink::new());
+        let mut controller = controller(&heater, &cooler, &sink);
+
+        send_temp(&sink, 77.0);
+        controller.tick(5, NaiveTime::from_hms(12, 0, 0)).unwrap();
+        assert!(heater.is_on());
+        assert!(!cooler.is_on());
+        assert!(controller.status().heater);
+
+        send_temp(&sink, 78.5);
+        controller.tick(10, NaiveTime::from_hms(12, 0, 0)).unwrap();
+        assert!(!heater.is_on());
+    }
+
+    #[test]
+    fn heater_turns_off_when_readings_stop() {
+        let (heater, cooler, sink) = (MemoryRelay::new(), MemoryRelay::new(), Sink::new());
+        let mut controller = controller(&heater, &cooler, &sink);
+
+        send_temp(&sink, 77.0);
+        controller.tick(5, NaiveTime::from_hms(12, 0, 0)).unwrap();
+        assert!(heater.is_on());
+
+        controller.tick(5 + STALE_AFTER_S + 5, NaiveTime::from_hms(12, 0, 0)).unwrap();
+        assert!(!heater.is_on());
+        assert_eq!(controller.status().alerts.len(), 1);
+
+        // and picks back up when they return
+        send_temp(&sink, 77.0);
+        controller.tick(5 + STALE_AFTER_S + 10, NaiveTime::from_hms(12, 0, 0)).unwrap();
+        assert!(heater.is_on());
+        assert!(controller.status().alerts.is_empty());
+    }
+
+    #[test]
+    fn cooler_turns_on_above_max_and_off_below() {
+        let (heater, cooler, sink) = (MemoryRelay::new(), MemoryRelay::new(), Sink::new());
+        let mut controller = controller(&heater, &cooler, &sink);
+
+        send_temp(&sink, 81.0);
+        controller.tick(5, NaiveTime::from_hms(12, 0, 0)).unwrap();
+        assert!(cooler.is_on());
+        assert!(!heater.is_on());
+
+        // after its minimum run
+        send_temp(&sink, 79.5);
+        controller.tick(5 + CoolerLimits::default().min_on_s, NaiveTime::from_hms(12, 0, 0)).unwrap();
+        assert!(!cooler.is_on());
+    }
+
+    #[test]
+    fn cooler_keeps_to_its_run_and_rest_times() {
+        let (heater, cooler, sink) = (MemoryRelay::new(), MemoryRelay::new(), Sink::new());
+        let mut controller = controller(&heater, &cooler, &sink);
+        controller.set_cooler_limits(CoolerLimits { min_on_s: 60, min_off_s: 120, max_on_s: 600 });
+
+        send_temp(&sink, 81.0);
+        controller.tick(5, NaiveTime::from_hms(12, 0, 0)).unwrap();
+        assert!(cooler.is_on());
+
+        // cooled off quickly but has to finish its minimum run
+        send_temp(&sink, 79.5);
+        controller.tick(30, NaiveTime::from_hms(12, 0, 0)).unwrap();
+        assert!(cooler.is_on());
+        controller.tick(65, NaiveTime::from_hms(12, 0, 0)).unwrap();
+        assert!(!cooler.is_on());
+
+        // then rests before starting again
+        send_temp(&sink, 81.0);
+        controller.tick(120, NaiveTime::from_hms(12, 0, 0)).unwrap();
+        assert!(!cooler.is_on());
+        controller.tick(185, NaiveTime::from_hms(12, 0, 0)).unwrap();
+        assert!(cooler.is_on());
+
+        // and never runs past its max
+        controller.tick(790, NaiveTime::from_hms(12, 0, 0)).unwrap();
+        assert!(!cooler.is_on());
+        assert_eq!(controller.status().alerts.len(), 1);
+    }
+
+    #[test]
+    fn backup_heater_helps_when_the_heater_cant_keep_up() {
+        let (heater, cooler, sink) = (MemoryRelay::new(), MemoryRelay::new(), Sink::new());
+        let (backup, fan) = (MemoryRelay::new(), MemoryRelay::new());
+        let mut controller = controller(&heater, &cooler, &sink);
+        controller.set_stage_outlets(Some(Box::new(backup.clone())), Some(Box::new(fan.clone())));
+
+        send_temp(&sink, 77.7);
+        controller.tick(5, NaiveTime::from_hms(12, 0, 0)).unwrap();
+        assert!(heater.is_on() && !backup.is_on());
+
+        send_temp(&sink, 77.2);
+        controller.tick(10, NaiveTime::from_hms(12, 0, 0)).unwrap();
+        assert!(heater.is_on() && backup.is_on());
+        let stages: Vec<&str> = controller.status().stages.iter().filter(|s| s.on).map(|s| s.name).collect();
+        assert_eq!(stages, vec!["heater", "backupHeater"]);
+
+        // the fan comes on before the cooler
+        send_temp(&sink, 79.8);
+        controller.tick(15, NaiveTime::from_hms(12, 0, 0)).unwrap();
+        assert!(!backup.is_on() && fan.is_on() && !cooler.is_on());
+    }
+
+    #[test]
+    fn heater_is_cut_when_it_stops_warming() {
+        let (heater, cooler, sink) = (MemoryRelay::new(), MemoryRelay::new(), Sink::new());
+        let mut controller = controller(&heater, &cooler, &sink);
+
+        let mut tick_s = 0;
+        while tick_s <= EFFECTIVENESS_CHECK_S + 5 {
+            tick_s += 5;
+            send_temp(&sink, 77.0);
+            controller.tick(tick_s, NaiveTime::from_hms(12, 0, 0)).unwrap();
+        }
+        assert!(!heater.is_on());
+        assert_eq!(controller.status().alerts.len(), 1);
+
+        // and stays off until it's reset, saving the settings isn't enough
+        controller.set_range(TemperatureRange::discrete(Temperature::in_f(78.0)), TemperatureRange::discrete(Temperature::in_f(80.0)));
+        controller.tick(tick_s + 5, NaiveTime::from_hms(12, 0, 0)).unwrap();
+        assert!(!heater.is_on());
+        controller.reset_faults();
+        controller.tick(tick_s + 10, NaiveTime::from_hms(12, 0, 0)).unwrap();
+        assert!(heater.is_on());
+    }
+
+    #[test]
+    fn pid_mode_cycles_the_heater_within_a_window() {
+        let (heater, cooler, sink) = (MemoryRelay::new(), MemoryRelay::new(), Sink::new());
+        let mut controller = controller(&heater, &cooler, &sink);
+        controller.set_pid(Some(PidSettings { kp: 0.5, ki: 0.0, kd: 0.0, window_s: 100 }));
+
+        // 0.25F low is on for 12.5% of the window
+        send_temp(&sink, 77.75);
+        controller.tick(5, NaiveTime::from_hms(12, 0, 0)).unwrap();
+        assert!(heater.is_on());
+        assert_eq!(controller.status().pid.unwrap().output, 0.125);
+        controller.tick(10, NaiveTime::from_hms(12, 0, 0)).unwrap();
+        assert!(heater.is_on());
+        controller.tick(20, NaiveTime::from_hms(12, 0, 0)).unwrap();
+        assert!(!heater.is_on());
+        controller.tick(105, NaiveTime::from_hms(12, 0, 0)).unwrap();
+        assert!(heater.is_on());
+
+        controller.set_pid(None);
+        assert!(controller.status().pid.is_none());
+    }
 
     #[test]
     fn temperature_between_times() {
-        let range = TemperatureRange {
-            min: Temperature::in_f(77.0),
-            min_time: NaiveTime::from_hms(10, 0, 0),
-            max: Temperature::in_f(79.0),
-            max_time: NaiveTime::from_hms(15, 0, 0),
-        };
+        let range = TemperatureRange::min_max(Temperature::in_f(77.0), NaiveTime::from_hms(10, 0, 0), Temperature::in_f(79.0), NaiveTime::from_hms(15, 0, 0));
 
         assert_eq!(
             range.temperature_for_time(NaiveTime::from_hms(12, 40, 0)),
@@ -171,12 +598,7 @@ mod test {
 
     #[test]
     fn temperature_before_times() {
-        let range = TemperatureRange {
-            min: Temperature::in_f(77.0),
-            min_time: NaiveTime::from_hms(10, 0, 0),
-            max: Temperature::in_f(79.0),
-            max_time: NaiveTime::from_hms(15, 0, 0),
-        };
+        let range = TemperatureRange::min_max(Temperature::in_f(77.0), NaiveTime::from_hms(10, 0, 0), Temperature::in_f(79.0), NaiveTime::from_hms(15, 0, 0));
 
         assert_eq!(
             range.temperature_for_time(NaiveTime::from_hms(9, 15, 0)),
@@ -185,12 +607,7 @@ mod test {
 
     #[test]
     fn temperature_after_times() {
-        let range = TemperatureRange {
-            min: Temperature::in_f(77.0),
-            min_time: NaiveTime::from_hms(10, 0, 0),
-            max: Temperature::in_f(79.0),
-            max_time: NaiveTime::from_hms(15, 0, 0),
-        };
+        let range = TemperatureRange::min_max(Temperature::in_f(77.0), NaiveTime::from_hms(10, 0, 0), Temperature::in_f(79.0), NaiveTime::from_hms(15, 0, 0));
 
         assert_eq!(
             range.temperature_for_time(NaiveTime::from_hms(19, 15, 0)),
@@ -199,12 +616,7 @@ mod test {
 
     #[test]
     fn temperature_at_time() {
-        let range = TemperatureRange {
-            min: Temperature::in_f(77.0),
-            min_time: NaiveTime::from_hms(10, 0, 0),
-            max: Temperature::in_f(79.0),
-            max_time: NaiveTime::from_hms(15, 0, 0),
-        };
+        let range = TemperatureRange::min_max(Temperature::in_f(77.0), NaiveTime::from_hms(10, 0, 0), Temperature::in_f(79.0), NaiveTime::from_hms(15, 0, 0));
 
         assert_eq!(
             range.temperature_for_time(NaiveTime::from_hms(10, 0, 0)),
@@ -215,14 +627,22 @@ mod test {
 
     }
 
+    #[test]
+    fn points_interpolate_across_midnight() {
+        let point = |h, temp| TemperaturePoint { time: NaiveTime::from_hms(h, 0, 0), temp: Temperature::in_f(temp) };
+        // given out of order, with a peak in the afternoon and a dip overnight
+        let range = TemperatureRange::new(vec![point(14, 79.0), point(2, 77.0), point(8, 78.0), point(20, 78.0)]);
+
+        assert_eq!(range.points()[0].time, NaiveTime::from_hms(2, 0, 0));
+        assert_eq!(range.temperature_for_time(NaiveTime::from_hms(11, 0, 0)), Temperature::in_f(78.5));
+        assert_eq!(range.temperature_for_time(NaiveTime::from_hms(23, 0, 0)), Temperature::in_f(77.5));
+        assert_eq!(range.temperature_for_time(NaiveTime::from_hms(1, 0, 0)), Temperature::in_f(77.2));
+        assert_eq!(range.temperature_for_time(NaiveTime::from_hms(2, 0, 0)), Temperature::in_f(77.0));
+    }
+
     #[test]
     fn zero_times() {
-        let range = TemperatureRange {
-            min: Temperature::in_f(77.0),
-            min_time: NaiveTime::from_hms(0, 0, 0),
-            max: Temperature::in_f(79.0),
-            max_time: NaiveTime::from_hms(0, 0, 0),
-        };
+        let range = TemperatureRange::min_max(Temperature::in_f(77.0), NaiveTime::from_hms(0, 0, 0), Temperature::in_f(79.0), NaiveTime::from_hms(0, 0, 0));
 
         assert_eq!(
             range.temperature_for_time(NaiveTime::from_hms(19, 15, 0)),
@@ -231,12 +651,7 @@ mod test {
 
     #[test]
     fn zero_min() {
-        let range = TemperatureRange {
-            min: Temperature::in_f(77.0),
-            min_time: NaiveTime::from_hms(0, 0, 0),
-            max: Temperature::in_f(79.0),
-            max_time: NaiveTime::from_hms(12, 0, 0),
-        };
+        let range = TemperatureRange::min_max(Temperature::in_f(77.0), NaiveTime::from_hms(0, 0, 0), Temperature::in_f(79.0), NaiveTime::from_hms(12, 0, 0));
 
         assert_eq!(
             range.temperature_for_time(NaiveTime::from_hms(6, 30, 0)),
@@ -3,32 +3,55 @@ use ::uom::temp::*;
 use std::io::ErrorKind;
 use std::io as io;
 
-use i2cdev::core::*;
 use i2cdev::linux::{LinuxI2CDevice};
 use super::Depth;
+use super::i2c::I2CBus;
 
 use byteorder::{ByteOrder, BigEndian};
 
-pub struct AvrController {
-    device: LinuxI2CDevice,
-    depth_avg: [Depth; 10],
-    depth_index: usize,
+pub struct AvrController<B: I2CBus = LinuxI2CDevice> {
+    device: B,
 }
 
 const CONTROLLER_SLAVE_ADDR: u16 = 0x32;
-const AQ_CMD_SETCHANNELS: u8 = 0x11;
-const AQ_CMD_GET_TEMP: u8 = 0x12;
-const AQ_CMD_GET_DEPTH: u8 = 0x13;
-const AQ_CMD_GET_AIR_TEMP_HUMIDITY: u8 = 0x14;
+pub const AQ_CMD_SETCHANNELS: u8 = 0x11;
+pub const AQ_CMD_GET_TEMP: u8 = 0x12;
+pub const AQ_CMD_GET_DEPTH: u8 = 0x13;
+pub const AQ_CMD_GET_AIR_TEMP_HUMIDITY: u8 = 0x14;
+pub const AQ_CMD_GET_PROBE_ROM: u8 = 0x15;
+pub const AQ_CMD_GET_PROBE_TEMP: u8 = 0x16;
+
+/// The 64 bit ROM id burned into each DS18B20, family code first
+pub type ProbeRom = [u8; 8];
+
+/// Parses a ROM id written as 16 hex digits, the way `format_rom` prints it
+pub fn parse_rom(hex: &str) -> io::Result<ProbeRom> {
+    let invalid = || io::Error::new(ErrorKind::InvalidInput, format!("Probe ROM id must be 16 hex digits: {}", hex));
+    if hex.len() != 16 || !hex.is_ascii() { return Err(invalid()); }
+
+    let mut rom = [0_u8; 8];
+    for i in 0..8 {
+        rom[i] = try!(u8::from_str_radix(&hex[i*2..i*2 + 2], 16).map_err(|_| invalid()));
+    }
+    Ok(rom)
+}
+
+pub fn format_rom(rom: &ProbeRom) -> String {
+    rom.iter().map(|b| format!("{:02x}", b)).collect()
+}
 
 impl AvrController {
     pub fn new(i2c_device_id: u8) -> Result<AvrController, io::Error> {
         let device = try!(LinuxI2CDevice::new("/dev/i2c-".to_string() + &i2c_device_id.to_string(), CONTROLLER_SLAVE_ADDR));
-        Ok(AvrController { 
+        Ok(AvrController::with_bus(device))
+    }
+}
+
+impl<B: I2CBus> AvrController<B> {
+    pub fn with_bus(device: B) -> AvrController<B> {
+        AvrController { 
             device: device,
-            depth_avg: [0; 10],
-            depth_index: 0,
-        })
+        }
     }
 
     pub fn get_temp(&mut self) -> Result<Temperature<C>, io::Error> {
@@ -43,6 +66,44 @@ impl AvrController {
         Ok(Temperature::in_c(temp_c))
     }
 
+    /// ROM ids of the probes the AVR found on its 1-Wire bus at startup
+    pub fn list_probes(&mut self) -> io::Result<Vec<ProbeRom>> {
+        let mut roms = vec![];
+        let mut index = 0;
+        loop {
+            let mut reply = [0_u8; 10];
+            try!(self.device.write(&[AQ_CMD_GET_PROBE_ROM, index]));
+            try!(self.device.read(&mut reply));
+            try!(check_crc(&reply));
+
+            let count = reply[0];
+            if index >= count { return Ok(roms); }
+
+            let mut rom = [0_u8; 8];
+            rom.copy_from_slice(&reply[1..9]);
+            roms.push(rom);
+            index += 1;
+        }
+    }
+
+    pub fn get_probe_temp(&mut self, rom: &ProbeRom) -> io::Result<Temperature<C>> {
+        let mut request = [0_u8; 9];
+        request[0] = AQ_CMD_GET_PROBE_TEMP;
+        request[1..9].copy_from_slice(rom);
+        let mut temp = [0_u8; 4];
+        try!(self.device.write(&request));
+        try!(self.device.read(&mut temp));
+
+        try!(check_crc(&temp));
+
+        if temp[0] == 0 {
+            return Err(io::Error::new(ErrorKind::NotFound, format!("Probe {} is missing or failed its last read", format_rom(rom))));
+        }
+
+        let temp_c = (BigEndian::read_u16(&temp[1..3]) as f32) * 0.0625;
+        Ok(Temperature::in_c(temp_c))
+    }
+
     pub fn get_air_temp_humidity(&mut self) -> io::Result<(Temperature<C>, f32)> {
         let mut temp = [0_u8; 5];
         try!(self.device.write(&[AQ_CMD_GET_AIR_TEMP_HUMIDITY]));
@@ -63,17 +124,8 @@ impl AvrController {
 
         try!(check_crc(&depth));
 
-        let result = BigEndian::read_u16(&depth);
-
-        // TODO: refactor averaging code
-        if self.depth_avg[0] == 0 {
-            self.depth_avg = [result; 10];
-        } else {
-            self.depth_avg[self.depth_index] = result;
-        }
-        self.depth_index = if self.depth_index >= 9 { 0 } else { self.depth_index + 1 };
-
-        Ok(self.depth_avg.iter().fold(0, |sum, i| sum + i) / 10)
+        // smoothing is up to the depth filter
+        Ok(BigEndian::read_u16(&depth))
     }
 
     pub fn set_intensities(&mut self, values: &[u8]) -> Result<(), io::Error> {
@@ -94,7 +146,7 @@ fn check_crc(values: &[u8]) -> io::Result<()> {
     Ok(())
 }
 
-fn crc(values: &[u8]) -> u8 {
+pub fn crc(values: &[u8]) -> u8 {
     let mut crc: u8 = 0xff;
 
     for j in 0..values.len() - 1 {
@@ -113,3 +165,137 @@ fn crc(values: &[u8]) -> u8 {
     crc
 }
 
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::devices::fake_avr::{FakeAvr, Fault};
+
+    fn controller() -> (FakeAvr, AvrController<FakeAvr>) {
+        let avr = FakeAvr::new();
+        (avr.clone(), AvrController::with_bus(avr))
+    }
+
+    #[test]
+    fn crc_matches_firmware() {
+        // CRC-8, polynomial 0x07, initial value 0xff. Last byte is the checksum slot.
+        assert_eq!(crc(&[0x01, 0x91, 0]), 0x3c);
+        assert!(check_crc(&[0x01, 0x91, 0x3c]).is_ok());
+        assert!(check_crc(&[0x01, 0x91, 0x3d]).is_err());
+    }
+
+    #[test]
+    fn decodes_temp() {
+        let (avr, mut controller) = controller();
+        avr.set_temp_raw(0x0191);
+
+        assert_eq!(controller.get_temp().unwrap(), Temperature::in_c(25.0625));
+    }
+
+    #[test]
+    fn rom_ids_round_trip() {
+        let rom = [0x28, 0xff, 0x4a, 0x1c, 0x61, 0x16, 0x04, 0x9e];
+
+        assert_eq!(format_rom(&rom), "28ff4a1c6116049e");
+        assert_eq!(parse_rom("28FF4A1C6116049E").unwrap(), rom);
+        assert!(parse_rom("28ff4a1c").is_err());
+        assert!(parse_rom("28ff4a1c611604zz").is_err());
+    }
+
+    #[test]
+    fn lists_and_reads_probes() {
+        let (avr, mut controller) = controller();
+        let display = [0x28, 1, 2, 3, 4, 5, 6, 7];
+        let sump = [0x28, 9, 8, 7, 6, 5, 4, 3];
+        avr.add_probe(display, 0x0191);
+        avr.add_probe(sump, 0x0180);
+
+        assert_eq!(controller.list_probes().unwrap(), vec![display, sump]);
+        assert_eq!(controller.get_probe_temp(&sump).unwrap(), Temperature::in_c(24.0));
+        assert_eq!(controller.get_probe_temp(&display).unwrap(), Temperature::in_c(25.0625));
+    }
+
+    #[test]
+    fn unknown_probe_is_not_found() {
+        let (avr, mut controller) = controller();
+        avr.add_probe([0x28, 1, 2, 3, 4, 5, 6, 7], 0x0191);
+
+        assert_eq!(controller.get_probe_temp(&[0x28, 0, 0, 0, 0, 0, 0, 0]).unwrap_err().kind(), ErrorKind::NotFound);
+        assert!(!avr.error());
+    }
+
+    #[test]
+    fn decodes_air_temp_and_humidity() {
+        let (avr, mut controller) = controller();
+        avr.set_air_temp_humidity(245, 553);
+
+        let (temp, humidity) = controller.get_air_temp_humidity().unwrap();
+        assert_eq!(temp, Temperature::in_c(24.5));
+        assert_eq!(humidity, 55.3);
+    }
+
+    #[test]
+    fn depth_is_raw() {
+        let (avr, mut controller) = controller();
+        avr.set_depth(500);
+        assert_eq!(controller.get_depth().unwrap(), 500);
+
+        avr.set_depth(600);
+        assert_eq!(controller.get_depth().unwrap(), 600);
+    }
+
+    #[test]
+    fn sets_intensities() {
+        let (avr, mut controller) = controller();
+
+        controller.set_intensities(&[1, 2, 3, 4, 5, 255]).unwrap();
+
+        assert_eq!(avr.channels(), [1, 2, 3, 4, 5, 255]);
+        assert!(!avr.error());
+    }
+
+    #[test]
+    fn firmware_rejects_corrupted_intensities() {
+        let (avr, mut controller) = controller();
+        avr.inject(Fault::BadCrc);
+
+        controller.set_intensities(&[1, 2, 3, 4, 5, 6]).unwrap();
+
+        assert_eq!(avr.channels(), [0_u8; 6]);
+        assert!(avr.error());
+    }
+
+    #[test]
+    fn bad_crc_is_invalid_data() {
+        let (avr, mut controller) = controller();
+        avr.set_temp_raw(0x0191);
+        avr.inject(Fault::Nak);
+        avr.inject(Fault::BadCrc);
+
+        // NAK on the command write
+        assert!(controller.get_temp().is_err());
+        // then the corrupted read
+        assert_eq!(controller.get_temp().unwrap_err().kind(), ErrorKind::InvalidData);
+        assert!(controller.get_temp().is_ok());
+    }
+
+    #[test]
+    fn short_read_is_invalid_data() {
+        let (avr, mut controller) = controller();
+        avr.set_depth(500);
+        avr.inject(Fault::Nak);
+
+        assert!(controller.get_depth().is_err());
+
+        avr.inject(Fault::ShortRead(1));
+        assert_eq!(controller.get_air_temp_humidity().unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn nak_is_reported() {
+        let (avr, mut controller) = controller();
+        avr.inject(Fault::Nak);
+
+        assert!(controller.set_intensities(&[0_u8; 6]).is_err());
+    }
+}
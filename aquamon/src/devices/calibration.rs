@@ -0,0 +1,62 @@
+use ::uom::temp::*;
+
+use std::io as io;
+use std::io::ErrorKind;
+
+// a sensor that needs more correction than this is broken, or the points were entered wrong
+const MIN_GAIN: f32 = 0.5;
+const MAX_GAIN: f32 = 1.5;
+
+/// Corrects a temperature sensor to agree with a reference thermometer. Works in F.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TempCalibration {
+    pub gain: f32,
+    pub offset: f32,
+}
+
+impl TempCalibration {
+    pub fn offset(offset: f32) -> TempCalibration {
+        TempCalibration { gain: 1.0, offset: offset }
+    }
+
+    /// From what the sensor read and what the reference read at two temperatures
+    pub fn two_point(low: (f32, f32), high: (f32, f32)) -> io::Result<TempCalibration> {
+        let (low_read, low_actual) = low;
+        let (high_read, high_actual) = high;
+        // points too close together make the gain meaningless
+        if (high_read - low_read).abs() < 1.0 {
+            return Err(io::Error::new(ErrorKind::InvalidInput,
+                format!("Calibration points {}F and {}F need to be at least 1F apart", low_read, high_read)));
+        }
+        let gain = (high_actual - low_actual) / (high_read - low_read);
+        if !(gain >= MIN_GAIN && gain <= MAX_GAIN) {
+            return Err(io::Error::new(ErrorKind::InvalidInput,
+                format!("Calibration gain {:.2} is outside {}-{}, check each point is read then actual", gain, MIN_GAIN, MAX_GAIN)));
+        }
+        Ok(TempCalibration { gain: gain, offset: low_actual - gain * low_read })
+    }
+
+    pub fn apply(&self, temp: Temperature<F>) -> Temperature<F> {
+        Temperature::in_f(temp.value() * self.gain + self.offset)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn two_points_correct_gain_and_offset() {
+        // reads 0.5F low at 70F and 1.5F low at 90F
+        let calibration = TempCalibration::two_point((69.5, 70.0), (88.5, 90.0)).unwrap();
+
+        assert!((calibration.apply(Temperature::in_f(69.5)).value() - 70.0).abs() < 0.001);
+        assert!((calibration.apply(Temperature::in_f(79.0)).value() - 80.0).abs() < 0.001);
+        assert!(TempCalibration::two_point((78.0, 78.0), (78.5, 79.0)).is_err());
+        // swapped points invert the gain
+        assert!(TempCalibration::two_point((88.5, 70.0), (69.5, 90.0)).is_err());
+        assert!(TempCalibration::two_point((70.0, 69.5), (90.0, 88.5)).is_ok());
+        assert!(TempCalibration::two_point((70.0, 70.0), (80.0, 100.0)).is_err());
+        assert_eq!(TempCalibration::offset(-0.4).apply(Temperature::in_f(80.0)).value(), 79.6);
+    }
+}
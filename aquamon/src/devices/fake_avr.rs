@@ -0,0 +1,192 @@
+use std::io as io;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use byteorder::{ByteOrder, BigEndian};
+
+use super::i2c::I2CBus;
+use super::avr_controller::{crc, ProbeRom, AQ_CMD_SETCHANNELS, AQ_CMD_GET_TEMP, AQ_CMD_GET_DEPTH, AQ_CMD_GET_AIR_TEMP_HUMIDITY,
+                            AQ_CMD_GET_PROBE_ROM, AQ_CMD_GET_PROBE_TEMP};
+
+/// Something going wrong on the bus during the next transaction
+#[derive(Debug, Clone, Copy)]
+pub enum Fault {
+    /// The checksum byte is corrupted on the way to the master
+    BadCrc,
+    /// Only this many bytes make it to the master
+    ShortRead(usize),
+    /// The slave doesn't acknowledge its address
+    Nak,
+}
+
+struct State {
+    temp_raw: u16,
+    depth: u16,
+    air_temp: u16,
+    humidity: u16,
+    probes: Vec<(ProbeRom, u16)>,
+    channels: [u8; 6],
+    tx: Vec<u8>,
+    error: bool,
+    faults: VecDeque<Fault>,
+}
+
+/// An in-process stand-in for the ATmega at 0x32, answering the way the firmware's
+/// `ProcessMessage` does. Clones share the same slave.
+#[derive(Clone)]
+pub struct FakeAvr {
+    state: Rc<RefCell<State>>,
+}
+
+impl FakeAvr {
+    pub fn new() -> FakeAvr {
+        FakeAvr {
+            state: Rc::new(RefCell::new(State {
+                temp_raw: 0,
+                depth: 0,
+                air_temp: 0,
+                humidity: 0,
+                probes: vec![],
+                channels: [0_u8; 6],
+                tx: vec![],
+                error: false,
+                faults: VecDeque::new(),
+            }))
+        }
+    }
+
+    /// Raw DS18B20 reading in 1/16ths of a degree C
+    pub fn set_temp_raw(&self, raw: u16) { self.state.borrow_mut().temp_raw = raw; }
+
+    pub fn set_depth(&self, depth: u16) { self.state.borrow_mut().depth = depth; }
+
+    /// RHT03 readings in tenths of a degree C and tenths of a percent
+    pub fn set_air_temp_humidity(&self, temp: u16, humidity: u16) {
+        let mut state = self.state.borrow_mut();
+        state.air_temp = temp;
+        state.humidity = humidity;
+    }
+
+    /// A probe found by the 1-Wire search, reading `raw` 1/16ths of a degree C. The first
+    /// one is also what `AQ_CMD_GET_TEMP` reports, like the firmware.
+    pub fn add_probe(&self, rom: ProbeRom, raw: u16) {
+        let mut state = self.state.borrow_mut();
+        if state.probes.is_empty() { state.temp_raw = raw; }
+        state.probes.push((rom, raw));
+    }
+
+    pub fn channels(&self) -> [u8; 6] { self.state.borrow().channels }
+
+    /// Whether the firmware flagged an error (bad CRC or undefined command)
+    pub fn error(&self) -> bool { self.state.borrow().error }
+
+    pub fn inject(&self, fault: Fault) { self.state.borrow_mut().faults.push_back(fault); }
+
+    fn next_fault(&self) -> Option<Fault> { self.state.borrow_mut().faults.pop_front() }
+
+    // the command byte only fails if the slave isn't there, other faults hit the data
+    fn nak_pending(&self) -> bool {
+        let mut state = self.state.borrow_mut();
+        if let Some(&Fault::Nak) = state.faults.front() {
+            state.faults.pop_front();
+            return true;
+        }
+        false
+    }
+}
+
+fn nak() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "Remote I/O error")
+}
+
+fn with_crc(values: &[u8]) -> Vec<u8> {
+    let mut buf = values.to_vec();
+    buf.push(0);
+    let len = buf.len();
+    buf[len - 1] = crc(&buf);
+    buf
+}
+
+impl I2CBus for FakeAvr {
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        if self.nak_pending() { return Err(nak()); }
+
+        let mut state = self.state.borrow_mut();
+        let mut buf = [0_u8; 4];
+        state.tx = match data[0] {
+            AQ_CMD_GET_TEMP => {
+                BigEndian::write_u16(&mut buf, state.temp_raw);
+                with_crc(&buf[0..2])
+            },
+            AQ_CMD_GET_DEPTH => {
+                BigEndian::write_u16(&mut buf, state.depth);
+                with_crc(&buf[0..2])
+            },
+            AQ_CMD_GET_AIR_TEMP_HUMIDITY => {
+                BigEndian::write_u16(&mut buf[0..2], state.air_temp);
+                BigEndian::write_u16(&mut buf[2..4], state.humidity);
+                with_crc(&buf)
+            },
+            AQ_CMD_GET_PROBE_ROM if data.len() == 2 => {
+                let index = data[1] as usize;
+                let mut reply = vec![state.probes.len() as u8];
+                reply.extend_from_slice(&state.probes.get(index).map(|p| p.0).unwrap_or([0_u8; 8]));
+                with_crc(&reply)
+            },
+            AQ_CMD_GET_PROBE_TEMP if data.len() == 9 => {
+                let (ok, raw) = match state.probes.iter().find(|p| p.0[..] == data[1..9]) {
+                    Some(&(_, raw)) => (1, raw),
+                    None => (0, 0),
+                };
+                BigEndian::write_u16(&mut buf, raw);
+                with_crc(&[ok, buf[0], buf[1]])
+            },
+            _ => {
+                state.error = true;
+                vec![]
+            }
+        };
+        Ok(())
+    }
+
+    fn read(&mut self, data: &mut [u8]) -> io::Result<()> {
+        let fault = self.next_fault();
+        if let Some(Fault::Nak) = fault { return Err(nak()); }
+
+        let mut state = self.state.borrow_mut();
+        let mut tx = state.tx.clone();
+        state.tx = vec![];
+        if let Some(Fault::BadCrc) = fault {
+            if let Some(last) = tx.last_mut() { *last = !*last; }
+        }
+        let len = match fault {
+            Some(Fault::ShortRead(len)) => len,
+            _ => data.len(),
+        };
+        for i in 0..len.min(data.len()) {
+            // an idle slave leaves the bus high
+            data[i] = *tx.get(i).unwrap_or(&0xff);
+        }
+        Ok(())
+    }
+
+    fn smbus_write_block_data(&mut self, register: u8, values: &[u8]) -> io::Result<()> {
+        let fault = self.next_fault();
+        if let Some(Fault::Nak) = fault { return Err(nak()); }
+
+        let mut values = values.to_vec();
+        if let Some(Fault::BadCrc) = fault {
+            let last = values.len() - 1;
+            values[last] = !values[last];
+        }
+
+        let mut state = self.state.borrow_mut();
+        if register == AQ_CMD_SETCHANNELS && values.len() == 7 && crc(&values) == values[6] {
+            state.channels.copy_from_slice(&values[0..6]);
+        } else {
+            state.error = true;
+        }
+        Ok(())
+    }
+}
@@ -3,6 +3,7 @@ use sysfs_gpio::{Pin, Direction};
 use sysfs_gpio::Error as GpioError;
 use std::error::Error;
 use std::io::ErrorKind;
+use super::{Relay, Input};
 
 pub struct GpioPin {
     pin: Pin,
@@ -36,20 +37,44 @@ impl GpioPin {
                     })
     }
 
-    pub fn set(&mut self, value: bool) -> io::Result<()> {
+}
+
+impl Relay for GpioPin {
+    fn set(&mut self, value: bool) -> io::Result<()> {
         self.pin.set_value(if value ^ self.active_low { 1 } else { 0 }).map_err(from_gpio_error)
     }
 
-    pub fn turn_on(&mut self) -> io::Result<()> {
+    fn turn_on(&mut self) -> io::Result<()> {
         self.pin.set_value(if self.active_low { 0 } else { 1 }).map_err(from_gpio_error)
     }
 
-    pub fn turn_off(&mut self) -> io::Result<()> {
+    fn turn_off(&mut self) -> io::Result<()> {
         self.pin.set_value(if self.active_low { 1 } else { 0 }).map_err(from_gpio_error)
     }
 
-    pub fn status(&mut self) -> io::Result<bool> {
+    fn status(&mut self) -> io::Result<bool> {
         self.pin.get_value().map(|val| (self.active_low && val == 0) || (!self.active_low && val != 0)).map_err(from_gpio_error)
     }
 }
 
+
+/// A GPIO line read as an input
+pub struct GpioInput {
+    pin: Pin,
+    active_low: bool,
+}
+
+impl GpioInput {
+    pub fn new(index: u64, active_low: bool) -> io::Result<GpioInput> {
+        let pin = Pin::new(index);
+        pin.export().and_then(|_| pin.set_direction(Direction::In))
+                    .map(|_| GpioInput { pin: pin, active_low: active_low })
+                    .map_err(from_gpio_error)
+    }
+}
+
+impl Input for GpioInput {
+    fn read(&mut self) -> io::Result<bool> {
+        self.pin.get_value().map(|val| (val != 0) ^ self.active_low).map_err(from_gpio_error)
+    }
+}
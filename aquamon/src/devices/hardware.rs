@@ -0,0 +1,161 @@
+use ::uom::temp::*;
+
+use std::io as io;
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use carboxyl::Stream;
+
+use super::{Depth, Humidity, pH};
+use super::health::SensorHealth;
+use super::ph_monitor::{PhConfig, PhCalibration, PhCalibrationCommand};
+
+/// A switched output: a relay, an outlet or anything else that is either on or off.
+pub trait Relay {
+    fn set(&mut self, value: bool) -> io::Result<()>;
+
+    fn turn_on(&mut self) -> io::Result<()> {
+        self.set(true)
+    }
+
+    fn turn_off(&mut self) -> io::Result<()> {
+        self.set(false)
+    }
+
+    fn status(&mut self) -> io::Result<bool>;
+}
+
+/// A digital input: a float switch, leak sensor, button or anything else that reads on or off
+pub trait Input {
+    /// True when active, after any inversion for active low wiring
+    fn read(&mut self) -> io::Result<bool>;
+}
+
+pub trait TemperatureSource {
+    /// The water temperature from `AQ_CMD_GET_TEMP`, i.e. the first probe on the bus
+    fn temp_stream(&self) -> Stream<Temperature<F>>;
+    /// Each named water probe
+    fn probe_streams(&self) -> Vec<(String, Stream<Temperature<F>>)>;
+}
+
+pub trait DepthSource {
+    fn depth_stream(&self) -> Stream<Depth>;
+}
+
+pub trait AirSource {
+    fn air_temp_stream(&self) -> Stream<Temperature<F>>;
+    fn humidity_stream(&self) -> Stream<Humidity>;
+}
+
+pub trait PhSource {
+    fn ph_stream(&self) -> Stream<pH>;
+}
+
+/// The six dimmable LED channels driven by the AVR
+pub trait LightChannels {
+    fn set_intensities(&mut self, values: &[u8; 6]) -> io::Result<()>;
+}
+
+/// Everything the main loop needs from a hardware backend. `tick` is called with the
+/// millisecond counter and is expected to push new readings into the streams. A sensor
+/// that fails to read goes in its health, so there's nothing for `tick` to return.
+pub trait Hardware: TemperatureSource + DepthSource + AirSource + PhSource + LightChannels {
+    fn tick(&mut self, ticks: u64);
+
+    /// How each sensor has been reading
+    fn sensor_health(&self) -> Vec<SensorHealth>;
+
+    /// Moves a guided pH probe calibration along. Finishing applies the new calibration
+    /// and returns it so it can be saved.
+    fn calibrate_ph(&mut self, _command: PhCalibrationCommand) -> io::Result<Option<PhConfig>> {
+        Err(io::Error::new(ErrorKind::NotFound, "No pH probe to calibrate"))
+    }
+
+    /// The pH calibration in progress, if there is one
+    fn ph_calibration(&self) -> Option<&PhCalibration> { None }
+
+    /// Each named digital input's debounced edges. The first event is its initial level, and
+    /// None means it can't be read.
+    fn input_streams(&self) -> Vec<(String, Stream<Option<bool>>)> { vec![] }
+}
+
+/// Several relays switched together, e.g. two heaters in one tank. Reports on if any of
+/// them is on.
+pub struct RelayGroup {
+    relays: Vec<Box<Relay>>,
+}
+
+impl RelayGroup {
+    pub fn new(relays: Vec<Box<Relay>>) -> RelayGroup {
+        RelayGroup { relays: relays }
+    }
+}
+
+impl Relay for RelayGroup {
+    fn set(&mut self, value: bool) -> io::Result<()> {
+        for relay in self.relays.iter_mut() {
+            try!(relay.set(value));
+        }
+        Ok(())
+    }
+
+    fn status(&mut self) -> io::Result<bool> {
+        let mut on = false;
+        for relay in self.relays.iter_mut() {
+            on = on || try!(relay.status());
+        }
+        Ok(on)
+    }
+}
+
+/// A relay that only lives in memory. Clones share the same state so the owner can
+/// observe what a controller did with it.
+#[derive(Clone, Default)]
+pub struct MemoryRelay {
+    state: Arc<AtomicBool>,
+}
+
+impl MemoryRelay {
+    pub fn new() -> MemoryRelay {
+        MemoryRelay { state: Arc::new(AtomicBool::new(false)) }
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.state.load(Ordering::SeqCst)
+    }
+}
+
+impl Relay for MemoryRelay {
+    fn set(&mut self, value: bool) -> io::Result<()> {
+        self.state.store(value, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn status(&mut self) -> io::Result<bool> {
+        Ok(self.is_on())
+    }
+}
+
+/// An input that only lives in memory. Clones share the same state so the owner can
+/// drive it.
+#[derive(Clone, Default)]
+pub struct MemoryInput {
+    state: Arc<AtomicBool>,
+}
+
+impl MemoryInput {
+    pub fn new() -> MemoryInput {
+        MemoryInput { state: Arc::new(AtomicBool::new(false)) }
+    }
+
+    pub fn set(&self, active: bool) {
+        self.state.store(active, Ordering::SeqCst);
+    }
+}
+
+impl Input for MemoryInput {
+    fn read(&mut self) -> io::Result<bool> {
+        Ok(self.state.load(Ordering::SeqCst))
+    }
+}
@@ -0,0 +1,96 @@
+use ::uom::temp::*;
+
+use super::recording::Reading;
+
+/// How a single sensor has been doing
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensorHealth {
+    pub sensor: String,
+    /// Tick of the last reading that made it into the stream
+    pub last_good_ticks: Option<u64>,
+    /// Failed or out of range reads since the last good one
+    pub consecutive_failures: u32,
+    pub total_failures: u64,
+    pub out_of_range: u64,
+    pub last_error: Option<String>,
+    /// Something wrong with readings that still get through
+    pub warning: Option<String>,
+}
+
+impl SensorHealth {
+    pub fn new(sensor: &str) -> SensorHealth {
+        SensorHealth {
+            sensor: sensor.to_string(),
+            last_good_ticks: None,
+            consecutive_failures: 0,
+            total_failures: 0,
+            out_of_range: 0,
+            last_error: None,
+            warning: None,
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.last_good_ticks.is_some() && self.consecutive_failures == 0
+    }
+
+    pub fn good(&mut self, ticks: u64) {
+        self.last_good_ticks = Some(ticks);
+        self.consecutive_failures = 0;
+    }
+
+    pub fn failed(&mut self, error: String) {
+        self.consecutive_failures += 1;
+        self.total_failures += 1;
+        self.last_error = Some(error);
+    }
+
+    pub fn rejected(&mut self, reading: &Reading) {
+        self.out_of_range += 1;
+        self.failed(format!("Out of range: {:?}", reading));
+    }
+}
+
+/// Whether a reading is physically plausible. Disconnected or glitching sensors tend to
+/// read at the rails, e.g. a DS18B20 that lost power reports its 85C reset value.
+pub fn in_range(reading: &Reading) -> bool {
+    match *reading {
+        Reading::Temp(temp) | Reading::Probe(_, temp) => temp > Temperature::in_f(40.0) && temp < Temperature::in_f(100.0),
+        Reading::AirTemp(temp) => temp > Temperature::in_f(0.0) && temp < Temperature::in_f(130.0),
+        Reading::Humidity(humidity) => humidity >= 0.0 && humidity <= 100.0,
+        Reading::Ph(ph) => ph > 0.0 && ph < 14.0,
+        // raw ADC counts, the ATO has its own sanity checks
+        Reading::Depth(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn failures_reset_on_a_good_reading() {
+        let mut health = SensorHealth::new("temp");
+        assert!(!health.is_healthy());
+
+        health.good(1000);
+        health.failed("Bad CRC".to_string());
+        health.rejected(&Reading::Temp(Temperature::in_c(85.0).to_f()));
+        assert_eq!(health.consecutive_failures, 2);
+        assert_eq!(health.out_of_range, 1);
+        assert!(!health.is_healthy());
+
+        health.good(7500);
+        assert!(health.is_healthy());
+        assert_eq!(health.total_failures, 2);
+        assert_eq!(health.last_good_ticks, Some(7500));
+    }
+
+    #[test]
+    fn power_on_reset_temp_is_out_of_range() {
+        assert!(!in_range(&Reading::Temp(Temperature::in_c(85.0).to_f())));
+        assert!(!in_range(&Reading::Probe("Sump".to_string(), Temperature::in_c(0.0).to_f())));
+        assert!(in_range(&Reading::Temp(Temperature::in_f(78.0))));
+        assert!(!in_range(&Reading::Humidity(102.0)));
+    }
+}
@@ -0,0 +1,26 @@
+use std::io as io;
+
+use i2cdev::core::*;
+use i2cdev::linux::LinuxI2CDevice;
+
+/// The parts of an I2C/SMBus master the device drivers need, so they can run against
+/// something other than `/dev/i2c-*`
+pub trait I2CBus {
+    fn write(&mut self, data: &[u8]) -> io::Result<()>;
+    fn read(&mut self, data: &mut [u8]) -> io::Result<()>;
+    fn smbus_write_block_data(&mut self, register: u8, values: &[u8]) -> io::Result<()>;
+}
+
+impl I2CBus for LinuxI2CDevice {
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        I2CDevice::write(self, data).map_err(From::from)
+    }
+
+    fn read(&mut self, data: &mut [u8]) -> io::Result<()> {
+        I2CDevice::read(self, data).map_err(From::from)
+    }
+
+    fn smbus_write_block_data(&mut self, register: u8, values: &[u8]) -> io::Result<()> {
+        I2CDevice::smbus_write_block_data(self, register, values).map_err(From::from)
+    }
+}
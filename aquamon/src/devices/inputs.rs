@@ -0,0 +1,175 @@
+use super::Input;
+use super::health::SensorHealth;
+
+use carboxyl::{Sink, Stream};
+
+/// Only accepts a new level once it has held for `debounce_ms`, so contact bounce and
+/// ripples lapping a float switch don't come through as a burst of changes.
+pub struct Debouncer {
+    debounce_ms: u64,
+    stable: Option<bool>,
+    candidate: Option<(bool, u64)>,
+}
+
+impl Debouncer {
+    pub fn new(debounce_ms: u64) -> Debouncer {
+        Debouncer { debounce_ms: debounce_ms, stable: None, candidate: None }
+    }
+
+    /// Returns the level when it changes, including the first one read
+    pub fn update(&mut self, level: bool, ticks: u64) -> Option<bool> {
+        if self.stable == Some(level) {
+            self.candidate = None;
+            return None;
+        }
+        let since = match self.candidate {
+            Some((candidate, since)) if candidate == level => since,
+            _ => ticks,
+        };
+        if ticks >= since + self.debounce_ms {
+            self.stable = Some(level);
+            self.candidate = None;
+            Some(level)
+        } else {
+            self.candidate = Some((level, since));
+            None
+        }
+    }
+
+    /// Forgets the level, so the next one read is published again
+    pub fn reset(&mut self) {
+        self.stable = None;
+        self.candidate = None;
+    }
+}
+
+struct DigitalInput {
+    name: String,
+    input: Box<Input>,
+    debouncer: Debouncer,
+    sink: Sink<Option<bool>>,
+    health: SensorHealth,
+}
+
+/// Named digital inputs, each published as a stream of its debounced edges, with None when
+/// it stops reading
+pub struct DigitalInputs {
+    inputs: Vec<DigitalInput>,
+}
+
+impl DigitalInputs {
+    pub fn new() -> DigitalInputs {
+        DigitalInputs { inputs: vec![] }
+    }
+
+    pub fn add(&mut self, name: &str, input: Box<Input>, debounce_ms: u64) {
+        self.inputs.push(DigitalInput {
+            name: name.to_string(),
+            input: input,
+            debouncer: Debouncer::new(debounce_ms),
+            sink: Sink::new(),
+            health: SensorHealth::new(&format!("input:{}", name)),
+        });
+    }
+
+    /// Reads every input, failures go in its health
+    pub fn tick(&mut self, ticks: u64) {
+        for input in self.inputs.iter_mut() {
+            match input.input.read() {
+                Ok(level) => {
+                    input.health.good(ticks);
+                    if let Some(level) = input.debouncer.update(level, ticks) {
+                        info!("Input {} is now {}", input.name, if level { "active" } else { "inactive" });
+                        input.sink.send(Some(level));
+                    }
+                },
+                Err(err) => {
+                    if input.health.is_healthy() {
+                        warn!("Input {} can't be read: {}", input.name, err);
+                        input.debouncer.reset();
+                        input.sink.send(None);
+                    }
+                    input.health.failed(err.to_string());
+                },
+            }
+        }
+    }
+
+    pub fn streams(&self) -> Vec<(String, Stream<Option<bool>>)> {
+        self.inputs.iter().map(|i| (i.name.clone(), i.sink.stream())).collect()
+    }
+
+    pub fn health(&self) -> Vec<SensorHealth> {
+        self.inputs.iter().map(|i| i.health.clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::devices::MemoryInput;
+    use std::io as io;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[derive(Clone)]
+    struct FlakyInput {
+        broken: Arc<AtomicBool>,
+    }
+
+    impl Input for FlakyInput {
+        fn read(&mut self) -> io::Result<bool> {
+            if self.broken.load(Ordering::SeqCst) {
+                Err(io::Error::new(io::ErrorKind::Other, "GPIO read failed"))
+            } else {
+                Ok(true)
+            }
+        }
+    }
+
+    #[test]
+    fn bounces_are_ignored() {
+        let mut debouncer = Debouncer::new(50);
+
+        assert_eq!(debouncer.update(false, 0), None);
+        assert_eq!(debouncer.update(false, 50), Some(false));
+        assert_eq!(debouncer.update(true, 60), None);
+        assert_eq!(debouncer.update(false, 70), None);
+        assert_eq!(debouncer.update(true, 80), None);
+        assert_eq!(debouncer.update(true, 120), None);
+        assert_eq!(debouncer.update(true, 130), Some(true));
+        assert_eq!(debouncer.update(true, 500), None);
+    }
+
+    #[test]
+    fn publishes_edges() {
+        let float = MemoryInput::new();
+        let mut inputs = DigitalInputs::new();
+        inputs.add("highFloat", Box::new(float.clone()), 20);
+        let edges = inputs.streams().remove(0).1.fold(vec![], |mut edges, level| { edges.push(level); edges });
+
+        for ticks in (0..10).map(|t| t * 10) {
+            if ticks == 50 { float.set(true); }
+            inputs.tick(ticks);
+        }
+
+        assert_eq!(edges.sample(), vec![Some(false), Some(true)]);
+        assert!(inputs.health()[0].is_healthy());
+    }
+
+    #[test]
+    fn failures_publish_none() {
+        let float = FlakyInput { broken: Arc::new(AtomicBool::new(false)) };
+        let mut inputs = DigitalInputs::new();
+        inputs.add("highFloat", Box::new(float.clone()), 20);
+        let edges = inputs.streams().remove(0).1.fold(vec![], |mut edges, level| { edges.push(level); edges });
+
+        for ticks in (0..10).map(|t| t * 10) {
+            float.broken.store(ticks >= 30 && ticks < 60, Ordering::SeqCst);
+            inputs.tick(ticks);
+        }
+
+        assert_eq!(edges.sample(), vec![Some(true), None, Some(true)]);
+        assert!(inputs.health()[0].is_healthy());
+    }
+}
@@ -1,11 +1,27 @@
 mod gpio;
+mod hardware;
+mod i2c;
 mod avr_controller;
-#[allow(dead_code)]
+#[cfg(test)]
+mod fake_avr;
+mod simulator;
+mod recording;
+mod health;
+mod calibration;
 mod ph_monitor;
+mod inputs;
 
-pub use self::gpio::GpioPin;
-pub use self::ph_monitor::PhConfig;
-use self::ph_monitor::PhMonitor;
+pub use self::gpio::{GpioPin, GpioInput};
+pub use self::i2c::I2CBus;
+pub use self::avr_controller::{ProbeRom, parse_rom, format_rom};
+pub use self::hardware::{Relay, RelayGroup, MemoryRelay, Input, MemoryInput, TemperatureSource, DepthSource, AirSource, PhSource, LightChannels, Hardware};
+pub use self::simulator::{Simulator, SimulatorConfig};
+pub use self::recording::{Reading, Sample, Recorder, Replay, SensorSinks};
+pub use self::health::SensorHealth;
+pub use self::calibration::TempCalibration;
+pub use self::ph_monitor::{PhConfig, PhCalibration, PhCalibrationCommand};
+use self::ph_monitor::{PhMonitor, CompensationTemp, calibration_temp};
+use self::inputs::DigitalInputs;
 
 use self::avr_controller::AvrController;
 use ::uom::temp::*;
@@ -13,69 +29,213 @@ use ::uom::temp::*;
 use std::io::ErrorKind;
 use std::io as io;
 
-use carboxyl::{Sink,Stream};
+use carboxyl::{Signal, Stream};
 
 pub type Depth = u16;
-#[allow(dead_code)]
 pub type pH = f32;
 pub type Humidity = f32;
 
+/// A DS18B20 water temperature probe on the AVR's 1-Wire bus
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempProbe {
+    pub name: String,
+    pub rom: ProbeRom,
+}
+
 pub struct Devices {
     avr_controller: AvrController,
-    ph_monitor: PhMonitor,
-    temp_sink: Sink<Temperature<F>>,
-    depth_sink: Sink<Depth>,
-    air_temp_sink: Sink<Temperature<F>>,
-    humidity_sink: Sink<Humidity>,
-    ph_sink: Sink<pH>,
+    ph_monitor: Option<PhMonitor>,
+    ph_temp: Option<CompensationTemp>,
+    ph_enabled: bool,
+    ph_calibration: Option<PhCalibration>,
+    probes: Vec<TempProbe>,
+    sinks: SensorSinks,
+    inputs: DigitalInputs,
     last_intensities: [u8; 6],
 }
 
 impl Devices {
-    pub fn new(i2c_device_id: u8, ph_i2c_device_id: u8) -> io::Result<Devices> {
-        let avr_controller = try!(AvrController::new(i2c_device_id));
-        let ph_monitor = try!(PhMonitor::new(ph_i2c_device_id, PhConfig::default()));
+    pub fn new(i2c_device_id: u8, probes: Vec<TempProbe>) -> io::Result<Devices> {
+        let mut avr_controller = try!(AvrController::new(i2c_device_id));
+
+        // log what's on the bus so new probes can be added to the settings
+        match avr_controller.list_probes() {
+            Ok(found) => for rom in found.iter() {
+                match probes.iter().find(|p| p.rom == *rom) {
+                    Some(probe) => info!("Found temperature probe {} ({})", format_rom(rom), probe.name),
+                    None => warn!("Found temperature probe {} that isn't in the settings", format_rom(rom)),
+                }
+            },
+            Err(err) => error!("Could not list temperature probes: {}", err),
+        }
+
+        let mut sinks = SensorSinks::new();
+        for probe in probes.iter() {
+            sinks.add_probe(&probe.name);
+        }
+        // pH is off until it's enabled
+        sinks.ignore("ph");
 
         Ok(Devices {
             avr_controller: avr_controller,
-            ph_monitor: ph_monitor,
-            temp_sink: Sink::new(),
-            depth_sink: Sink::new(),
-            air_temp_sink: Sink::new(), 
-            humidity_sink: Sink::new(),
-            ph_sink: Sink::new(),
+            ph_monitor: None,
+            ph_temp: None,
+            ph_enabled: false,
+            ph_calibration: None,
+            probes: probes,
+            sinks: sinks,
+            inputs: DigitalInputs::new(),
             last_intensities: [255_u8; 6], // initialize to high values so we ramp down by default
         })
     }
 
-    pub fn tick(&mut self, ticks: u64) -> io::Result<()> {
+    /// Attaches the MCP3221 on `ph_i2c_device_id`. Readings are compensated with the water
+    /// temperature from `temp_probe`, or the first probe on the bus if that's empty, and are
+    /// only reported once pH is enabled.
+    pub fn add_ph_probe(&mut self, ph_i2c_device_id: u8, config: PhConfig, temp_probe: &str) -> io::Result<()> {
+        self.ph_monitor = Some(try!(PhMonitor::new(ph_i2c_device_id, config)));
+        let temp_stream = self.sinks.probe_streams().into_iter()
+            .find(|p| p.0 == temp_probe)
+            .map(|p| p.1)
+            .unwrap_or_else(|| self.sinks.temp_stream());
+        self.ph_temp = Some(CompensationTemp::new(&temp_stream));
+        Ok(())
+    }
+
+    /// Publishes `input` as `name`. It has to hold a new level for `debounce_ms` before the
+    /// change comes through.
+    pub fn add_input(&mut self, name: &str, input: Box<Input>, debounce_ms: u64) {
+        self.inputs.add(name, input, debounce_ms);
+    }
+
+    pub fn enable_ph(&mut self) {
+        self.ph_enabled = true;
+    }
+
+    /// Corrects readings from the temperature sensor `sensor`: temp, airTemp or probe:<name>
+    pub fn calibrate(&mut self, sensor: &str, calibration: TempCalibration) {
+        self.sinks.calibrate(sensor, calibration);
+    }
+
+    /// Writes every reading to `recorder` from now on
+    pub fn record_to(&mut self, recorder: Recorder) {
+        self.sinks.record_to(recorder);
+    }
+}
+
+impl Hardware for Devices {
+    // Every sensor is read on its own so one failing doesn't starve the others. Failures
+    // are counted in the sensor health rather than returned.
+    fn tick(&mut self, ticks: u64) {
+        // inputs are read every tick so debouncing has the full resolution
+        self.inputs.tick(ticks);
+
         // temp is only updated by the micro every 5 seconds + 1 second conversion time
         if ticks % 6500 == 0 {
-            let temp = try!(self.avr_controller.get_temp()).to_f();
-            info!("Got temp: {:?}", temp.value());
-            self.temp_sink.send(temp);
+            match self.avr_controller.get_temp() {
+                Ok(temp) => {
+                    info!("Got temp: {:?}", temp.to_f().value());
+                    self.sinks.send(ticks, Reading::Temp(temp.to_f()));
+                },
+                Err(err) => self.sinks.fail("temp", err.to_string()),
+            }
 
-            let (air_temp, humidity) = try!(self.avr_controller.get_air_temp_humidity());
-            info!("Got air temp: {:?} and humidity: {:?}", air_temp.to_f().value(), humidity);
-            self.air_temp_sink.send(air_temp.to_f());
-            self.humidity_sink.send(humidity);
+            for probe in self.probes.iter() {
+                match self.avr_controller.get_probe_temp(&probe.rom) {
+                    Ok(temp) => {
+                        info!("Got {} temp: {:?}", probe.name, temp.to_f().value());
+                        self.sinks.send(ticks, Reading::Probe(probe.name.clone(), temp.to_f()));
+                    },
+                    Err(err) => self.sinks.fail(&format!("probe:{}", probe.name), err.to_string()),
+                }
+            }
 
-            // TODO: when re-enabling, remove dead code warning at top of this file
-            // let ph = try!(self.ph_monitor.get_ph());
+            match self.avr_controller.get_air_temp_humidity() {
+                Ok((air_temp, humidity)) => {
+                    info!("Got air temp: {:?} and humidity: {:?}", air_temp.to_f().value(), humidity);
+                    self.sinks.send(ticks, Reading::AirTemp(air_temp.to_f()));
+                    self.sinks.send(ticks, Reading::Humidity(humidity));
+                },
+                Err(err) => {
+                    self.sinks.fail("airTemp", err.to_string());
+                    self.sinks.fail("humidity", err.to_string());
+                },
+            }
 
-            self.ph_sink.send(10.0);
+            if let (true, Some(ph_monitor)) = (self.ph_enabled, self.ph_monitor.as_mut()) {
+                // without a current water temperature the probe is assumed to be at its calibration temperature
+                let water_temp = self.ph_temp.as_mut().and_then(|t| t.sample(ticks / 1000));
+                self.sinks.warn("ph", match water_temp {
+                    Some(_) => None,
+                    None => Some("No current water temperature, pH isn't temperature compensated".to_string()),
+                });
+                match ph_monitor.get_ph(water_temp.unwrap_or_else(calibration_temp)) {
+                    Ok(ph) => self.sinks.send(ticks, Reading::Ph(ph)),
+                    Err(err) => self.sinks.fail("ph", err.to_string()),
+                }
+            }
         }
 
         if ticks % 1000 == 0 {
-            let depth = try!(self.avr_controller.get_depth());
-            info!("Got depth: {:?}", depth);
-            self.depth_sink.send(depth);
+            if let (Some(calibration), Some(ph_monitor)) = (self.ph_calibration.as_mut(), self.ph_monitor.as_mut()) {
+                if calibration.sampling().is_some() {
+                    match ph_monitor.get_raw() {
+                        Ok(raw) => calibration.add_sample(raw),
+                        Err(err) => calibration.failed(err.to_string()),
+                    }
+                }
+            }
+
+            match self.avr_controller.get_depth() {
+                Ok(depth) => {
+                    info!("Got depth: {:?}", depth);
+                    self.sinks.send(ticks, Reading::Depth(depth));
+                },
+                Err(err) => self.sinks.fail("depth", err.to_string()),
+            }
         }
+    }
 
-        Ok(())
+    fn sensor_health(&self) -> Vec<SensorHealth> {
+        let mut health = self.sinks.health();
+        health.extend(self.inputs.health());
+        health
     }
 
-    pub fn set_intensities(&mut self, values: &[u8; 6]) -> io::Result<()> { 
+    fn calibrate_ph(&mut self, command: PhCalibrationCommand) -> io::Result<Option<PhConfig>> {
+        let not_started = || io::Error::new(ErrorKind::InvalidInput, "No pH calibration in progress");
+        let ph_monitor = match self.ph_monitor {
+            Some(ref mut ph_monitor) => ph_monitor,
+            None => return Err(io::Error::new(ErrorKind::NotFound, "No pH probe to calibrate")),
+        };
+        match command {
+            PhCalibrationCommand::Start => self.ph_calibration = Some(PhCalibration::new()),
+            PhCalibrationCommand::Cancel => self.ph_calibration = None,
+            PhCalibrationCommand::Capture(buffer) => match self.ph_calibration {
+                Some(ref mut calibration) => try!(calibration.capture(buffer)),
+                None => return Err(not_started()),
+            },
+            PhCalibrationCommand::Finish => {
+                let config = match self.ph_calibration {
+                    Some(ref calibration) => try!(calibration.finish(ph_monitor.config().gain)),
+                    None => return Err(not_started()),
+                };
+                ph_monitor.set_config(config.clone());
+                self.ph_calibration = None;
+                self.ph_enabled = true;
+                return Ok(Some(config));
+            },
+        }
+        Ok(None)
+    }
+
+    fn ph_calibration(&self) -> Option<&PhCalibration> { self.ph_calibration.as_ref() }
+
+    fn input_streams(&self) -> Vec<(String, Stream<Option<bool>>)> { self.inputs.streams() }
+}
+
+impl LightChannels for Devices {
+    fn set_intensities(&mut self, values: &[u8; 6]) -> io::Result<()> { 
         // check so we don't spam the i2c bus
         if self.last_intensities != *values {
             self.last_intensities = values.clone();
@@ -85,36 +245,59 @@ impl Devices {
             Ok(())
         }
     }
+}
 
-    pub fn temp_stream(&self) -> Stream<Temperature<F>> { 
-        self.temp_sink.stream()
+impl TemperatureSource for Devices {
+    fn temp_stream(&self) -> Stream<Temperature<F>> { 
+        self.sinks.temp_stream()
     }
-    pub fn depth_stream(&self) -> Stream<Depth> { self.depth_sink.stream() }
-    pub fn air_temp_stream(&self) -> Stream<Temperature<F>> { self.air_temp_sink.stream() }
-    pub fn humidity_stream(&self) -> Stream<Humidity> { self.humidity_sink.stream() }
-    pub fn ph_stream(&self) -> Stream<pH> { self.ph_sink.stream() }
+
+    fn probe_streams(&self) -> Vec<(String, Stream<Temperature<F>>)> { self.sinks.probe_streams() }
+}
+
+impl DepthSource for Devices {
+    fn depth_stream(&self) -> Stream<Depth> { self.sinks.depth_stream() }
+}
+
+impl AirSource for Devices {
+    fn air_temp_stream(&self) -> Stream<Temperature<F>> { self.sinks.air_temp_stream() }
+    fn humidity_stream(&self) -> Stream<Humidity> { self.sinks.humidity_stream() }
+}
+
+impl PhSource for Devices {
+    fn ph_stream(&self) -> Stream<pH> { self.sinks.ph_stream() }
 }
 
 pub struct PiGpio {
-    owners: [(u64,bool); 7],
+    owners: Vec<u64>,
 }
 
 impl PiGpio {
     pub fn new() -> PiGpio {
         PiGpio {
-            // these are the exported pins we reserved for IO
-            owners: [(17, false), (27, false), (22, false), (5, false), (6, false), (13, false), (26, false)]
+            owners: vec![]
         }
     }
 
-    pub fn take_pin(&mut self, index: usize, active_low: bool) -> io::Result<GpioPin> {
-        let owner = self.owners[index];
-        if owner.1 {
-            return Err(io::Error::new(ErrorKind::AlreadyExists, "Port in use"));
+    /// Exports the BCM GPIO line `gpio` as an output
+    pub fn take_pin(&mut self, gpio: u64, active_low: bool) -> io::Result<GpioPin> {
+        try!(self.claim(gpio));
+        GpioPin::new(gpio, active_low)
+    }
+
+    /// Exports the BCM GPIO line `gpio` as an input
+    pub fn take_input(&mut self, gpio: u64, active_low: bool) -> io::Result<GpioInput> {
+        try!(self.claim(gpio));
+        GpioInput::new(gpio, active_low)
+    }
+
+    fn claim(&mut self, gpio: u64) -> io::Result<()> {
+        if self.owners.contains(&gpio) {
+            return Err(io::Error::new(ErrorKind::AlreadyExists, format!("GPIO {} in use", gpio)));
         }
         // assign the owner
-        self.owners[index] = (owner.0, true);
-        GpioPin::new(owner.0, active_low)
+        self.owners.push(gpio);
+        Ok(())
     }
 }
 
@@ -1,6 +1,11 @@
+use std::collections::VecDeque;
 use std::io as io;
+use std::io::ErrorKind;
 
 use super::pH;
+use ::uom::temp::*;
+use ::controller::Freshness;
+use carboxyl::{Signal, Stream};
 use i2cdev::core::*;
 use i2cdev::linux::{LinuxI2CDevice};
 
@@ -8,79 +13,298 @@ use byteorder::{ByteOrder, BigEndian};
 
 pub struct PhMonitor {
     mcp3221: Mcp3221,
-    ph_avg: [pH; 20],
-    ph_index: usize,
     config: PhConfig,
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct PhConfig {
     pub ph7_cal: u16,
     pub ph10_cal: u16,
+    /// From a three point calibration, used for readings below pH 7
+    pub ph4_cal: Option<u16>,
+    /// Gain of the amp between the probe and the ADC, None until it's set. Only affects the
+    /// reported slope and efficiency.
+    pub gain: Option<f32>,
 }
 
-// TODO: setup these values
 const SLAVE_ADDR: u16 = 0x4d;
 const V_REF: f32 = 4.096;
-const OPAMP_GAIN: f32 = 1.0;
-// const IDEAL_SLOPE: f32 = 59.16;
+// slopes are at the ADC until the gain is set
+const UNSET_GAIN: f32 = 1.0;
+const IDEAL_SLOPE: f32 = 59.16;
+// a healthy probe's slope, in percent of ideal
+const MIN_EFFICIENCY: f32 = 85.0;
+const MAX_EFFICIENCY: f32 = 105.0;
 const STEP_12BIT: f32 = 4096.0;
+// the probe is assumed to be calibrated at room temperature
+const CAL_TEMP_C: f32 = 25.0;
+// the same as the temperature controller's
+const WATER_TEMP_STALE_S: u64 = 2 * 60;
+const KELVIN: f32 = 273.15;
 
 impl PhConfig {
     pub fn default() -> PhConfig {
         PhConfig {
             ph7_cal: 2048,
             ph10_cal: 1065, // manually calibrated
+            ph4_cal: None,
+            gain: None,
         }
     }
 
-    //Temperature compensation can be added by providing the temp offset per degree
-    //IIRC .009 per degree off 25c (temperature-25*.009 added pH@4calc)
+    pub fn validate(&self) -> io::Result<()> {
+        let invalid = |message: &str| Err(io::Error::new(ErrorKind::InvalidInput, message.to_string()));
+        if self.ph7_cal == self.ph10_cal {
+            return invalid("pH 7 and pH 10 calibration values must differ");
+        }
+        if let Some(ph4) = self.ph4_cal {
+            // pH 4 has to be on the other side of pH 7 from pH 10
+            if (self.ph10_cal > self.ph7_cal) != (self.ph7_cal > ph4) || ph4 == self.ph7_cal {
+                return invalid("pH 4 calibration value must be on the other side of pH 7 from pH 10");
+            }
+        }
+        if self.gain.map_or(false, |gain| !(gain > 0.0)) {
+            return invalid("pH amp gain must be positive");
+        }
+        Ok(())
+    }
+
+    /// mV per pH unit at the calibration temperature. Negative when the signal falls as pH rises.
     pub fn get_step(&self) -> f32 {
         // RefVoltage * our deltaRawpH / 12bit steps *mV in V / OP-Amp gain /pH step difference 10-7
-        (V_REF * (self.ph10_cal as f32 - self.ph7_cal as f32) as f32) / STEP_12BIT * 1000.0 / OPAMP_GAIN / 3.0
+        (V_REF * (self.ph10_cal as f32 - self.ph7_cal as f32) as f32) / STEP_12BIT * 1000.0 / self.gain() / 3.0
+    }
+
+    fn gain(&self) -> f32 { self.gain.unwrap_or(UNSET_GAIN) }
+
+    /// Like `get_step`, between pH 4 and 7
+    pub fn get_acid_step(&self) -> Option<f32> {
+        self.ph4_cal.map(|ph4| (V_REF * (self.ph7_cal as f32 - ph4 as f32)) / STEP_12BIT * 1000.0 / self.gain() / 3.0)
+    }
+
+    /// The step at `temp`. The electrode's slope is proportional to absolute temperature (Nernst).
+    pub fn get_step_at(&self, temp: Temperature<C>) -> f32 {
+        self.get_step() * compensation(temp)
+    }
+
+    /// How the probe's slope compares to an ideal one, in percent. New probes are 95-105%.
+    /// None until the gain is set, as the slope means nothing without it.
+    pub fn efficiency(&self) -> Option<f32> {
+        self.gain.map(|_| self.get_step().abs() / IDEAL_SLOPE * 100.0)
+    }
+
+    pub fn acid_efficiency(&self) -> Option<f32> {
+        self.gain.and(self.get_acid_step()).map(|step| step.abs() / IDEAL_SLOPE * 100.0)
+    }
+
+    /// A warning when either slope is outside what a working probe reads, which is a worn
+    /// probe or a gain that doesn't match the board
+    pub fn efficiency_warning(&self) -> Option<String> {
+        self.efficiency().into_iter().chain(self.acid_efficiency())
+            .find(|efficiency| *efficiency < MIN_EFFICIENCY || *efficiency > MAX_EFFICIENCY)
+            .map(|efficiency| format!("pH probe efficiency is {:.0}%, outside {}-{}%. Check ph.ampGain matches the board, or the probe may need replacing",
+                                      efficiency, MIN_EFFICIENCY, MAX_EFFICIENCY))
     }
 }
 
+/// The water temperature pH is compensated with, as long as it keeps updating
+pub struct CompensationTemp {
+    temp: Signal<Option<Temperature<F>>>,
+    freshness: Freshness,
+}
+
+impl CompensationTemp {
+    pub fn new(temp_stream: &Stream<Temperature<F>>) -> CompensationTemp {
+        CompensationTemp {
+            temp: temp_stream.map(Some).hold(None),
+            freshness: Freshness::new(temp_stream, WATER_TEMP_STALE_S),
+        }
+    }
+
+    /// None without a reading in the last 2 minutes
+    pub fn sample(&mut self, tick_s: u64) -> Option<Temperature<C>> {
+        if self.freshness.is_stale(tick_s) {
+            None
+        } else {
+            self.temp.sample().map(|temp| temp.to_c())
+        }
+    }
+}
+
+/// What to compensate with when there's no water temperature
+pub fn calibration_temp() -> Temperature<C> { Temperature::in_c(CAL_TEMP_C) }
+
+fn compensation(temp: Temperature<C>) -> f32 {
+    (temp.value() + KELVIN) / (CAL_TEMP_C + KELVIN)
+}
+
+fn fault(message: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+// A reading at either end of the ADC's range means the probe is unplugged or the amp is
+// saturated, so there's no pH to report.
 #[allow(non_snake_case)]
-fn calc_ph(raw: u16, config: &PhConfig) -> pH {
-    info!("Raw pH: {:?}, step: {:?}", raw, config.get_step());
+fn calc_ph(raw: u16, config: &PhConfig, water_temp: Temperature<C>) -> io::Result<pH> {
+    if raw == 0 || raw as f32 >= STEP_12BIT - 1.0 {
+        return Err(fault(format!("pH ADC reading at its limit ({}), check the probe", raw)));
+    }
+    let step = config.get_step_at(water_temp);
+    info!("Raw pH: {:?}, step: {:?} at {:?}C", raw, step, water_temp.value());
     let mV: f32 = (raw as f32) / STEP_12BIT * V_REF * 1000.0;
-    let temp: f32 = ((V_REF * config.ph7_cal as f32 / STEP_12BIT * 1000.0) - mV) / OPAMP_GAIN;
-    let ph = 7.0 - (temp / config.get_step());
+    let temp: f32 = ((V_REF * config.ph7_cal as f32 / STEP_12BIT * 1000.0) - mV) / config.gain();
+    let mut ph = 7.0 - (temp / step);
+    if let Some(acid_step) = config.get_acid_step() {
+        if ph < 7.0 {
+            ph = 7.0 - (temp / (acid_step * compensation(water_temp)));
+        }
+    }
     info!("Calculated pH at {:?}", ph);
-    if ph > 10.0 {
-        warn!("pH greater than 10, truncating to 10");
-        return 10.0;
+    if !(ph >= 0.0 && ph <= 14.0) {
+        return Err(fault(format!("Calculated pH {} is outside 0-14, check the probe and calibration", ph)));
     }
-    ph
+    Ok(ph)
 }
 
 impl PhMonitor {
     pub fn new(i2c_device_id: u8, config: PhConfig) -> io::Result<PhMonitor> {
+        try!(config.validate());
         let mcp3221 = try!(Mcp3221::new(i2c_device_id));
         Ok(PhMonitor {
             mcp3221: mcp3221,
-            ph_avg: [8.0_f32; 20],
-            ph_index: 0,
             config: config,
         })
     }
 
-    pub fn get_ph(&mut self) -> io::Result<pH> {
+    /// pH compensated for the water being at `water_temp`
+    pub fn get_ph(&mut self, water_temp: Temperature<C>) -> io::Result<pH> {
         let raw: u16 = try!(self.mcp3221.sample());
 
-        let ph = calc_ph(raw, &self.config);
-        let avg = self.update_average_and_get(ph);
-        info!("Got raw ph reading: {:?}, avg: {:?}", raw, avg);
-        Ok((avg * 100.0).round() / 100.0)
+        // smoothing is up to the pH filter
+        let ph = try!(calc_ph(raw, &self.config, water_temp));
+        info!("Got raw ph reading: {:?}, pH: {:?}", raw, ph);
+        Ok(ph)
+    }
+
+    /// The averaged ADC counts, for calibrating
+    pub fn get_raw(&mut self) -> io::Result<u16> {
+        self.mcp3221.sample()
+    }
+
+    pub fn config(&self) -> &PhConfig { &self.config }
+
+    pub fn set_config(&mut self, config: PhConfig) {
+        self.config = config;
+    }
+}
+
+/// A step in a guided calibration
+#[derive(Debug, Clone, PartialEq)]
+pub enum PhCalibrationCommand {
+    /// Throws away any points captured so far
+    Start,
+    /// Samples the probe until it settles in the given buffer
+    Capture(pH),
+    /// Works out the new calibration from the captured points
+    Finish,
+    Cancel,
+}
+
+// a point is captured once this many samples in a row are within STABLE_COUNTS of each other
+const STABLE_SAMPLES: usize = 10;
+const STABLE_COUNTS: u16 = 2;
+// gives up on a buffer that hasn't settled after this many samples
+const MAX_SAMPLES: u32 = 180;
+const BUFFERS: [pH; 3] = [4.0, 7.0, 10.0];
+
+/// A calibration in progress. Each buffer's raw reading is captured once it stops drifting.
+#[derive(Debug, Clone)]
+pub struct PhCalibration {
+    points: Vec<(pH, u16)>,
+    sampling: Option<pH>,
+    recent: VecDeque<u16>,
+    samples: u32,
+    last_raw: Option<u16>,
+    error: Option<String>,
+}
+
+impl PhCalibration {
+    pub fn new() -> PhCalibration {
+        PhCalibration {
+            points: vec![],
+            sampling: None,
+            recent: VecDeque::new(),
+            samples: 0,
+            last_raw: None,
+            error: None,
+        }
+    }
+
+    pub fn capture(&mut self, buffer: pH) -> io::Result<()> {
+        if !BUFFERS.contains(&buffer) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("Calibration buffers are pH 4, 7 and 10, not {}", buffer)));
+        }
+        self.sampling = Some(buffer);
+        self.recent.clear();
+        self.samples = 0;
+        self.error = None;
+        Ok(())
+    }
+
+    pub fn add_sample(&mut self, raw: u16) {
+        let buffer = match self.sampling {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        self.last_raw = Some(raw);
+        self.samples += 1;
+        self.recent.push_back(raw);
+        while self.recent.len() > STABLE_SAMPLES {
+            self.recent.pop_front();
+        }
+
+        let min = *self.recent.iter().min().unwrap();
+        let max = *self.recent.iter().max().unwrap();
+        if self.recent.len() == STABLE_SAMPLES && max - min <= STABLE_COUNTS {
+            let sum = self.recent.iter().fold(0_u32, |a, b| a + *b as u32);
+            let raw = (sum as f32 / STABLE_SAMPLES as f32).round() as u16;
+            info!("Captured pH {} calibration point at {}", buffer, raw);
+            self.points.retain(|p| p.0 != buffer);
+            self.points.push((buffer, raw));
+            self.sampling = None;
+        } else if self.samples >= MAX_SAMPLES {
+            self.error = Some(format!("pH {} reading didn't settle, check the probe is in the buffer", buffer));
+            self.sampling = None;
+        }
     }
 
-    pub fn update_average_and_get(&mut self, ph: pH) -> pH {
-        self.ph_avg[self.ph_index] = ph;
-        self.ph_index += 1;
-        if self.ph_index >= self.ph_avg.len() { self.ph_index = 0; }
-        self.ph_avg.iter().fold(0.0, |a,b| a+b) / self.ph_avg.len() as f32
+    pub fn failed(&mut self, error: String) {
+        self.error = Some(error);
     }
+
+    /// Needs pH 7 and pH 10. pH 4 is optional. Needs the gain too, or the probe's efficiency
+    /// can't be judged.
+    pub fn finish(&self, gain: Option<f32>) -> io::Result<PhConfig> {
+        if gain.is_none() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "Set ph.ampGain to the board's amplifier gain before finishing a calibration"));
+        }
+        let point = |buffer: pH| self.points.iter().find(|p| p.0 == buffer).map(|p| p.1);
+        match (point(7.0), point(10.0)) {
+            (Some(ph7), Some(ph10)) => {
+                let config = PhConfig { ph7_cal: ph7, ph10_cal: ph10, ph4_cal: point(4.0), gain: gain };
+                try!(config.validate());
+                Ok(config)
+            },
+            _ => Err(io::Error::new(ErrorKind::InvalidInput, "Calibration needs pH 7 and pH 10 points")),
+        }
+    }
+
+    /// Captured (buffer, raw) points
+    pub fn points(&self) -> &[(pH, u16)] { &self.points }
+    /// The buffer being sampled, if any
+    pub fn sampling(&self) -> Option<pH> { self.sampling }
+    pub fn last_raw(&self) -> Option<u16> { self.last_raw }
+    pub fn error(&self) -> Option<&String> { self.error.as_ref() }
 }
 
 struct Mcp3221 {
@@ -118,12 +342,20 @@ impl Mcp3221 {
 mod test {
     use super::*;
 
+    fn assert_near(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 0.01, "expected {} but got {}", expected, actual);
+    }
+
+    fn at_25c(raw: u16, config: &PhConfig) -> pH {
+        calc_ph(raw, config, Temperature::in_c(25.0)).unwrap()
+    }
+
     #[test]
     pub fn step_calculation() {
         let config = PhConfig::default();
 
-        // should be very close to ideal of 59.16
-        assert_eq!(config.get_step(), 59.174606);
+        // this board's output falls as pH rises
+        assert_near(config.get_step(), -327.67);
     }
 
     #[test]
@@ -131,9 +363,10 @@ mod test {
         let config = PhConfig {
             ph7_cal: 2020,
             ph10_cal: 2999,
+            ..PhConfig::default()
         };
 
-        assert_eq!(config.get_step(), 62.158733);
+        assert_near(config.get_step(), 326.33);
     }
 
     #[test]
@@ -141,11 +374,10 @@ mod test {
         let config = PhConfig::default();
 
         // sanity check
-        assert_eq!(calc_ph(2048+932, &config), 10.0);
-        // now test a random sampling. We don't care about pH < 7 for our use
-        assert_eq!(calc_ph(2102, &config), 7.17);
-        assert_eq!(calc_ph(2500, &config), 8.45);
-        assert_eq!(calc_ph(2842, &config), 9.56);
+        assert_near(at_25c(2048, &config), 7.0);
+        assert_near(at_25c(1065, &config), 10.0);
+        assert_near(at_25c(1884, &config), 7.5);
+        assert_near(at_25c(1393, &config), 9.0);
     }
 
     #[test]
@@ -153,14 +385,116 @@ mod test {
         let config = PhConfig {
             ph7_cal: 2020,
             ph10_cal: 2999,
+            ..PhConfig::default()
         };
 
         // sanity check
-        assert_eq!(calc_ph(2020, &config), 7.0);
-        assert_eq!(calc_ph(2999, &config), 10.0);
-        // now test a random sampling. We don't care about pH < 7 for our use
-        assert_eq!(calc_ph(2102, &config), 7.25);
-        assert_eq!(calc_ph(2500, &config), 8.47);
-        assert_eq!(calc_ph(2842, &config), 9.52);
+        assert_near(at_25c(2020, &config), 7.0);
+        assert_near(at_25c(2999, &config), 10.0);
+        assert_near(at_25c(2102, &config), 7.25);
+        assert_near(at_25c(2842, &config), 9.52);
+    }
+
+    #[test]
+    pub fn warmer_water_has_a_steeper_slope() {
+        let config = PhConfig::default();
+
+        assert_near(calc_ph(1065, &config, Temperature::in_c(30.0)).unwrap(), 7.0 + 3.0 * 298.15 / 303.15);
+        // pH 7 is the isopotential point, so it doesn't move
+        assert_near(calc_ph(2048, &config, Temperature::in_c(30.0)).unwrap(), 7.0);
+    }
+
+    #[test]
+    pub fn faults_instead_of_truncating() {
+        let config = PhConfig::default();
+        let temp = Temperature::in_c(25.0);
+
+        assert!(calc_ph(0, &config, temp).is_err());
+        assert!(calc_ph(4095, &config, temp).is_err());
+        // a probe this far out of calibration reads well below 0
+        assert!(calc_ph(100, &PhConfig { ph7_cal: 2048, ph10_cal: 2148, ..PhConfig::default() }, temp).is_err());
+        assert!(PhConfig { ph7_cal: 2048, ph10_cal: 2048, ..PhConfig::default() }.validate().is_err());
+        assert!(PhConfig { ph4_cal: Some(1500), ..PhConfig::default() }.validate().is_err());
+    }
+
+    #[test]
+    pub fn three_point_uses_the_acid_slope_below_7() {
+        let config = PhConfig { ph4_cal: Some(3031), gain: Some(5.6), ..PhConfig::default() };
+
+        assert_near(at_25c(3031, &config), 4.0);
+        assert_near(at_25c(1065, &config), 10.0);
+        assert_near(config.acid_efficiency().unwrap(), config.efficiency().unwrap());
+    }
+
+    #[test]
+    pub fn efficiency_needs_the_gain() {
+        let config = PhConfig::default();
+        assert_eq!(config.efficiency(), None);
+        assert!(config.efficiency_warning().is_none());
+
+        // -327.67 mV/pH at the ADC is about 99% of ideal through a gain of 5.6
+        let config = PhConfig { gain: Some(5.6), ..PhConfig::default() };
+        assert_near(config.efficiency().unwrap(), 98.9);
+        assert!(config.efficiency_warning().is_none());
+
+        // a gain left at 1 reads far too high, a worn probe too low
+        assert!(PhConfig { gain: Some(1.0), ..PhConfig::default() }.efficiency_warning().is_some());
+        assert!(PhConfig { gain: Some(7.0), ..PhConfig::default() }.efficiency_warning().is_some());
+    }
+
+    #[test]
+    pub fn compensation_temp_goes_stale() {
+        use carboxyl::Sink;
+
+        let sink = Sink::new();
+        let mut temp = CompensationTemp::new(&sink.stream());
+        assert_eq!(temp.sample(5), None);
+
+        sink.send(Temperature::in_f(77.0));
+        assert_near(temp.sample(10).unwrap().value(), 25.0);
+        assert!(temp.sample(10 + WATER_TEMP_STALE_S).is_some());
+        assert_eq!(temp.sample(15 + WATER_TEMP_STALE_S), None);
+    }
+
+    fn settle(calibration: &mut PhCalibration, raw: u16) {
+        for _ in 0..STABLE_SAMPLES {
+            calibration.add_sample(raw);
+        }
+    }
+
+    #[test]
+    pub fn calibration_captures_once_settled() {
+        let mut calibration = PhCalibration::new();
+        assert!(calibration.capture(8.0).is_err());
+
+        calibration.capture(7.0).unwrap();
+        // still drifting
+        for raw in [2000, 2010, 2020, 2030].iter() {
+            calibration.add_sample(*raw);
+        }
+        assert_eq!(calibration.sampling(), Some(7.0));
+        settle(&mut calibration, 2040);
+        assert_eq!(calibration.points(), &[(7.0, 2040)]);
+        assert!(calibration.finish(Some(1.0)).is_err());
+
+        calibration.capture(10.0).unwrap();
+        settle(&mut calibration, 2340);
+        assert!(calibration.finish(None).is_err());
+        let config = calibration.finish(Some(5.0)).unwrap();
+        assert_eq!(config, PhConfig { ph7_cal: 2040, ph10_cal: 2340, ph4_cal: None, gain: Some(5.0) });
+        assert_near(config.get_step(), 20.0);
+    }
+
+    #[test]
+    pub fn calibration_gives_up_on_a_drifting_probe() {
+        let mut calibration = PhCalibration::new();
+        calibration.capture(4.0).unwrap();
+        for i in 0..MAX_SAMPLES {
+            calibration.add_sample(1000 + (i % 2) as u16 * 10);
+        }
+
+        assert_eq!(calibration.sampling(), None);
+        assert!(calibration.points().is_empty());
+        assert!(calibration.error().is_some());
     }
 }
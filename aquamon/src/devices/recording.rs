@@ -0,0 +1,417 @@
+use ::uom::temp::*;
+
+use std::fs::File;
+use std::io as io;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::Path;
+
+use carboxyl::{Sink, Stream};
+use chrono::prelude::*;
+use chrono::Duration;
+
+use super::{Depth, Humidity, pH};
+use super::health::{SensorHealth, in_range};
+use super::calibration::TempCalibration;
+use super::hardware::{TemperatureSource, DepthSource, AirSource, PhSource, LightChannels, Hardware};
+
+/// A single value pushed into one of the sensor streams
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reading {
+    Temp(Temperature<F>),
+    /// A reading from one of the named water probes
+    Probe(String, Temperature<F>),
+    Depth(Depth),
+    AirTemp(Temperature<F>),
+    Humidity(Humidity),
+    Ph(pH),
+}
+
+impl Reading {
+    /// Name of the sensor the reading came from, as written in recordings
+    pub fn sensor(&self) -> String {
+        match *self {
+            Reading::Temp(_) => "temp".to_string(),
+            Reading::Probe(ref name, _) => format!("probe:{}", name),
+            Reading::Depth(_) => "depth".to_string(),
+            Reading::AirTemp(_) => "airTemp".to_string(),
+            Reading::Humidity(_) => "humidity".to_string(),
+            Reading::Ph(_) => "ph".to_string(),
+        }
+    }
+}
+
+/// A reading along with the tick it was sent on and the wall-clock time it was recorded
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub ticks: u64,
+    pub time: DateTime<Local>,
+    pub reading: Reading,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+impl Sample {
+    /// One line of a recording: `ticks,time,sensor,value`
+    pub fn to_line(&self) -> String {
+        let value = match self.reading {
+            Reading::Temp(temp) | Reading::Probe(_, temp) | Reading::AirTemp(temp) => temp.value().to_string(),
+            Reading::Depth(depth) => depth.to_string(),
+            Reading::Humidity(humidity) => humidity.to_string(),
+            Reading::Ph(ph) => ph.to_string(),
+        };
+        format!("{},{},{},{}", self.ticks, self.time.to_rfc3339(), self.reading.sensor(), value)
+    }
+
+    pub fn parse(line: &str) -> io::Result<Sample> {
+        let fields: Vec<&str> = line.trim().split(',').collect();
+        if fields.len() != 4 {
+            return Err(invalid(format!("Expected ticks,time,sensor,value but got: {}", line)));
+        }
+        let ticks = try!(fields[0].parse::<u64>().map_err(|e| invalid(format!("Bad ticks {}: {}", fields[0], e))));
+        let time = try!(DateTime::parse_from_rfc3339(fields[1]).map_err(|e| invalid(format!("Bad time {}: {}", fields[1], e))));
+        let value = fields[3];
+        let reading = match fields[2] {
+            "temp" => Reading::Temp(Temperature::in_f(try!(parse_value(value)))),
+            "depth" => Reading::Depth(try!(value.parse::<Depth>().map_err(|e| invalid(format!("Bad depth {}: {}", value, e))))),
+            "airTemp" => Reading::AirTemp(Temperature::in_f(try!(parse_value(value)))),
+            "humidity" => Reading::Humidity(try!(parse_value(value))),
+            "ph" => Reading::Ph(try!(parse_value(value))),
+            sensor if sensor.starts_with("probe:") => Reading::Probe(sensor["probe:".len()..].to_string(), Temperature::in_f(try!(parse_value(value)))),
+            sensor => return Err(invalid(format!("Unknown sensor: {}", sensor))),
+        };
+        Ok(Sample { ticks: ticks, time: time.with_timezone(&Local), reading: reading })
+    }
+}
+
+fn parse_value(value: &str) -> io::Result<f32> {
+    value.parse::<f32>().map_err(|e| invalid(format!("Bad value {}: {}", value, e)))
+}
+
+/// Appends every reading to a file, one `Sample` line each
+pub struct Recorder {
+    out: Box<Write>,
+}
+
+impl Recorder {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Recorder> {
+        let file = try!(File::create(path));
+        Ok(Recorder::new(Box::new(file)))
+    }
+
+    pub fn new(out: Box<Write>) -> Recorder {
+        Recorder { out: out }
+    }
+
+    pub fn record(&mut self, ticks: u64, reading: &Reading) -> io::Result<()> {
+        let sample = Sample { ticks: ticks, time: Local::now(), reading: reading.clone() };
+        writeln!(self.out, "{}", sample.to_line())
+    }
+}
+
+/// The sinks behind the sensor streams of a hardware backend. Everything sent through
+/// here is also written to the recorder, if there is one, and counts towards the
+/// sensor's health. Temperatures are calibrated and implausible readings are kept out
+/// of the streams.
+pub struct SensorSinks {
+    temp: Sink<Temperature<F>>,
+    depth: Sink<Depth>,
+    air_temp: Sink<Temperature<F>>,
+    humidity: Sink<Humidity>,
+    ph: Sink<pH>,
+    probes: Vec<(String, Sink<Temperature<F>>)>,
+    health: Vec<SensorHealth>,
+    calibrations: Vec<(String, TempCalibration)>,
+    recorder: Option<Recorder>,
+}
+
+impl SensorSinks {
+    pub fn new() -> SensorSinks {
+        SensorSinks {
+            temp: Sink::new(),
+            depth: Sink::new(),
+            air_temp: Sink::new(),
+            humidity: Sink::new(),
+            ph: Sink::new(),
+            probes: vec![],
+            health: ["temp", "depth", "airTemp", "humidity", "ph"].iter().map(|s| SensorHealth::new(s)).collect(),
+            calibrations: vec![],
+            recorder: None,
+        }
+    }
+
+    pub fn record_to(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Adds a stream for the named probe, if there isn't one already
+    pub fn add_probe(&mut self, name: &str) {
+        if !self.probes.iter().any(|p| p.0 == name) {
+            self.probes.push((name.to_string(), Sink::new()));
+            self.health.push(SensorHealth::new(&format!("probe:{}", name)));
+        }
+    }
+
+    /// Corrects readings from the temperature sensor `sensor`, named like `Reading::sensor`
+    pub fn calibrate(&mut self, sensor: &str, calibration: TempCalibration) {
+        self.calibrations.retain(|c| c.0 != sensor);
+        self.calibrations.push((sensor.to_string(), calibration));
+    }
+
+    fn calibrated(&self, reading: Reading) -> Reading {
+        let calibration = match self.calibrations.iter().find(|c| c.0 == reading.sensor()) {
+            Some(c) => c.1,
+            None => return reading,
+        };
+        match reading {
+            Reading::Temp(temp) => Reading::Temp(calibration.apply(temp)),
+            Reading::Probe(name, temp) => Reading::Probe(name, calibration.apply(temp)),
+            Reading::AirTemp(temp) => Reading::AirTemp(calibration.apply(temp)),
+            reading => reading,
+        }
+    }
+
+    /// Leaves `sensor` out of the health report until it has a reading, for sensors that
+    /// are switched off
+    pub fn ignore(&mut self, sensor: &str) {
+        self.health.retain(|h| h.sensor != sensor);
+    }
+
+    fn health_mut(&mut self, sensor: &str) -> &mut SensorHealth {
+        if !self.health.iter().any(|h| h.sensor == sensor) {
+            self.health.push(SensorHealth::new(sensor));
+        }
+        self.health.iter_mut().find(|h| h.sensor == sensor).unwrap()
+    }
+
+    /// Counts a failed read against `sensor`, named like `Reading::sensor`
+    pub fn fail(&mut self, sensor: &str, error: String) {
+        error!("Could not read {}: {}", sensor, error);
+        self.health_mut(sensor).failed(error);
+    }
+
+    /// Flags readings from `sensor` as suspect, or clears the flag with None
+    pub fn warn(&mut self, sensor: &str, warning: Option<String>) {
+        self.health_mut(sensor).warning = warning;
+    }
+
+    pub fn health(&self) -> Vec<SensorHealth> {
+        self.health.clone()
+    }
+
+    pub fn send(&mut self, ticks: u64, reading: Reading) {
+        // recorded as read so a replay goes through the same checks
+        self.record(ticks, &reading);
+        let reading = self.calibrated(reading);
+
+        if !in_range(&reading) {
+            warn!("Ignoring out of range reading {:?}", reading);
+            self.health_mut(&reading.sensor()).rejected(&reading);
+            return;
+        }
+        self.health_mut(&reading.sensor()).good(ticks);
+
+        match reading {
+            Reading::Temp(temp) => self.temp.send(temp),
+            Reading::Probe(ref name, temp) => {
+                self.add_probe(name);
+                if let Some(probe) = self.probes.iter().find(|p| p.0 == *name) {
+                    probe.1.send(temp);
+                }
+            },
+            Reading::Depth(depth) => self.depth.send(depth),
+            Reading::AirTemp(temp) => self.air_temp.send(temp),
+            Reading::Humidity(humidity) => self.humidity.send(humidity),
+            Reading::Ph(ph) => self.ph.send(ph),
+        }
+    }
+
+    fn record(&mut self, ticks: u64, reading: &Reading) {
+        // a full disk shouldn't stop the tank from being controlled
+        let failed = match self.recorder {
+            Some(ref mut recorder) => recorder.record(ticks, reading).err(),
+            None => None,
+        };
+        if let Some(err) = failed {
+            error!("Could not record reading, recording stopped: {}", err);
+            self.recorder = None;
+        }
+    }
+
+    pub fn temp_stream(&self) -> Stream<Temperature<F>> { self.temp.stream() }
+    pub fn depth_stream(&self) -> Stream<Depth> { self.depth.stream() }
+    pub fn air_temp_stream(&self) -> Stream<Temperature<F>> { self.air_temp.stream() }
+    pub fn humidity_stream(&self) -> Stream<Humidity> { self.humidity.stream() }
+    pub fn ph_stream(&self) -> Stream<pH> { self.ph.stream() }
+
+    pub fn probe_streams(&self) -> Vec<(String, Stream<Temperature<F>>)> {
+        self.probes.iter().map(|p| (p.0.clone(), p.1.stream())).collect()
+    }
+}
+
+/// Plays a recording back through the sensor streams. Each reading is sent on the first
+/// tick at or after the one it was recorded on, so controllers see the same sequence
+/// they did when it was recorded.
+pub struct Replay {
+    samples: Vec<Sample>,
+    next: usize,
+    sinks: SensorSinks,
+}
+
+impl Replay {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Replay> {
+        let file = try!(File::open(path));
+        Replay::from_reader(BufReader::new(file))
+    }
+
+    pub fn from_reader<R: BufRead>(reader: R) -> io::Result<Replay> {
+        let mut samples = vec![];
+        for line in reader.lines() {
+            let line = try!(line);
+            if line.trim().is_empty() { continue; }
+            samples.push(try!(Sample::parse(&line)));
+        }
+        Ok(Replay::new(samples))
+    }
+
+    pub fn new(samples: Vec<Sample>) -> Replay {
+        // probe streams have to exist before anything subscribes to them
+        let mut sinks = SensorSinks::new();
+        for sample in samples.iter() {
+            if let Reading::Probe(ref name, _) = sample.reading {
+                sinks.add_probe(name);
+            }
+        }
+        Replay { samples: samples, next: 0, sinks: sinks }
+    }
+
+    /// Wall-clock time of tick 0 in the recording, to start a `TickClock` at
+    pub fn start(&self) -> Option<DateTime<Local>> {
+        self.samples.first().map(|s| s.time - Duration::milliseconds(s.ticks as i64))
+    }
+
+    pub fn finished(&self) -> bool {
+        self.next >= self.samples.len()
+    }
+
+    /// Recordings are uncalibrated, so this is applied as they're replayed
+    pub fn calibrate(&mut self, sensor: &str, calibration: TempCalibration) {
+        self.sinks.calibrate(sensor, calibration);
+    }
+}
+
+impl Hardware for Replay {
+    fn tick(&mut self, ticks: u64) {
+        while self.next < self.samples.len() && self.samples[self.next].ticks <= ticks {
+            let sample = self.samples[self.next].clone();
+            self.sinks.send(sample.ticks, sample.reading);
+            self.next += 1;
+        }
+    }
+
+    fn sensor_health(&self) -> Vec<SensorHealth> { self.sinks.health() }
+}
+
+impl LightChannels for Replay {
+    fn set_intensities(&mut self, _values: &[u8; 6]) -> io::Result<()> { Ok(()) }
+}
+
+impl TemperatureSource for Replay {
+    fn temp_stream(&self) -> Stream<Temperature<F>> { self.sinks.temp_stream() }
+    fn probe_streams(&self) -> Vec<(String, Stream<Temperature<F>>)> { self.sinks.probe_streams() }
+}
+
+impl DepthSource for Replay {
+    fn depth_stream(&self) -> Stream<Depth> { self.sinks.depth_stream() }
+}
+
+impl AirSource for Replay {
+    fn air_temp_stream(&self) -> Stream<Temperature<F>> { self.sinks.air_temp_stream() }
+    fn humidity_stream(&self) -> Stream<Humidity> { self.sinks.humidity_stream() }
+}
+
+impl PhSource for Replay {
+    fn ph_stream(&self) -> Stream<pH> { self.sinks.ph_stream() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    // a Write that can still be read after the recorder owns it
+    #[derive(Clone)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.0.lock().unwrap().write(buf) }
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn sample_round_trips() {
+        let sample = Sample { ticks: 6500, time: Local.ymd(2018, 3, 1).and_hms(7, 0, 0), reading: Reading::AirTemp(Temperature::in_f(72.5)) };
+        let parsed = Sample::parse(&sample.to_line()).unwrap();
+
+        assert_eq!(parsed.ticks, 6500);
+        assert_eq!(parsed.time, sample.time);
+        assert_eq!(parsed.reading, sample.reading);
+    }
+
+    #[test]
+    fn bad_lines_are_rejected() {
+        assert!(Sample::parse("1000,2018-03-01T07:00:00-05:00,depth").is_err());
+        assert!(Sample::parse("1000,2018-03-01T07:00:00-05:00,salinity,35").is_err());
+        assert_eq!(Sample::parse("1000,yesterday,depth,400").unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn replay_sends_what_was_recorded() {
+        let buf = SharedBuf(Arc::new(Mutex::new(vec![])));
+        let mut sinks = SensorSinks::new();
+        sinks.record_to(Recorder::new(Box::new(buf.clone())));
+        sinks.send(0, Reading::Depth(400));
+        sinks.send(0, Reading::Temp(Temperature::in_f(78.0)));
+        sinks.send(1000, Reading::Depth(399));
+        sinks.send(1000, Reading::Probe("Sump".to_string(), Temperature::in_f(77.5)));
+
+        let recording = buf.0.lock().unwrap().clone();
+        let mut replay = Replay::from_reader(&recording[..]).unwrap();
+        let depth = replay.depth_stream().hold(0);
+        let temp = replay.temp_stream().hold(Temperature::in_f(0.0));
+        let (name, sump) = replay.probe_streams().remove(0);
+        let sump = sump.hold(Temperature::in_f(0.0));
+        assert_eq!(name, "Sump");
+
+        replay.tick(0);
+        assert_eq!(depth.sample(), 400);
+        assert_eq!(temp.sample(), Temperature::in_f(78.0));
+
+        replay.tick(990);
+        assert_eq!(depth.sample(), 400);
+        assert!(!replay.finished());
+
+        replay.tick(1000);
+        assert_eq!(depth.sample(), 399);
+        assert_eq!(sump.sample(), Temperature::in_f(77.5));
+        assert!(replay.finished());
+    }
+
+    #[test]
+    fn calibration_is_applied_before_the_range_check() {
+        let mut sinks = SensorSinks::new();
+        sinks.calibrate("probe:Sump", TempCalibration::offset(-0.5));
+        sinks.calibrate("temp", TempCalibration::offset(30.0));
+        sinks.add_probe("Sump");
+        let sump = sinks.probe_streams().remove(0).1.hold(Temperature::in_f(0.0));
+        let temp = sinks.temp_stream().hold(Temperature::in_f(0.0));
+
+        sinks.send(0, Reading::Probe("Sump".to_string(), Temperature::in_f(78.0)));
+        sinks.send(0, Reading::Temp(Temperature::in_f(78.0)));
+
+        assert_eq!(sump.sample(), Temperature::in_f(77.5));
+        assert_eq!(temp.sample(), Temperature::in_f(0.0));
+        assert_eq!(sinks.health().iter().find(|h| h.sensor == "temp").unwrap().out_of_range, 1);
+    }
+}
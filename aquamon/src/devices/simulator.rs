@@ -0,0 +1,271 @@
+use ::uom::temp::*;
+use ::controller::Outputs;
+
+use std::io as io;
+
+use carboxyl::Stream;
+
+use super::{Depth, Humidity, pH};
+use super::{Reading, Recorder, SensorSinks, SensorHealth};
+use super::{MemoryRelay, TemperatureSource, DepthSource, AirSource, PhSource, LightChannels, Hardware};
+
+const CU_IN_PER_GALLON: f32 = 231.0;
+const ML_PER_GALLON: f32 = 3785.41;
+const BTU_PER_WATT_HOUR: f32 = 3.412;
+const LB_PER_GALLON: f32 = 8.34;
+
+/// The physical tank being simulated
+pub struct SimulatorConfig {
+    pub tank_volume_gallons: f32,
+    pub tank_surface_area: f32,
+    pub ambient: Temperature<F>,
+    pub humidity: Humidity,
+    pub ph: pH,
+    pub heater_watts: f32,
+    pub chiller_watts: f32,
+    /// Fraction of the difference between water and ambient temperature lost per hour
+    pub heat_loss_per_hour: f32,
+    pub evaporation_gph: f32,
+    pub ato_pump_gph: f32,
+    pub doser_ml_min: f32,
+    /// Raw sensor reading with the water at the empty/reference mark
+    pub depth_low: Depth,
+    /// Raw sensor reading at `depth_high_inches` above the reference mark
+    pub depth_high: Depth,
+    pub depth_high_inches: f32,
+    pub start_temp: Temperature<F>,
+    pub start_level_inches: f32,
+    /// Named water probes, all reading the simulated water temperature
+    pub probes: Vec<String>,
+}
+
+impl SimulatorConfig {
+    pub fn default() -> SimulatorConfig {
+        SimulatorConfig {
+            tank_volume_gallons: 40.0,
+            tank_surface_area: 36.0 * 15.0,
+            ambient: Temperature::in_f(74.0),
+            humidity: 55.0,
+            ph: 8.2,
+            heater_watts: 150.0,
+            chiller_watts: 200.0,
+            heat_loss_per_hour: 0.1,
+            evaporation_gph: 0.05,
+            ato_pump_gph: 50.0,
+            doser_ml_min: 1.1,
+            depth_low: 0,
+            depth_high: 4096,
+            depth_high_inches: 10.0,
+            start_temp: Temperature::in_f(78.0),
+            start_level_inches: 5.0,
+            probes: vec!["Display".to_string(), "Sump".to_string()],
+        }
+    }
+}
+
+struct Relays {
+    heater: MemoryRelay,
+    ato: MemoryRelay,
+    cooler: MemoryRelay,
+    return_pump: MemoryRelay,
+    fuge_light: MemoryRelay,
+    doser: MemoryRelay,
+}
+
+/// A tank without hardware. Models water temperature, evaporation, ATO refill and doser
+/// flow from the state of the outputs handed to the controller, and publishes readings
+/// on the same schedule as `Devices`.
+pub struct Simulator {
+    config: SimulatorConfig,
+    relays: Relays,
+    water_temp_f: f32,
+    level_inches: f32,
+    dosed_ml: f32,
+    last_tick: u64,
+    sinks: SensorSinks,
+    last_intensities: [u8; 6],
+}
+
+impl Simulator {
+    pub fn new(config: SimulatorConfig) -> Simulator {
+        let mut sinks = SensorSinks::new();
+        for name in config.probes.iter() {
+            sinks.add_probe(name);
+        }
+        Simulator {
+            water_temp_f: config.start_temp.value(),
+            level_inches: config.start_level_inches,
+            config: config,
+            relays: Relays {
+                heater: MemoryRelay::new(),
+                ato: MemoryRelay::new(),
+                cooler: MemoryRelay::new(),
+                return_pump: MemoryRelay::new(),
+                fuge_light: MemoryRelay::new(),
+                doser: MemoryRelay::new(),
+            },
+            dosed_ml: 0.0,
+            last_tick: 0,
+            sinks: sinks,
+            last_intensities: [0_u8; 6],
+        }
+    }
+
+    /// Outputs wired to this simulator, to be handed to the `AquariumController`
+    pub fn outputs(&self) -> Outputs {
+        Outputs {
+            heater: Box::new(self.relays.heater.clone()),
+            ato: Box::new(self.relays.ato.clone()),
+            cooler: Box::new(self.relays.cooler.clone()),
+            return_pump: Box::new(self.relays.return_pump.clone()),
+            fuge_light: Box::new(self.relays.fuge_light.clone()),
+            doser: Box::new(self.relays.doser.clone()),
+            backup_heater: None,
+            fan: None,
+        }
+    }
+
+    /// Writes every simulated reading to `recorder` from now on
+    pub fn record_to(&mut self, recorder: Recorder) {
+        self.sinks.record_to(recorder);
+    }
+
+    pub fn water_temp(&self) -> Temperature<F> { Temperature::in_f(self.water_temp_f) }
+
+    pub fn level_inches(&self) -> f32 { self.level_inches }
+
+    pub fn dosed_ml(&self) -> f32 { self.dosed_ml }
+
+    pub fn intensities(&self) -> [u8; 6] { self.last_intensities }
+
+    pub fn depth(&self) -> Depth {
+        let steps_per_in = (self.config.depth_high as f32 - self.config.depth_low as f32) / self.config.depth_high_inches;
+        let depth = self.config.depth_low as f32 + self.level_inches * steps_per_in;
+        depth.max(0.0).min(Depth::max_value() as f32).round() as Depth
+    }
+
+    fn step(&mut self, elapsed_ms: u64) {
+        let hours = elapsed_ms as f32 / 1000.0 / 60.0 / 60.0;
+
+        let mut watts = 0.0;
+        if self.relays.heater.is_on() { watts += self.config.heater_watts; }
+        if self.relays.cooler.is_on() { watts -= self.config.chiller_watts; }
+        let water_lb = self.config.tank_volume_gallons * LB_PER_GALLON;
+        let loss_f = (self.water_temp_f - self.config.ambient.value()) * self.config.heat_loss_per_hour * hours;
+        self.water_temp_f += watts * BTU_PER_WATT_HOUR * hours / water_lb - loss_f;
+
+        let mut gallons = -self.config.evaporation_gph * hours;
+        if self.relays.ato.is_on() { gallons += self.config.ato_pump_gph * hours; }
+        if self.relays.doser.is_on() {
+            let ml = self.config.doser_ml_min * 60.0 * hours;
+            self.dosed_ml += ml;
+            gallons += ml / ML_PER_GALLON;
+        }
+        self.level_inches += gallons * CU_IN_PER_GALLON / self.config.tank_surface_area;
+    }
+}
+
+impl Hardware for Simulator {
+    fn tick(&mut self, ticks: u64) {
+        let elapsed = ticks.saturating_sub(self.last_tick);
+        self.last_tick = ticks;
+        self.step(elapsed);
+
+        // same cadence as the real devices
+        if ticks % 6500 == 0 {
+            let temp = self.water_temp();
+            self.sinks.send(ticks, Reading::Temp(temp));
+            for name in self.config.probes.iter() {
+                self.sinks.send(ticks, Reading::Probe(name.clone(), temp));
+            }
+            self.sinks.send(ticks, Reading::AirTemp(self.config.ambient));
+            self.sinks.send(ticks, Reading::Humidity(self.config.humidity));
+            self.sinks.send(ticks, Reading::Ph(self.config.ph));
+        }
+
+        if ticks % 1000 == 0 {
+            self.sinks.send(ticks, Reading::Depth(self.depth()));
+        }
+    }
+
+    fn sensor_health(&self) -> Vec<SensorHealth> { self.sinks.health() }
+}
+
+impl LightChannels for Simulator {
+    fn set_intensities(&mut self, values: &[u8; 6]) -> io::Result<()> {
+        self.last_intensities = *values;
+        Ok(())
+    }
+}
+
+impl TemperatureSource for Simulator {
+    fn temp_stream(&self) -> Stream<Temperature<F>> { self.sinks.temp_stream() }
+    fn probe_streams(&self) -> Vec<(String, Stream<Temperature<F>>)> { self.sinks.probe_streams() }
+}
+
+impl DepthSource for Simulator {
+    fn depth_stream(&self) -> Stream<Depth> { self.sinks.depth_stream() }
+}
+
+impl AirSource for Simulator {
+    fn air_temp_stream(&self) -> Stream<Temperature<F>> { self.sinks.air_temp_stream() }
+    fn humidity_stream(&self) -> Stream<Humidity> { self.sinks.humidity_stream() }
+}
+
+impl PhSource for Simulator {
+    fn ph_stream(&self) -> Stream<pH> { self.sinks.ph_stream() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const HOUR_MS: u64 = 60 * 60 * 1000;
+
+    #[test]
+    fn heater_warms_the_water() {
+        let mut sim = Simulator::new(SimulatorConfig::default());
+        let mut outputs = sim.outputs();
+        outputs.heater.turn_on().unwrap();
+
+        sim.tick(HOUR_MS);
+
+        assert!(sim.water_temp() > Temperature::in_f(78.0));
+    }
+
+    #[test]
+    fn water_drifts_toward_ambient() {
+        let mut sim = Simulator::new(SimulatorConfig::default());
+
+        sim.tick(HOUR_MS);
+
+        assert!(sim.water_temp() < Temperature::in_f(78.0));
+        assert!(sim.water_temp() > Temperature::in_f(74.0));
+    }
+
+    #[test]
+    fn evaporation_lowers_depth_and_ato_refills() {
+        let mut sim = Simulator::new(SimulatorConfig::default());
+        let start = sim.depth();
+
+        sim.tick(10 * HOUR_MS);
+        let evaporated = sim.depth();
+        assert!(evaporated < start);
+
+        let mut outputs = sim.outputs();
+        outputs.ato.turn_on().unwrap();
+        sim.tick(10 * HOUR_MS + 60 * 1000);
+        assert!(sim.depth() > evaporated);
+    }
+
+    #[test]
+    fn doser_tracks_volume() {
+        let mut sim = Simulator::new(SimulatorConfig::default());
+        let mut outputs = sim.outputs();
+        outputs.doser.turn_on().unwrap();
+
+        sim.tick(60 * 1000);
+
+        assert!((sim.dosed_ml() - 1.1).abs() < 0.01);
+    }
+}
@@ -0,0 +1,263 @@
+use ::uom::temp::*;
+use ::devices::Depth;
+
+use std::collections::VecDeque;
+use std::io as io;
+use std::io::ErrorKind;
+use std::sync::Mutex;
+
+use carboxyl::Stream;
+
+/// How readings from one sensor are smoothed before anything sees them
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterConfig {
+    /// Passes readings through untouched
+    None,
+    /// Mean of the last `window` readings
+    MovingAverage(usize),
+    /// Each reading moves the output `alpha` of the way towards it
+    Exponential(f32),
+    /// Median of the last `window` readings
+    Median(usize),
+    /// Drops readings more than `max_step` from the last one let through. After `limit`
+    /// drops in a row the level is assumed to have really moved and the reading is kept.
+    RejectSpikes { max_step: f32, limit: usize },
+    /// Each filter feeds the next
+    Chain(Vec<FilterConfig>),
+}
+
+pub trait Filter: Send {
+    /// The filtered value, or `None` if the reading should be dropped
+    fn next(&mut self, value: f32) -> Option<f32>;
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, message)
+}
+
+impl FilterConfig {
+    pub fn validate(&self) -> io::Result<()> {
+        match *self {
+            FilterConfig::MovingAverage(0) | FilterConfig::Median(0) =>
+                Err(invalid(format!("{:?} needs a window of at least 1", self))),
+            FilterConfig::Exponential(alpha) if !(alpha > 0.0 && alpha <= 1.0) =>
+                Err(invalid(format!("Exponential alpha must be in (0, 1]: {}", alpha))),
+            FilterConfig::RejectSpikes { max_step, .. } if !(max_step > 0.0) =>
+                Err(invalid(format!("Spike rejection needs a positive max step: {}", max_step))),
+            FilterConfig::Chain(ref filters) => {
+                for filter in filters.iter() {
+                    try!(filter.validate());
+                }
+                Ok(())
+            },
+            _ => Ok(()),
+        }
+    }
+
+    pub fn build(&self) -> Box<Filter> {
+        match *self {
+            FilterConfig::None => Box::new(Chain { filters: vec![] }),
+            FilterConfig::MovingAverage(window) => Box::new(MovingAverage { window: window, values: VecDeque::new() }),
+            FilterConfig::Exponential(alpha) => Box::new(Exponential { alpha: alpha, value: None }),
+            FilterConfig::Median(window) => Box::new(Median { window: window, values: VecDeque::new() }),
+            FilterConfig::RejectSpikes { max_step, limit } =>
+                Box::new(RejectSpikes { max_step: max_step, limit: limit, last: None, rejected: 0 }),
+            FilterConfig::Chain(ref filters) => Box::new(Chain { filters: filters.iter().map(|f| f.build()).collect() }),
+        }
+    }
+}
+
+// keeps the last `window` values
+fn push(values: &mut VecDeque<f32>, window: usize, value: f32) {
+    values.push_back(value);
+    while values.len() > window {
+        values.pop_front();
+    }
+}
+
+struct MovingAverage {
+    window: usize,
+    values: VecDeque<f32>,
+}
+
+impl Filter for MovingAverage {
+    fn next(&mut self, value: f32) -> Option<f32> {
+        push(&mut self.values, self.window, value);
+        Some(self.values.iter().fold(0.0, |a, b| a + b) / self.values.len() as f32)
+    }
+}
+
+struct Exponential {
+    alpha: f32,
+    value: Option<f32>,
+}
+
+impl Filter for Exponential {
+    fn next(&mut self, value: f32) -> Option<f32> {
+        let next = match self.value {
+            Some(last) => last + self.alpha * (value - last),
+            None => value,
+        };
+        self.value = Some(next);
+        self.value
+    }
+}
+
+struct Median {
+    window: usize,
+    values: VecDeque<f32>,
+}
+
+impl Filter for Median {
+    fn next(&mut self, value: f32) -> Option<f32> {
+        push(&mut self.values, self.window, value);
+        // a NaN can't be ordered, and isn't a reading anyway
+        let mut sorted: Vec<f32> = self.values.iter().cloned().filter(|v| v.is_finite()).collect();
+        if sorted.is_empty() {
+            return None;
+        }
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let mid = sorted.len() / 2;
+        if sorted.len() % 2 == 0 {
+            Some((sorted[mid - 1] + sorted[mid]) / 2.0)
+        } else {
+            Some(sorted[mid])
+        }
+    }
+}
+
+struct RejectSpikes {
+    max_step: f32,
+    limit: usize,
+    last: Option<f32>,
+    rejected: usize,
+}
+
+impl Filter for RejectSpikes {
+    fn next(&mut self, value: f32) -> Option<f32> {
+        if let Some(last) = self.last {
+            if (value - last).abs() > self.max_step && self.rejected < self.limit {
+                self.rejected += 1;
+                return None;
+            }
+        }
+        self.rejected = 0;
+        self.last = Some(value);
+        self.last
+    }
+}
+
+struct Chain {
+    filters: Vec<Box<Filter>>,
+}
+
+impl Filter for Chain {
+    fn next(&mut self, value: f32) -> Option<f32> {
+        let mut value = value;
+        for filter in self.filters.iter_mut() {
+            value = match filter.next(value) {
+                Some(value) => value,
+                None => return None,
+            };
+        }
+        Some(value)
+    }
+}
+
+/// A sensor value that can be run through a `Filter`
+pub trait Filterable {
+    fn to_f32(&self) -> f32;
+    fn from_f32(value: f32) -> Self;
+}
+
+impl Filterable for Temperature<F> {
+    fn to_f32(&self) -> f32 { self.value() }
+    fn from_f32(value: f32) -> Temperature<F> { Temperature::in_f(value) }
+}
+
+impl Filterable for Depth {
+    fn to_f32(&self) -> f32 { *self as f32 }
+    fn from_f32(value: f32) -> Depth { value.round().max(0.0).min(Depth::max_value() as f32) as Depth }
+}
+
+impl Filterable for f32 {
+    fn to_f32(&self) -> f32 { *self }
+    fn from_f32(value: f32) -> f32 { value }
+}
+
+/// The readings on `stream` run through a new filter built from `config`
+pub fn apply<A>(stream: &Stream<A>, config: &FilterConfig) -> Stream<A>
+    where A: Filterable + Clone + Send + Sync + 'static {
+    let filter = Mutex::new(config.build());
+    stream.filter_map(move |reading: A| filter.lock().unwrap().next(reading.to_f32()).map(A::from_f32))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use carboxyl::Sink;
+
+    fn run(config: FilterConfig, values: &[f32]) -> Vec<Option<f32>> {
+        let mut filter = config.build();
+        values.iter().map(|v| filter.next(*v)).collect()
+    }
+
+    #[test]
+    fn moving_average_uses_what_it_has_until_full() {
+        assert_eq!(run(FilterConfig::MovingAverage(3), &[3.0, 6.0, 9.0, 12.0]),
+                   vec![Some(3.0), Some(4.5), Some(6.0), Some(9.0)]);
+    }
+
+    #[test]
+    fn exponential_starts_at_first_reading() {
+        assert_eq!(run(FilterConfig::Exponential(0.5), &[10.0, 20.0, 20.0]),
+                   vec![Some(10.0), Some(15.0), Some(17.5)]);
+    }
+
+    #[test]
+    fn median_ignores_a_single_outlier() {
+        assert_eq!(run(FilterConfig::Median(3), &[400.0, 402.0, 900.0, 401.0]),
+                   vec![Some(400.0), Some(401.0), Some(402.0), Some(402.0)]);
+    }
+
+    #[test]
+    fn median_skips_nan() {
+        assert_eq!(run(FilterConfig::Median(3), &[::std::f32::NAN, 400.0, ::std::f32::NAN, 402.0]),
+                   vec![None, Some(400.0), Some(400.0), Some(401.0)]);
+    }
+
+    #[test]
+    fn spikes_are_dropped_until_the_limit() {
+        let config = FilterConfig::RejectSpikes { max_step: 5.0, limit: 2 };
+        assert_eq!(run(config, &[400.0, 450.0, 401.0, 450.0, 450.0, 450.0]),
+                   vec![Some(400.0), None, Some(401.0), None, None, Some(450.0)]);
+    }
+
+    #[test]
+    fn chain_stops_at_a_dropped_reading() {
+        let config = FilterConfig::Chain(vec![
+            FilterConfig::RejectSpikes { max_step: 5.0, limit: 10 },
+            FilterConfig::MovingAverage(2),
+        ]);
+        assert_eq!(run(config, &[400.0, 900.0, 402.0]), vec![Some(400.0), None, Some(401.0)]);
+    }
+
+    #[test]
+    fn invalid_configs() {
+        assert!(FilterConfig::MovingAverage(0).validate().is_err());
+        assert!(FilterConfig::Exponential(1.5).validate().is_err());
+        assert!(FilterConfig::Chain(vec![FilterConfig::Median(0)]).validate().is_err());
+        assert!(FilterConfig::Chain(vec![FilterConfig::None, FilterConfig::Median(5)]).validate().is_ok());
+    }
+
+    #[test]
+    fn applies_to_depth_streams() {
+        let sink: Sink<Depth> = Sink::new();
+        let depth = apply(&sink.stream(), &FilterConfig::MovingAverage(2)).hold(0);
+
+        sink.send(400);
+        sink.send(403);
+
+        assert_eq!(depth.sample(), 402);
+    }
+}
@@ -6,8 +6,14 @@ extern crate sysfs_gpio;
 extern crate log;
 extern crate carboxyl;
 extern crate lettre;
+extern crate aquamon_server;
+extern crate serde_json;
+extern crate env_logger;
 
+pub mod clock;
 pub mod uom;
 pub mod devices;
+pub mod filter;
 pub mod controller;
 pub mod alerting;
+pub mod app;
@@ -108,3 +108,173 @@ pub mod temp {
         }
     }
 }
+
+// Lengths, areas, volumes and flow rates are kept in SI units internally and can be
+// built from and read back in either metric or imperial units.
+const CM_PER_INCH: f32 = 2.54;
+const LITRES_PER_GALLON: f32 = 3.785411784;
+const CU_IN_PER_GALLON: f32 = 231.0;
+
+pub mod length {
+    use std::ops::{Add, Sub, Mul};
+    use super::area::Area;
+    use super::CM_PER_INCH;
+
+    #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+    pub struct Length(f32); // metres
+
+    impl Length {
+        pub fn in_m(metres: f32) -> Length { Length(metres) }
+        pub fn in_cm(cm: f32) -> Length { Length(cm / 100.0) }
+        pub fn in_mm(mm: f32) -> Length { Length(mm / 1000.0) }
+        pub fn in_inches(inches: f32) -> Length { Length::in_cm(inches * CM_PER_INCH) }
+
+        pub fn m(&self) -> f32 { self.0 }
+        pub fn cm(&self) -> f32 { self.0 * 100.0 }
+        pub fn mm(&self) -> f32 { self.0 * 1000.0 }
+        pub fn inches(&self) -> f32 { self.cm() / CM_PER_INCH }
+    }
+
+    impl Add for Length {
+        type Output = Length;
+        fn add(self, rhs: Length) -> Length { Length(self.0 + rhs.0) }
+    }
+
+    impl Sub for Length {
+        type Output = Length;
+        fn sub(self, rhs: Length) -> Length { Length(self.0 - rhs.0) }
+    }
+
+    impl Mul<f32> for Length {
+        type Output = Length;
+        fn mul(self, rhs: f32) -> Length { Length(self.0 * rhs) }
+    }
+
+    impl Mul for Length {
+        type Output = Area;
+        fn mul(self, rhs: Length) -> Area { Area::in_sq_m(self.0 * rhs.0) }
+    }
+}
+
+pub mod area {
+    use std::ops::Mul;
+    use super::length::Length;
+    use super::volume::Volume;
+    use super::CM_PER_INCH;
+
+    #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+    pub struct Area(f32); // square metres
+
+    impl Area {
+        pub fn in_sq_m(sq_m: f32) -> Area { Area(sq_m) }
+        pub fn in_sq_cm(sq_cm: f32) -> Area { Area(sq_cm / 10000.0) }
+        pub fn in_sq_inches(sq_inches: f32) -> Area { Area::in_sq_cm(sq_inches * CM_PER_INCH * CM_PER_INCH) }
+
+        pub fn sq_m(&self) -> f32 { self.0 }
+        pub fn sq_cm(&self) -> f32 { self.0 * 10000.0 }
+        pub fn sq_inches(&self) -> f32 { self.sq_cm() / (CM_PER_INCH * CM_PER_INCH) }
+    }
+
+    /// The volume of water `rhs` deep over this area
+    impl Mul<Length> for Area {
+        type Output = Volume;
+        fn mul(self, rhs: Length) -> Volume { Volume::in_cu_m(self.0 * rhs.m()) }
+    }
+}
+
+pub mod volume {
+    use std::ops::{Add, Sub, Mul, Div};
+    use super::area::Area;
+    use super::length::Length;
+    use super::{LITRES_PER_GALLON, CU_IN_PER_GALLON};
+
+    #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+    pub struct Volume(f32); // cubic metres
+
+    impl Volume {
+        pub fn in_cu_m(cu_m: f32) -> Volume { Volume(cu_m) }
+        pub fn in_litres(litres: f32) -> Volume { Volume(litres / 1000.0) }
+        pub fn in_ml(ml: f32) -> Volume { Volume::in_litres(ml / 1000.0) }
+        pub fn in_gallons(gallons: f32) -> Volume { Volume::in_litres(gallons * LITRES_PER_GALLON) }
+        pub fn in_cu_inches(cu_inches: f32) -> Volume { Volume::in_gallons(cu_inches / CU_IN_PER_GALLON) }
+
+        pub fn cu_m(&self) -> f32 { self.0 }
+        pub fn litres(&self) -> f32 { self.0 * 1000.0 }
+        pub fn ml(&self) -> f32 { self.litres() * 1000.0 }
+        pub fn gallons(&self) -> f32 { self.litres() / LITRES_PER_GALLON }
+        pub fn cu_inches(&self) -> f32 { self.gallons() * CU_IN_PER_GALLON }
+    }
+
+    impl Add for Volume {
+        type Output = Volume;
+        fn add(self, rhs: Volume) -> Volume { Volume(self.0 + rhs.0) }
+    }
+
+    impl Sub for Volume {
+        type Output = Volume;
+        fn sub(self, rhs: Volume) -> Volume { Volume(self.0 - rhs.0) }
+    }
+
+    impl Mul<f32> for Volume {
+        type Output = Volume;
+        fn mul(self, rhs: f32) -> Volume { Volume(self.0 * rhs) }
+    }
+
+    /// How far the water rises when this volume is spread over `rhs`
+    impl Div<Area> for Volume {
+        type Output = Length;
+        fn div(self, rhs: Area) -> Length { Length::in_m(self.0 / rhs.sq_m()) }
+    }
+}
+
+pub mod flow {
+    use super::volume::Volume;
+
+    /// Volume per unit of time, e.g. a pump's rating or an evaporation rate
+    #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+    pub struct FlowRate(f32); // cubic metres per second
+
+    impl FlowRate {
+        pub fn in_cu_m_per_s(cu_m_per_s: f32) -> FlowRate { FlowRate(cu_m_per_s) }
+        pub fn in_lph(litres_per_hour: f32) -> FlowRate { FlowRate(Volume::in_litres(litres_per_hour).cu_m() / 3600.0) }
+        pub fn in_gph(gallons_per_hour: f32) -> FlowRate { FlowRate(Volume::in_gallons(gallons_per_hour).cu_m() / 3600.0) }
+        pub fn in_ml_per_min(ml_per_min: f32) -> FlowRate { FlowRate(Volume::in_ml(ml_per_min).cu_m() / 60.0) }
+
+        pub fn lph(&self) -> f32 { self.volume_in(3600.0).litres() }
+        pub fn gph(&self) -> f32 { self.volume_in(3600.0).gallons() }
+        pub fn ml_per_min(&self) -> f32 { self.volume_in(60.0).ml() }
+
+        /// What moves in `seconds`
+        pub fn volume_in(&self, seconds: f32) -> Volume { Volume::in_cu_m(self.0 * seconds) }
+
+        /// Seconds it takes to move `volume`
+        pub fn seconds_for(&self, volume: Volume) -> f32 { volume.cu_m() / self.0 }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::length::Length;
+    use super::area::Area;
+    use super::volume::Volume;
+    use super::flow::FlowRate;
+
+    fn near(a: f32, b: f32) -> bool { (a - b).abs() < 0.001 * b.abs().max(1.0) }
+
+    #[test]
+    fn converts_between_metric_and_imperial() {
+        assert!(near(Length::in_inches(10.0).cm(), 25.4));
+        assert!(near(Volume::in_gallons(10.0).litres(), 37.854));
+        assert!(near(Area::in_sq_inches(1.0).sq_cm(), 6.4516));
+        assert!(near(FlowRate::in_gph(50.0).lph(), 189.27));
+        assert!(near(FlowRate::in_ml_per_min(1.1).ml_per_min(), 1.1));
+    }
+
+    #[test]
+    fn an_inch_over_231_square_inches_is_a_gallon() {
+        let volume = Area::in_sq_inches(231.0) * Length::in_inches(1.0);
+        assert!(near(volume.gallons(), 1.0));
+        assert!(near((volume / Area::in_sq_inches(231.0)).inches(), 1.0));
+        assert!(near(FlowRate::in_gph(1.0).seconds_for(volume), 3600.0));
+    }
+}
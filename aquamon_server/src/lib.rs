@@ -32,7 +32,8 @@ pub mod server {
     use std::fmt;
 
     use std::sync::{Mutex, RwLock, Arc};
-    use std::sync::mpsc::Sender;
+    use std::sync::mpsc::{channel, Sender};
+    use std::time::Duration;
     use std::fs::File;
     use std::io::{Read, SeekFrom, Seek};
     use std::error::Error;
@@ -42,6 +43,7 @@ pub mod server {
     use bodyparser;
 
     const MAX_BODY_LENGTH: usize = 1024 * 1024 * 10;
+    const LITRES_PER_GALLON: f32 = 3.785411784;
 
     #[allow(non_snake_case)]
     #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -65,16 +67,112 @@ pub mod server {
     }
 
     #[allow(non_snake_case)]
-    #[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct PhCalibrationPoint {
+        pub buffer: f32,
+        pub raw: u16,
+    }
+
+    // Progress of a guided calibration, and how the current calibration compares to an
+    // ideal probe's 59.16 mV/pH
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Default)]
+    pub struct PhCalibrationStatus {
+        pub calibrating: bool,
+        // buffer waiting for the reading to settle
+        pub sampling: Option<f32>,
+        pub lastRaw: Option<u16>,
+        pub points: Vec<PhCalibrationPoint>,
+        pub error: Option<String>,
+        // at the ADC, and with no efficiency, until ampGain is set
+        pub slopeMvPerPh: f32,
+        pub efficiencyPercent: Option<f32>,
+        pub acidSlopeMvPerPh: Option<f32>,
+        pub acidEfficiencyPercent: Option<f32>,
+    }
+
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    // level is in inches, or cm with depthValues.metric
+    pub struct DepthCalibrationPoint {
+        pub level: f32,
+        pub raw: u16,
+    }
+
+    // The fit is filled in once there are enough points
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Default)]
+    pub struct DepthCalibrationStatus {
+        pub calibrating: bool,
+        pub points: Vec<DepthCalibrationPoint>,
+        // per inch or cm, and in inches or cm, the same as the points
+        pub stepsPerUnit: Option<f32>,
+        pub maxError: Option<f32>,
+    }
+
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ProbeStatus {
+        pub name: String,
+        pub tempF: f32,
+        pub temp: f32,
+    }
+
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct InputStatus {
+        pub name: String,
+        pub active: bool,
+    }
+
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct SensorStatus {
+        pub name: String,
+        pub healthy: bool,
+        // null until the first good reading
+        pub secondsSinceGood: Option<u64>,
+        pub consecutiveFailures: u32,
+        pub totalFailures: u64,
+        pub outOfRange: u64,
+        pub lastError: Option<String>,
+        // e.g. pH that isn't temperature compensated
+        pub warning: Option<String>,
+    }
+
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Default)]
     pub struct Status {
+        // from the control probe
         pub currentTempF: f32,
+        // currentTemp, airTemp and each probe's temp are in this unit
+        pub temperatureUnit: TemperatureUnit,
+        pub currentTemp: f32,
+        pub airTemp: f32,
+        pub probes: Vec<ProbeStatus>,
+        pub sensors: Vec<SensorStatus>,
+        pub inputs: Vec<InputStatus>,
         pub depth: u16,
         pub airTempF: f32,
         pub humidity: f32,
         pub pH: f32,
+        pub phCalibration: PhCalibrationStatus,
+        pub depthCalibration: DepthCalibrationStatus,
         pub heater_on: bool,
+        // null when the heater is on/off controlled
+        pub heaterPid: Option<HeaterPidStatus>,
+        // learned from past runs, null until there's been one long enough
+        pub heaterRateFPerHour: Option<f32>,
+        pub coolerRateFPerHour: Option<f32>,
+        // heater, backupHeater, fan and cooler, for the stages that have an outlet
+        pub stages: Vec<TemperatureStageStatus>,
         pub cooler_on: bool,
         pub ato_pump_on: bool,
+        // idle, filling, timedOut, failedSafe, quickDrop, tooLow, noDepth, highFloat or
+        // reservoirEmpty
+        pub atoState: String,
+        pub atoReservoir: AtoReservoirStatus,
+        pub atoEvaporation: Option<AtoEvaporationStatus>,
         pub pump_on: bool,
         // pub timestamp: String,
         // TODO: map of on/off triggers
@@ -82,20 +180,212 @@ pub mod server {
         //
     }
 
+    // Volumes are in gallons, or litres with depthValues.metric. capacity, remaining and
+    // hoursLeft are null when no reservoir capacity is set
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
+    pub struct AtoReservoirStatus {
+        pub lastRun: Option<f32>,
+        pub lastDay: f32,
+        pub capacity: Option<f32>,
+        pub remaining: Option<f32>,
+        pub hoursLeft: Option<f32>,
+    }
+
+    // Daily ATO volume, in the same unit as atoReservoir, over the days kept. The
+    // correlations run from -1 to 1 and are null until there are a few days
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+    pub struct AtoEvaporationStatus {
+        pub today: f32,
+        pub averagePerDay: Option<f32>,
+        pub days: usize,
+        pub airTempCorrelation: Option<f32>,
+        pub humidityCorrelation: Option<f32>,
+    }
+
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct TemperatureStageStatus {
+        pub name: String,
+        pub on: bool,
+        pub offsetF: f32,
+    }
+
+    // The PID terms from the last controller tick, output is the heater duty from 0 to 1
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
+    pub struct HeaterPidStatus {
+        pub setpointF: f32,
+        pub errorF: f32,
+        pub p: f32,
+        pub i: f32,
+        pub d: f32,
+        pub output: f32,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+    pub enum TemperatureUnit { F, C }
+
+    impl Default for TemperatureUnit {
+        fn default() -> TemperatureUnit { TemperatureUnit::F }
+    }
+
+    impl TemperatureUnit {
+        // Settings and history.csv are always stored in F. Every conversion for the API, the
+        // status and history included, goes through these so they all round the same.
+        pub fn from_f(&self, f: f32) -> f32 {
+            match *self {
+                TemperatureUnit::F => f,
+                TemperatureUnit::C => ((f - 32.0) * 5.0 / 9.0 * 100.0).round() / 100.0,
+            }
+        }
+
+        pub fn to_f(&self, value: f32) -> f32 {
+            match *self {
+                TemperatureUnit::F => value,
+                TemperatureUnit::C => ((value * 9.0 / 5.0 + 32.0) * 100.0).round() / 100.0,
+            }
+        }
+    }
+
+    // Display preferences
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
+    #[serde(default)]
+    pub struct UnitSettings {
+        pub temperatureUnit: TemperatureUnit,
+    }
+
+    // unit is the unit min and max are in. Missing means the display unit when posted,
+    // and is always F when saved.
     #[allow(non_snake_case)]
     #[derive(Debug, Clone, Serialize, Deserialize, Default)]
     pub struct TemperatureSettings {
         pub heater: TemperatureRangeSettings,
         pub cooler: TemperatureRangeSettings,
+        #[serde(default)]
+        pub unit: Option<TemperatureUnit>,
+        // Missing keeps the heater on/off
+        #[serde(default)]
+        pub heaterPid: Option<HeaterPidSettings>,
+        #[serde(default)]
+        pub coolerLimits: CoolerLimitSettings,
+        #[serde(default)]
+        pub stageOffsets: StageOffsetSettings,
+        // how long the heater or cooler gets to move the temperature before it's cut
+        #[serde(default = "default_fault_check_minutes")]
+        pub faultCheckMinutes: u64,
+    }
+
+    pub fn default_fault_check_minutes() -> u64 { 30 }
+
+    // Where each stage switches, in F whatever the display unit. Heating stages are offset
+    // from the heater setpoint and cooling stages from the cooler setpoint.
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+    #[serde(default)]
+    pub struct StageOffsetSettings {
+        pub heaterF: f32,
+        pub backupHeaterF: f32,
+        pub fanF: f32,
+        pub coolerF: f32,
+    }
+
+    impl Default for StageOffsetSettings {
+        fn default() -> StageOffsetSettings {
+            StageOffsetSettings { heaterF: 0.0, backupHeaterF: -0.5, fanF: -0.5, coolerF: 0.0 }
+        }
+    }
+
+    // Compressor protection for the cooler outlet
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+    pub struct CoolerLimitSettings {
+        pub minRunSeconds: u64,
+        pub minRestSeconds: u64,
+        pub maxRunSeconds: u64,
+    }
+
+    impl Default for CoolerLimitSettings {
+        fn default() -> CoolerLimitSettings {
+            CoolerLimitSettings { minRunSeconds: 3 * 60, minRestSeconds: 5 * 60, maxRunSeconds: 4 * 60 * 60 }
+        }
+    }
+
+    // Gains are per degree F whatever the display unit. The heater is on for output *
+    // windowSeconds of each window.
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+    pub struct HeaterPidSettings {
+        pub kp: f32,
+        pub ki: f32,
+        pub kd: f32,
+        pub windowSeconds: u64,
+    }
+
+    impl TemperatureSettings {
+        pub fn in_unit(&self, unit: TemperatureUnit) -> TemperatureSettings {
+            let from = self.unit.unwrap_or(TemperatureUnit::F);
+            let convert = |range: &TemperatureRangeSettings| TemperatureRangeSettings {
+                min: unit.from_f(from.to_f(range.min)),
+                max: unit.from_f(from.to_f(range.max)),
+                points: range.points.iter()
+                    .map(|p| TemperaturePointSettings { temp: unit.from_f(from.to_f(p.temp)), ..p.clone() })
+                    .collect(),
+                ..range.clone()
+            };
+            TemperatureSettings { heater: convert(&self.heater), cooler: convert(&self.cooler), unit: Some(unit), heaterPid: self.heaterPid, coolerLimits: self.coolerLimits, stageOffsets: self.stageOffsets, faultCheckMinutes: self.faultCheckMinutes }
+        }
+
+        pub fn validate(&self) -> Result<(), String> {
+            if self.faultCheckMinutes == 0 {
+                return Err("faultCheckMinutes must be more than 0".to_string());
+            }
+            self.heater.validate().map_err(|err| format!("heater: {}", err))
+                .and_then(|_| self.cooler.validate().map_err(|err| format!("cooler: {}", err)))
+        }
+    }
+
+    // HH:MM on a 24 hour clock
+    fn valid_time(time: &str) -> bool {
+        let parts: Vec<&str> = time.splitn(2, ':').collect();
+        parts.len() == 2 && parts[1].len() == 2
+            && parts[0].parse::<u32>().map(|h| h < 24).unwrap_or(false)
+            && parts[1].parse::<u32>().map(|m| m < 60).unwrap_or(false)
     }
 
+    // When points are given they're the schedule and min/max are ignored
     #[allow(non_snake_case)]
     #[derive(Debug, Clone, Serialize, Deserialize, Default)]
+    #[serde(default)]
     pub struct TemperatureRangeSettings {
         pub min: f32,
         pub minTime: String,
         pub max: f32,
         pub maxTime: String,
+        pub points: Vec<TemperaturePointSettings>,
+    }
+
+    impl TemperatureRangeSettings {
+        /// Either points, or both times, all as HH:MM
+        pub fn validate(&self) -> Result<(), String> {
+            if self.points.is_empty() {
+                if !valid_time(&self.minTime) || !valid_time(&self.maxTime) {
+                    return Err(format!("minTime {:?} and maxTime {:?} must both be HH:MM when there are no points", self.minTime, self.maxTime));
+                }
+            } else if let Some(point) = self.points.iter().find(|p| !valid_time(&p.time)) {
+                return Err(format!("point time {:?} must be HH:MM", point.time));
+            }
+            Ok(())
+        }
+    }
+
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Default)]
+    pub struct TemperaturePointSettings {
+        pub time: String,
+        pub temp: f32,
     }
 
     #[allow(non_snake_case)]
@@ -107,13 +397,21 @@ pub mod server {
 
     #[allow(non_snake_case)]
     #[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
+    // highLevel is in inches, surfaceArea in square inches, tankVolume in gallons and
+    // pumpRate in gallons per hour, or cm, square cm, litres and litres per hour with metric
+    // set. The aliases load settings saved before there was a metric option.
     pub struct DepthSettingsDepthValues {
         pub low: u16,
         pub high: u16,
-        pub highInches: f32,
-        pub tankSurfaceArea: u16,
-        pub pumpGph: f32,
+        #[serde(alias = "highInches")]
+        pub highLevel: f32,
+        #[serde(alias = "tankSurfaceArea")]
+        pub surfaceArea: u16,
+        #[serde(alias = "pumpGph")]
+        pub pumpRate: f32,
         pub tankVolume: f32,
+        #[serde(default)]
+        pub metric: bool,
     }
 
     #[allow(non_snake_case)]
@@ -121,6 +419,27 @@ pub mod server {
     pub struct DepthSettings {
         pub maintainRange: DepthSettingsMaintain,
         pub depthValues: DepthSettingsDepthValues,
+        #[serde(default)]
+        pub reservoir: ReservoirSettings,
+        // the most the ATO may pump in 24 hours, in the same unit as the reservoir. null is
+        // 3 gallons
+        #[serde(default)]
+        pub maxDailyVolume: Option<f32>,
+    }
+
+    // capacity is in gallons, or litres with depthValues.metric. 0 doesn't track the
+    // reservoir. Alerts when it's projected to run dry within alertHours
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+    pub struct ReservoirSettings {
+        pub capacity: f32,
+        pub alertHours: f32,
+    }
+
+    impl Default for ReservoirSettings {
+        fn default() -> ReservoirSettings {
+            ReservoirSettings { capacity: 0.0, alertHours: 48.0 }
+        }
     }
 
     #[allow(non_snake_case)]
@@ -147,6 +466,205 @@ pub mod server {
         pub pump: bool,
     }
 
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct OutletSettings {
+        pub name: String,
+        // BCM numbering
+        pub gpio: u64,
+        pub activeLow: bool,
+        // heater, ato, cooler, returnPump, fugeLight or doser. backupHeater and fan are
+        // optional temperature stages
+        pub role: String,
+    }
+
+    // A digital input such as a float switch, leak sensor or button. Active low inputs
+    // read active when the line is pulled to ground.
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct InputSettings {
+        pub name: String,
+        // BCM numbering
+        pub gpio: u64,
+        #[serde(default)]
+        pub activeLow: bool,
+        #[serde(default = "default_debounce_ms")]
+        pub debounceMs: u64,
+    }
+
+    fn default_debounce_ms() -> u64 { 50 }
+
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct TempProbeSettings {
+        pub name: String,
+        // DS18B20 ROM id as 16 hex digits, logged at startup
+        pub rom: String,
+    }
+
+    // What the sensor read and what a reference thermometer read at the same time
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct TempReferencePoint {
+        pub readF: f32,
+        pub actualF: f32,
+    }
+
+    // sensor is temp, airTemp or probe:<name>. With two reference points the gain is
+    // corrected too, with one the offset comes from it, otherwise offsetF is used.
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct TempCalibrationSettings {
+        pub sensor: String,
+        #[serde(default)]
+        pub offsetF: f32,
+        #[serde(default)]
+        pub points: Vec<TempReferencePoint>,
+    }
+
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct HardwareSettings {
+        pub outlets: Vec<OutletSettings>,
+        #[serde(default)]
+        pub tempProbes: Vec<TempProbeSettings>,
+        // name of the probe the heater and cooler follow. Empty uses the first probe on the bus
+        #[serde(default)]
+        pub controlProbe: String,
+        #[serde(default)]
+        pub tempCalibrations: Vec<TempCalibrationSettings>,
+        #[serde(default)]
+        pub inputs: Vec<InputSettings>,
+        // names of the inputs that back up the ATO. The high float is active when the water
+        // reaches it, the reservoir float when the reservoir is empty. Empty for none
+        #[serde(default)]
+        pub atoHighFloat: String,
+        #[serde(default)]
+        pub atoReservoirFloat: String,
+    }
+
+    impl Default for HardwareSettings {
+        // the original relay board wiring
+        fn default() -> HardwareSettings {
+            let outlet = |name: &str, gpio: u64, active_low: bool, role: &str| OutletSettings {
+                name: name.to_string(), gpio: gpio, activeLow: active_low, role: role.to_string()
+            };
+            HardwareSettings {
+                outlets: vec![
+                    outlet("Heater", 17, true, "heater"),
+                    outlet("ATO", 27, true, "ato"),
+                    outlet("Cooler", 22, true, "cooler"),
+                    outlet("Return pump", 5, true, "returnPump"),
+                    outlet("Fuge light", 6, false, "fugeLight"),
+                    outlet("Doser", 13, false, "doser"),
+                ],
+                tempProbes: vec![],
+                controlProbe: String::new(),
+                tempCalibrations: vec![],
+                inputs: vec![],
+                atoHighFloat: String::new(),
+                atoReservoirFloat: String::new(),
+            }
+        }
+    }
+
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct FilterStageSettings {
+        // none, movingAverage, exponential, median or rejectSpikes
+        pub kind: String,
+        // movingAverage and median
+        #[serde(default)]
+        pub window: usize,
+        // exponential
+        #[serde(default)]
+        pub alpha: f32,
+        // rejectSpikes
+        #[serde(default)]
+        pub maxStep: f32,
+        #[serde(default)]
+        pub limit: usize,
+    }
+
+    impl FilterStageSettings {
+        pub fn moving_average(window: usize) -> FilterStageSettings {
+            FilterStageSettings { kind: "movingAverage".to_string(), window: window, alpha: 0.0, maxStep: 0.0, limit: 0 }
+        }
+    }
+
+    // Each sensor's readings go through its stages in order
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(default)]
+    pub struct FilterSettings {
+        pub temp: Vec<FilterStageSettings>,
+        pub depth: Vec<FilterStageSettings>,
+        pub airTemp: Vec<FilterStageSettings>,
+        pub humidity: Vec<FilterStageSettings>,
+        pub ph: Vec<FilterStageSettings>,
+    }
+
+    impl Default for FilterSettings {
+        // the averaging that used to be built in
+        fn default() -> FilterSettings {
+            FilterSettings {
+                temp: vec![FilterStageSettings::moving_average(4)],
+                depth: vec![FilterStageSettings::moving_average(10)],
+                airTemp: vec![FilterStageSettings::moving_average(4)],
+                humidity: vec![FilterStageSettings::moving_average(4)],
+                ph: vec![FilterStageSettings::moving_average(20)],
+            }
+        }
+    }
+
+    // The pH probe on the MCP3221 ADC. Calibration points are raw ADC counts.
+    #[allow(non_snake_case)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(default)]
+    pub struct PhSettings {
+        pub enabled: bool,
+        pub i2cBus: u8,
+        pub ph7Cal: u16,
+        pub ph10Cal: u16,
+        // only from a three point calibration
+        pub ph4Cal: Option<u16>,
+        // of the amp between the probe and the ADC, has to be set to finish a calibration
+        pub ampGain: Option<f32>,
+        // at 25C, saved by the last guided calibration
+        pub slopeMvPerPh: Option<f32>,
+        pub acidSlopeMvPerPh: Option<f32>,
+    }
+
+    impl Default for PhSettings {
+        // off until a probe is calibrated, with the values from the original board
+        fn default() -> PhSettings {
+            PhSettings {
+                enabled: false, i2cBus: 1, ph7Cal: 2048, ph10Cal: 1065, ph4Cal: None, ampGain: None,
+                slopeMvPerPh: None, acidSlopeMvPerPh: None,
+            }
+        }
+    }
+
+    // action is start, capture, finish or cancel. capture needs the buffer's pH: 4, 7 or 10
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct PhCalibrationAction {
+        pub action: String,
+        #[serde(default)]
+        pub buffer: f32,
+    }
+
+    // action is start, capture, finish or cancel. capture records the current depth as
+    // `level` above the reference mark, which is 0, in inches or cm with depthValues.metric
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct DepthCalibrationAction {
+        pub action: String,
+        #[serde(default, alias = "inches")]
+        pub level: f32,
+    }
+
+    // The new depth values once a calibration is finished, or why the action failed
+    pub type DepthCalibrationReply = Result<Option<DepthSettingsDepthValues>, String>;
+
     // Used to send commands to the main service
     #[derive(Default)]
     pub struct Commands {
@@ -157,6 +675,12 @@ pub mod server {
         pub viewing_mode: Option<LiveModeSettings>,
         pub garage_door_opener: Option<()>,
         pub doser_settings: Option<DoserSettings>,
+        pub ph_calibration: Option<PhCalibrationAction>,
+        pub units: Option<UnitSettings>,
+        pub depth_calibration: Option<(DepthCalibrationAction, Sender<DepthCalibrationReply>)>,
+        pub ato_refilled: Option<()>,
+        pub ato_reset: Option<()>,
+        pub temperature_reset: Option<()>,
     }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -165,6 +689,14 @@ pub struct Settings {
     pub depth_settings: DepthSettings,
     pub lighting_schedule: LightingSchedule,
     pub doser_settings: DoserSettings,
+    #[serde(default)]
+    pub hardware: HardwareSettings,
+    #[serde(default)]
+    pub filters: FilterSettings,
+    #[serde(default)]
+    pub ph: PhSettings,
+    #[serde(default)]
+    pub units: UnitSettings,
 }
 
 // impl fmt::Display for Config {
@@ -173,7 +705,47 @@ pub struct Settings {
 //     }
 // }
 
+// evaporation.csv is date, litres, air temp in F, humidity, one line per day
+fn evaporation_in(csv: &str, unit: TemperatureUnit, metric: bool) -> String {
+    csv.lines().map(|line| {
+        let fields: Vec<String> = line.split(',').enumerate().map(|(i, field)| {
+            match (i, field.parse::<f32>()) {
+                (1, Ok(litres)) if !metric => (litres / LITRES_PER_GALLON).to_string(),
+                (2, Ok(f)) => unit.from_f(f).to_string(),
+                _ => field.to_string(),
+            }
+        }).collect();
+        fields.join(",") + "\n"
+    }).collect()
+}
+
+// history.csv is written in F. Columns are time, temp, depth, heater, ato, cooler, air
+// temp, humidity, pH, then each probe's temp and each extra stage as name=on. The first
+// line is left alone as the read usually starts part way into it.
+fn history_in(csv: &str, unit: TemperatureUnit) -> String {
+    if unit == TemperatureUnit::F {
+        return csv.to_string();
+    }
+    let mut lines = csv.split('\n');
+    let mut out = lines.next().unwrap_or("").to_string();
+    for line in lines {
+        out.push('\n');
+        let fields: Vec<String> = line.split(',').enumerate().map(|(i, field)| {
+            match (i == 1 || i == 6 || i >= 9, field.parse::<f32>()) {
+                (true, Ok(f)) => unit.from_f(f).to_string(),
+                _ => field.to_string(),
+            }
+        }).collect();
+        out.push_str(&fields.join(","));
+    }
+    out
+}
+
 pub fn start(tx_live: Sender<LiveModeSettings>, status_lock: Arc<RwLock<Status>>, tx_command: Sender<Commands>, settings: Settings) {
+    start_on("192.168.1.243:80", tx_live, status_lock, tx_command, settings)
+}
+
+pub fn start_on(addr: &str, tx_live: Sender<LiveModeSettings>, status_lock: Arc<RwLock<Status>>, tx_command: Sender<Commands>, settings: Settings) {
     let mut router = Router::new();
     let mut ra_router = Router::new();
     let mutex_live = Mutex::new(tx_live);
@@ -217,19 +789,28 @@ pub fn start(tx_live: Sender<LiveModeSettings>, status_lock: Arc<RwLock<Status>>
         }
     }, "lighting_schedule");
 
+    let units_lock = Arc::new(RwLock::new(settings.units));
+    let (units_for_temp_get, units_for_temp_post, units_for_history) = (units_lock.clone(), units_lock.clone(), units_lock.clone());
+    let units_for_evaporation = units_lock.clone();
     let temp_settings_lock = Arc::new(RwLock::new(settings.temperature_settings));
     let (writer_temp_settings, mutex_temp) = (temp_settings_lock.clone(), mutex_c.clone());
     router.get("/settings/temperature", move |_: &mut Request| {
         let temp_settings = temp_settings_lock.read().unwrap();
+        let unit = units_for_temp_get.read().unwrap().temperatureUnit;
 
-        Ok(Response::with((status::Ok, serde_json::to_string(&(*temp_settings)).unwrap())))
+        Ok(Response::with((status::Ok, serde_json::to_string(&temp_settings.in_unit(unit)).unwrap())))
     }, "temperature_settings");
 
     router.post("/settings/temperature", move |req: &mut Request| {
         let body = req.get::<bodyparser::Struct<TemperatureSettings>>();
 
             match body {
-                Ok(Some(temperature_settings)) => {
+                Ok(Some(posted)) => {
+                    if let Err(err) = posted.validate() {
+                        return Ok(Response::with((status::BadRequest, err)));
+                    }
+                    let unit = posted.unit.unwrap_or(units_for_temp_post.read().unwrap().temperatureUnit);
+                    let temperature_settings = TemperatureSettings { unit: Some(unit), ..posted }.in_unit(TemperatureUnit::F);
                     {
                         mutex_temp.lock().unwrap()
                             .send(Commands { temperature_settings: Some(temperature_settings.clone()), ..Default::default() })
@@ -248,8 +829,37 @@ pub fn start(tx_live: Sender<LiveModeSettings>, status_lock: Arc<RwLock<Status>>
             }
         }, "temperature_settings");
 
+        let (writer_units, mutex_units) = (units_lock.clone(), mutex_c.clone());
+        router.get("/settings/units", move |_: &mut Request| {
+            let units = units_lock.read().unwrap();
+
+            Ok(Response::with((status::Ok, serde_json::to_string(&(*units)).unwrap())))
+        }, "units");
+
+        router.post("/settings/units", move |req: &mut Request| {
+            let body = req.get::<bodyparser::Struct<UnitSettings>>();
+            match body {
+                Ok(Some(units)) => {
+                    {
+                        mutex_units.lock().unwrap()
+                            .send(Commands { units: Some(units), ..Default::default() })
+                            .unwrap();
+                    }
+                    {
+                        let mut x = writer_units.write().unwrap();
+                        *x = units;
+                    }
+                    Ok(Response::with((status::Ok, "".to_string())))
+                },
+                Ok(None) => { error!("Error"); Ok(Response::with(status::Ok)) },
+                Err(err) => { error!("Error: {:?}", err); Ok(Response::with(status::Ok)) }
+            }
+        }, "units");
+
         let depth_settings_lock = Arc::new(RwLock::new(settings.depth_settings));
+        let depth_settings_for_evaporation = depth_settings_lock.clone();
         let (writer_depth_settings, mutex_depth) = (depth_settings_lock.clone(), mutex_c.clone());
+        let (writer_calibrated_depth, mutex_depth_calibration) = (depth_settings_lock.clone(), mutex_c.clone());
         router.get("/settings/depth", move |_: &mut Request| {
             let depth_settings = depth_settings_lock.read().unwrap();
 
@@ -261,6 +871,9 @@ pub fn start(tx_live: Sender<LiveModeSettings>, status_lock: Arc<RwLock<Status>>
 
             match body {
                 Ok(Some(depth_settings)) => {
+                    if depth_settings.maxDailyVolume.map_or(false, |cap| !(cap > 0.0)) {
+                        return Ok(Response::with((status::BadRequest, "maxDailyVolume must be more than 0".to_string())));
+                    }
                     {
                         mutex_depth.lock().unwrap()
                             .send(Commands { depth_settings: Some(depth_settings), ..Default::default() })
@@ -278,6 +891,59 @@ pub fn start(tx_live: Sender<LiveModeSettings>, status_lock: Arc<RwLock<Status>>
             }
         }, "depth_settings");
 
+        router.post("/depth/calibration", move |req: &mut Request| {
+            let body = req.get::<bodyparser::Struct<DepthCalibrationAction>>();
+            match body {
+                Ok(Some(action)) => {
+                    let (tx_reply, rx_reply) = channel();
+                    {
+                        mutex_depth_calibration.lock().unwrap()
+                            .send(Commands { depth_calibration: Some((action, tx_reply)), ..Default::default() })
+                            .unwrap();
+                    }
+                    // the main loop answers within a tick
+                    match rx_reply.recv_timeout(Duration::from_secs(5)) {
+                        Ok(Ok(Some(depth_values))) => {
+                            writer_calibrated_depth.write().unwrap().depthValues = depth_values;
+                            Ok(Response::with((status::Ok, serde_json::to_string(&depth_values).unwrap())))
+                        },
+                        Ok(Ok(None)) => Ok(Response::with((status::Ok, "".to_string()))),
+                        Ok(Err(err)) => Ok(Response::with((status::BadRequest, err))),
+                        Err(err) => { error!("Error: {:?}", err); Ok(Response::with(status::InternalServerError)) }
+                    }
+                },
+                Ok(None) => { error!("Error"); Ok(Response::with(status::BadRequest)) },
+                Err(err) => { error!("Error: {:?}", err); Ok(Response::with(status::BadRequest)) }
+            }
+        }, "depth_calibration");
+
+        let mutex_refilled = mutex_c.clone();
+        router.post("/ato/reservoir/refilled", move |_: &mut Request| {
+            mutex_refilled.lock().unwrap()
+                .send(Commands { ato_refilled: Some(()), ..Default::default() })
+                .unwrap();
+
+            Ok(Response::with((status::Ok, "")))
+        }, "ato_refilled");
+
+        let mutex_ato_reset = mutex_c.clone();
+        router.post("/ato/reset", move |_: &mut Request| {
+            mutex_ato_reset.lock().unwrap()
+                .send(Commands { ato_reset: Some(()), ..Default::default() })
+                .unwrap();
+
+            Ok(Response::with((status::Ok, "")))
+        }, "ato_reset");
+
+        let mutex_temperature_reset = mutex_c.clone();
+        router.post("/temperature/reset", move |_: &mut Request| {
+            mutex_temperature_reset.lock().unwrap()
+                .send(Commands { temperature_reset: Some(()), ..Default::default() })
+                .unwrap();
+
+            Ok(Response::with((status::Ok, "")))
+        }, "temperature_reset");
+
         let doser_settings_lock = Arc::new(RwLock::new(settings.doser_settings));
         let (writer_doser_settings, mutex_doser) = (doser_settings_lock.clone(), mutex_c.clone());
         router.get("/settings/doser", move |_: &mut Request| {
@@ -394,14 +1060,33 @@ pub fn start(tx_live: Sender<LiveModeSettings>, status_lock: Arc<RwLock<Status>>
             Ok(Response::with((status::Ok, "")))
         }, "garage_door");
 
+        let mutex_ph_calibration = mutex_c.clone();
+        router.post("/ph/calibration", move |req: &mut Request| {
+            let body = req.get::<bodyparser::Struct<PhCalibrationAction>>();
+            match body {
+                Ok(Some(action)) => {
+                    {
+                        mutex_ph_calibration.lock().unwrap()
+                            .send(Commands { ph_calibration: Some(action.clone()), ..Default::default() })
+                            .unwrap();
+                    }
+
+                    Ok(Response::with((status::Ok, serde_json::to_string(&action).unwrap())))
+                },
+                Ok(None) => { error!("Error"); Ok(Response::with(status::BadRequest)) },
+                Err(err) => { error!("Error: {:?}", err); Ok(Response::with(status::BadRequest)) }
+            }
+        }, "ph_calibration");
+
         let status_lock_main = status_lock.clone();
         router.get("/status", move |request: &mut Request| {
             let status = status_lock_main.read().unwrap();
             match request.headers.get::<ContentType>() {
                 Some(&ContentType(Mime(TopLevel::Text, SubLevel::Html, _))) => {
                     Ok(Response::with((status::Ok, 
-                                       format!("Temperature is {} farenheit. Depth is {}.",
-                                               status.currentTempF,
+                                       format!("Temperature is {} {}. Depth is {}.",
+                                               status.currentTemp,
+                                               if status.temperatureUnit == TemperatureUnit::C { "celsius" } else { "farenheit" },
                                                status.depth))))
                 }
                 _ => {
@@ -415,6 +1100,10 @@ pub fn start(tx_live: Sender<LiveModeSettings>, status_lock: Arc<RwLock<Status>>
         router.get("/status/history.csv", move |req: &mut Request| {
             let mut s = String::new();
             let query = req.get_ref::<UrlEncodedQuery>();
+            let unit = query.as_ref().ok()
+                .and_then(|map| map.get("unit").and_then(|u| u.get(0)).map(|u| u.clone()))
+                .and_then(|u| match u.as_str() { "C" => Some(TemperatureUnit::C), "F" => Some(TemperatureUnit::F), _ => None })
+                .unwrap_or(units_for_history.read().unwrap().temperatureUnit);
             let hours: i64 = query.map(|map| {
                 map.get("hours")
                     .map(|h| h.get(0).unwrap().parse::<i64>().unwrap())
@@ -426,11 +1115,21 @@ pub fn start(tx_live: Sender<LiveModeSettings>, status_lock: Arc<RwLock<Status>>
                 file.read_to_string(&mut s)
             });
             match result  {
-                Ok(_) => Ok(Response::with((status::Ok, GzipWriter(s.as_bytes())))),
+                Ok(_) => Ok(Response::with((status::Ok, GzipWriter(history_in(&s, unit).as_bytes())))),
                 Err(err) => { error!("Error: {}", err); Ok(Response::with(status::Ok)) }
             }
         }, "history");
 
+        router.get("/ato/evaporation.csv", move |_: &mut Request| {
+            let mut s = String::new();
+            let unit = units_for_evaporation.read().unwrap().temperatureUnit;
+            let metric = depth_settings_for_evaporation.read().unwrap().depthValues.metric;
+            match File::open("evaporation.csv").and_then(|mut file| file.read_to_string(&mut s)) {
+                Ok(_) => Ok(Response::with((status::Ok, evaporation_in(&s, unit, metric)))),
+                Err(err) => { error!("Error: {}", err); Ok(Response::with(status::Ok)) }
+            }
+        }, "evaporation");
+
         let ra_status = status_lock.clone();
         ra_router.get("/", move |_: &mut Request| {
             let status = ra_status.read().unwrap();
@@ -447,7 +1146,7 @@ pub fn start(tx_live: Sender<LiveModeSettings>, status_lock: Arc<RwLock<Status>>
         // chain.link_before(BasicAuth);
         chain.link_before(::persistent::Read::<bodyparser::MaxBodyLength>::one(MAX_BODY_LENGTH));
 
-        Iron::new(chain).http("192.168.1.243:80").unwrap();
+        Iron::new(chain).http(addr).unwrap();
     }
 
     // #[derive(Debug)]
@@ -508,6 +1207,31 @@ pub fn start(tx_live: Sender<LiveModeSettings>, status_lock: Arc<RwLock<Status>>
 
 }
 
+#[test]
+fn depth_values_load_the_old_names() {
+    use server::DepthSettingsDepthValues;
+
+    let old = r#"{"low": 60, "high": 120, "highInches": 6.0, "tankSurfaceArea": 100, "pumpGph": 5.0, "tankVolume": 10.0}"#;
+    let values: DepthSettingsDepthValues = serde_json::from_str(old).unwrap();
+    assert_eq!((values.highLevel, values.surfaceArea, values.pumpRate), (6.0, 100, 5.0));
+    assert!(!values.metric);
+    assert!(serde_json::to_string(&values).unwrap().contains("\"highLevel\":6"));
+}
+
+#[test]
+fn temperature_ranges_need_times() {
+    use server::{TemperatureRangeSettings, TemperaturePointSettings};
+
+    let min_max = TemperatureRangeSettings { minTime: "07:00".to_string(), maxTime: "16:30".to_string(), ..Default::default() };
+    assert!(min_max.validate().is_ok());
+    assert!(TemperatureRangeSettings::default().validate().is_err());
+    assert!(TemperatureRangeSettings { maxTime: "24:00".to_string(), ..min_max.clone() }.validate().is_err());
+
+    let point = |time: &str| TemperaturePointSettings { time: time.to_string(), temp: 78.0 };
+    assert!(TemperatureRangeSettings { points: vec![point("02:00"), point("14:00")], ..Default::default() }.validate().is_ok());
+    assert!(TemperatureRangeSettings { points: vec![point("02:00"), point("2pm")], ..Default::default() }.validate().is_err());
+}
+
 #[test]
 fn it_works() {
 }
@@ -365,7 +365,7 @@ $(function() {
     e.preventDefault();
     var settings = { 
       maintainRange: { low: getIntValue('#waterLevelLow'), high: getIntValue('#waterLevelHigh') },
-      depthValues: { low: getIntValue('#minDepthValue'), high: getIntValue('#maxDepthValue'), highInches: getFloatValue('#maxDepthInches'), tankSurfaceArea: getIntValue('#tankSurfaceArea'), pumpGph: getFloatValue('#pumpGph'), tankVolume: getFloatValue('#tankVolume') }
+      depthValues: { low: getIntValue('#minDepthValue'), high: getIntValue('#maxDepthValue'), highLevel: getFloatValue('#maxDepthInches'), surfaceArea: getIntValue('#tankSurfaceArea'), pumpRate: getFloatValue('#pumpGph'), tankVolume: getFloatValue('#tankVolume') }
     };
 
     $.post('/api/settings/depth', JSON.stringify(settings));
@@ -376,10 +376,10 @@ $(function() {
     $('#waterLevelHigh').val(data.maintainRange.high);
     $('#minDepthValue').val(data.depthValues.low);
     $('#maxDepthValue').val(data.depthValues.high);
-    $('#maxDepthInches').val(data.depthValues.highInches);
-    $('#tankSurfaceArea').val(data.depthValues.tankSurfaceArea);
+    $('#maxDepthInches').val(data.depthValues.highLevel);
+    $('#tankSurfaceArea').val(data.depthValues.surfaceArea);
     $('#tankVolume').val(data.depthValues.tankVolume);
-    $('#pumpGph').val(data.depthValues.pumpGph);
+    $('#pumpGph').val(data.depthValues.pumpRate);
 
     updateStatus();
     updateMaxDose();
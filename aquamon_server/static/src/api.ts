@@ -3,23 +3,118 @@ import { compose, postData } from './utils';
 import * as moment from 'moment';
 import { from } from 'rxjs';
 
+export interface SensorStatus {
+    name: string;
+    healthy: boolean;
+    secondsSinceGood: number | null;
+    consecutiveFailures: number;
+    totalFailures: number;
+    outOfRange: number;
+    lastError: string | null;
+}
+
+export interface InputStatus {
+    name: string;
+    active: boolean;
+}
+
+export interface ProbeStatus {
+    name: string;
+    tempF: number;
+    temp: number;
+}
+
+export type TemperatureUnit = 'F' | 'C';
+
+export interface HeaterPidSettings {
+    kp: number;
+    ki: number;
+    kd: number;
+    windowSeconds: number;
+}
+
+export interface CoolerLimitSettings {
+    minRunSeconds: number;
+    minRestSeconds: number;
+    maxRunSeconds: number;
+}
+
+export interface StageOffsetSettings {
+    heaterF: number;
+    backupHeaterF: number;
+    fanF: number;
+    coolerF: number;
+}
+
+export interface TemperatureStageStatus {
+    name: string;
+    on: boolean;
+    offsetF: number;
+}
+
+export interface HeaterPidStatus {
+    setpointF: number;
+    errorF: number;
+    p: number;
+    i: number;
+    d: number;
+    output: number;
+}
+
+export interface AtoReservoirStatus {
+    lastRun: number | null;
+    lastDay: number;
+    capacity: number | null;
+    remaining: number | null;
+    hoursLeft: number | null;
+}
+
+export interface AtoEvaporationStatus {
+    today: number;
+    averagePerDay: number | null;
+    days: number;
+    airTempCorrelation: number | null;
+    humidityCorrelation: number | null;
+}
+
+export type AtoState = 'idle' | 'filling' | 'timedOut' | 'failedSafe' | 'quickDrop' | 'tooLow' | 'noDepth' | 'highFloat' | 'reservoirEmpty';
+
 interface RawStatus {
     airTempF: number;
+    airTemp: number;
+    temperatureUnit: TemperatureUnit;
+    currentTemp: number;
     ato_pump_on: boolean;
+    atoState: AtoState;
+    atoReservoir: AtoReservoirStatus;
+    atoEvaporation: AtoEvaporationStatus | null;
     cooler_on: boolean;
     currentTempF: number;
     depth: number;
     heater_on: boolean;
+    heaterPid: HeaterPidStatus | null;
+    heaterRateFPerHour: number | null;
+    coolerRateFPerHour: number | null;
+    stages: TemperatureStageStatus[];
     humidity: number;
     pH: number;
+    probes: ProbeStatus[];
+    sensors: SensorStatus[];
+    inputs: InputStatus[];
     pump_on: boolean;
 }
 
+export interface TemperaturePoint {
+    time: string;
+    temp: number;
+}
+
 interface MinMaxTimes {
     min: number;
     minTime: string;
     max: number;
     maxTime: string;
+    points?: TemperaturePoint[];
 }
 
 export interface MinMaxTempTimes {
@@ -27,27 +122,45 @@ export interface MinMaxTempTimes {
     minTime: string;
     max: Temperature<'F'>;
     maxTime: string;
+    points?: TemperaturePoint[];
 }
 
 interface RawTempSettings {
     heater: MinMaxTimes;
     cooler: MinMaxTimes;
+    heaterPid?: HeaterPidSettings | null;
+    coolerLimits?: CoolerLimitSettings;
+    stageOffsets?: StageOffsetSettings;
+    faultCheckMinutes?: number;
 }
 
 export interface TempSettings {
     heater: MinMaxTempTimes;
     cooler: MinMaxTempTimes;
+    heaterPid?: HeaterPidSettings | null;
+    coolerLimits?: CoolerLimitSettings;
+    stageOffsets?: StageOffsetSettings;
+    faultCheckMinutes?: number;
 }
 
 export interface Status {
     airTempF: Temperature<'F'>;
     ato_pump_on: boolean;
+    atoState: AtoState;
+    atoReservoir: AtoReservoirStatus;
+    atoEvaporation: AtoEvaporationStatus | null;
     cooler_on: boolean;
     currentTempF: Temperature<'F'>;
     depth: number;
     heater_on: boolean;
+    heaterPid: HeaterPidStatus | null;
+    heaterRateFPerHour: number | null;
+    coolerRateFPerHour: number | null;
+    stages: TemperatureStageStatus[];
     humidity: number;
     pH: number;
+    probes: ProbeStatus[];
+    sensors: SensorStatus[];
     pump_on: boolean;
 }
 
@@ -56,11 +169,17 @@ export interface DepthSettings {
     depthValues: {
         low: number, 
         high: number,
-        highInches: number;
-        tankSurfaceArea: number;
+        highLevel: number;
+        surfaceArea: number;
         tankVolume: number;
-        pumpGph: number;
-    }
+        pumpRate: number;
+        metric?: boolean;
+    },
+    reservoir?: {
+        capacity: number;
+        alertHours: number;
+    },
+    maxDailyVolume?: number | null;
 }
 
 export interface LightingSchedulePoint {
@@ -103,6 +222,10 @@ function wrapTempSettings(settings: RawTempSettings): TempSettings {
     return {
         cooler: wrap(settings.cooler),
         heater: wrap(settings.heater),
+        heaterPid: settings.heaterPid,
+        coolerLimits: settings.coolerLimits,
+        stageOffsets: settings.stageOffsets,
+        faultCheckMinutes: settings.faultCheckMinutes,
     }
 }
 
@@ -117,6 +240,10 @@ function unwrapTempSettings(settings: TempSettings): RawTempSettings {
     return {
         cooler: wrap(settings.cooler),
         heater: wrap(settings.heater),
+        heaterPid: settings.heaterPid,
+        coolerLimits: settings.coolerLimits,
+        stageOffsets: settings.stageOffsets,
+        faultCheckMinutes: settings.faultCheckMinutes,
     }
 }
 
@@ -177,6 +304,22 @@ const Api = {
         return postData('/api/settings/test', unwrapTempSettings(data));
     },
 
+    reservoirRefilled(): Promise<any> {
+        return postData('/api/ato/reservoir/refilled');
+    },
+
+    resetAto(): Promise<any> {
+        return postData('/api/ato/reset');
+    },
+
+    resetTemperatureFaults(): Promise<any> {
+        return postData('/api/temperature/reset');
+    },
+
+    getEvaporation(): Promise<string> {
+        return fetch('/api/ato/evaporation.csv').then(csv => csv.text());
+    },
+
     getHistory(hours: number = 12) : Promise<HistoryValue[]> {
         return fetch(`/api/status/history.csv?hours=${hours}`)
             .then(csv => csv.text())
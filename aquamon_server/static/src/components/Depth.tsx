@@ -35,7 +35,7 @@ var formatInchFraction = function(value: number) {
 function calculateRange(value: number, depthSettings: DepthSettings) {
     const depthValues = depthSettings.depthValues;
     // calculate the range in inches
-    var inchesPerStep = depthValues.highInches / (depthValues.high - depthValues.low);
+    var inchesPerStep = depthValues.highLevel / (depthValues.high - depthValues.low);
     var depthInches = (value - depthSettings.maintainRange.high) * inchesPerStep;
     return depthInches;
 }
@@ -48,6 +48,8 @@ void ProcessReceiveByte(SMBData* data);
 inline static void OutputTemp(SMBData* smb);
 inline static void OutputAirTempHumidity(SMBData* smb);
 inline static void OutputDepth(SMBData* smb);
+inline static void OutputProbeRom(SMBData* smb);
+inline static void OutputProbeTemp(SMBData* smb);
 inline static void SetChannels(SMBData* smb);
 inline static void UndefinedCommand(SMBData *smb);
 inline static void crc_buf(uint8_t* buf, uint8_t len);
@@ -62,6 +64,10 @@ ISR(BADISR_vect)
 }
 
 static temp_info last_temp;
+static uint8_t probe_roms[THERM_MAX_PROBES][THERM_ROM_LENGTH];
+static temp_info probe_temps[THERM_MAX_PROBES];
+static bool probe_ok[THERM_MAX_PROBES];
+static uint8_t probe_count;
 static uint16_t depth_reading;
 static temp_humidity_info air_temp_humidity;
 
@@ -77,6 +83,9 @@ int main (void)
     sbi(PORTB, PB5);
 
     printf("Starting up!...\n");
+
+    probe_count = therm_search(probe_roms, THERM_MAX_PROBES);
+    printf("Found %u temperature probes\n", probe_count);
     
     for (uint8_t j=0; j<6; j++) {
       set_channel(j+1, 0);
@@ -102,13 +111,39 @@ int main (void)
 
 void publish_temp(void) {
   temp_info temp;
-  if (!therm_read_temp(&temp)) {
+  if (!therm_convert_all()) {
     printf("Error reading temperature\n");
-  } else {
+    return;
+  }
+
+  // a lone probe that didn't show up in the search can still be read with SKIP_ROM
+  if (probe_count == 0) {
+    if (!therm_read_scratch(NULL, &temp)) {
+      printf("Error reading temperature\n");
+    } else {
+      cli();
+      last_temp = temp;
+      sei();
+      printf("%u.%u\n", temp.major, temp.minor);
+    }
+    return;
+  }
+
+  for (uint8_t i=0; i<probe_count; i++) {
+    bool ok = therm_read_scratch(probe_roms[i], &temp);
     cli();
-    last_temp = temp;
+    probe_ok[i] = ok;
+    if (ok) {
+      probe_temps[i] = temp;
+      // AQ_CMD_GET_TEMP reports the first probe
+      if (i == 0) last_temp = temp;
+    }
     sei();
-    printf("%u.%u\n", temp.major, temp.minor);
+    if (ok) {
+      printf("Probe %u: %u.%u\n", i, temp.major, temp.minor);
+    } else {
+      printf("Error reading temperature probe %u\n", i);
+    }
   }
 }
 
@@ -192,6 +227,12 @@ void ProcessMessage(SMBData* smb)
       case AQ_CMD_GET_DEPTH:
         OutputDepth(smb);
         break;
+      case AQ_CMD_GET_PROBE_ROM:
+        OutputProbeRom(smb);
+        break;
+      case AQ_CMD_GET_PROBE_TEMP:
+        OutputProbeTemp(smb);
+        break;
       default:
         UndefinedCommand(smb);
         break;
@@ -234,6 +275,54 @@ inline static void OutputDepth(SMBData* smb)
 }
  
  
+// rxBuffer[1] is the index of the probe. Replies with the number of probes found and
+// the ROM id at that index, or all zeros if there isn't one.
+inline static void OutputProbeRom(SMBData* smb)
+{
+  if (smb->rxCount < 2) {
+    UndefinedCommand(smb);
+    return;
+  }
+
+  uint8_t index = smb->rxBuffer[1];
+  smb->txBuffer[0] = probe_count;
+  for (uint8_t i=0; i<THERM_ROM_LENGTH; i++) {
+    smb->txBuffer[i+1] = index < probe_count ? probe_roms[index][i] : 0;
+  }
+  crc_buf(smb->txBuffer, THERM_ROM_LENGTH + 1);
+  smb->txLength = THERM_ROM_LENGTH + 2;
+
+  smb->state = SMB_STATE_READ_REQUESTED;
+}
+
+// rxBuffer[1..8] is the ROM id of the probe. Replies with 1 and the raw reading, or 0
+// if the probe isn't on the bus or its last read failed.
+inline static void OutputProbeTemp(SMBData* smb)
+{
+  if (smb->rxCount < THERM_ROM_LENGTH + 1) {
+    UndefinedCommand(smb);
+    return;
+  }
+
+  uint8_t ok = 0;
+  uint16_t raw = 0;
+  for (uint8_t i=0; i<probe_count; i++) {
+    if (memcmp(probe_roms[i], smb->rxBuffer + 1, THERM_ROM_LENGTH) == 0) {
+      ok = probe_ok[i];
+      raw = probe_temps[i].raw;
+      break;
+    }
+  }
+
+  smb->txBuffer[0] = ok;
+  smb->txBuffer[1] = (uint8_t)(raw >> 8);
+  smb->txBuffer[2] = (uint8_t)(raw & 0xff);
+  crc_buf(smb->txBuffer, 3);
+  smb->txLength = 4;
+
+  smb->state = SMB_STATE_READ_REQUESTED;
+}
+ 
 inline static void SetChannels(SMBData* smb) 
 {
 
@@ -7,6 +7,8 @@
 #define AQ_CMD_GET_TEMP               0x12
 #define AQ_CMD_GET_DEPTH              0x13
 #define AQ_CMD_GET_AIR_TEMP_HUMIDITY  0x14
+#define AQ_CMD_GET_PROBE_ROM          0x15
+#define AQ_CMD_GET_PROBE_TEMP         0x16
 
 void ProcessMessage(SMBData* smb);
 
@@ -3,6 +3,7 @@
 #include <avr/interrupt.h>
 #include <util/crc16.h>
 #include <stdbool.h>
+#include <stddef.h>
 #include "ds18b20.h"
 #include "dbg.h"
 
@@ -98,6 +99,95 @@ void therm_command(uint8_t command)
 }
 
 bool therm_read_temp(temp_info* tinfo)
+{
+    return therm_convert_all() && therm_read_scratch(NULL, tinfo);
+}
+
+// addresses every probe if rom is NULL
+void therm_select(const uint8_t* rom)
+{
+    if (rom == NULL) {
+        therm_command(SKIP_ROM);
+        return;
+    }
+
+    therm_command(MATCH_ROM);
+    for (uint8_t i=0; i<THERM_ROM_LENGTH; i++) {
+        therm_write(rom[i]);
+    }
+}
+
+// The search algorithm from Maxim's application note 187. Each pass walks the ROM ids
+// bit by bit, taking the 0 branch at the last place two probes disagreed that hasn't
+// been explored yet.
+uint8_t therm_search(uint8_t roms[][THERM_ROM_LENGTH], uint8_t max)
+{
+    uint8_t rom[THERM_ROM_LENGTH] = {0};
+    int8_t last_discrepancy = -1;
+    uint8_t count = 0;
+
+    while (count < max) {
+        if (therm_reset()) {
+            break;
+        }
+        therm_command(SEARCH_ROM);
+
+        int8_t discrepancy = -1;
+        for (int8_t bit=0; bit<THERM_ROM_LENGTH * 8; bit++) {
+            uint8_t id_bit = therm_read_bit();
+            uint8_t cmp_id_bit = therm_read_bit();
+            uint8_t mask = 1 << (bit % 8);
+            uint8_t direction;
+
+            if (id_bit && cmp_id_bit) {
+                // nobody answered
+                return count;
+            } else if (id_bit != cmp_id_bit) {
+                direction = id_bit;
+            } else if (bit == last_discrepancy) {
+                direction = 1;
+            } else if (bit > last_discrepancy) {
+                direction = 0;
+            } else {
+                direction = (rom[bit / 8] & mask) ? 1 : 0;
+            }
+
+            if (id_bit == cmp_id_bit && direction == 0) {
+                discrepancy = bit;
+            }
+
+            if (direction) {
+                rom[bit / 8] |= mask;
+            } else {
+                rom[bit / 8] &= ~mask;
+            }
+            therm_write_bit(direction);
+        }
+
+        uint8_t crc = 0;
+        for (uint8_t i=0; i<THERM_ROM_LENGTH - 1; i++) {
+            crc = _crc_ibutton_update(crc, rom[i]);
+        }
+        if (crc != rom[THERM_ROM_LENGTH - 1]) {
+            debug("Bad ROM crc during search");
+            break;
+        }
+
+        for (uint8_t i=0; i<THERM_ROM_LENGTH; i++) {
+            roms[count][i] = rom[i];
+        }
+        count++;
+
+        last_discrepancy = discrepancy;
+        if (last_discrepancy < 0) {
+            break;
+        }
+    }
+
+    return count;
+}
+
+bool therm_convert_all(void)
 {
   // NOTE: there may be timing issues here, as leaving debug on seems to fix it
     debug("Reading temperature...");
@@ -112,12 +202,16 @@ bool therm_read_temp(temp_info* tinfo)
     while (!therm_read_bit());
 
     debug("Temp conversion finished, reading...");   
+    return true;
+}
 
+bool therm_read_scratch(const uint8_t* rom, temp_info* tinfo)
+{
     if (therm_reset()) {
         return false;
     }
 
-    therm_command(SKIP_ROM);
+    therm_select(rom);
     therm_command(READ_SCRATCH);
 
     uint16_t temp = 0;
@@ -13,7 +13,19 @@ typedef struct {
   uint16_t raw;
 } temp_info;
 
-/* Returns the temperature in deg Celcius */
+#define THERM_ROM_LENGTH 8
+#define THERM_MAX_PROBES 4
+
+/* Returns the temperature in deg Celcius. Only works with a single probe on the bus */
 bool therm_read_temp(temp_info* tinfo);
 
+/* Finds the ROM ids of up to max probes on the bus and returns how many there are */
+uint8_t therm_search(uint8_t roms[][THERM_ROM_LENGTH], uint8_t max);
+
+/* Starts a conversion on every probe and waits for them to finish */
+bool therm_convert_all(void);
+
+/* Reads the last conversion from the probe with the given ROM id, or the only probe if rom is NULL */
+bool therm_read_scratch(const uint8_t* rom, temp_info* tinfo);
+
 #endif